@@ -26,9 +26,9 @@ use queryscript::{
         autocomplete::{loc_to_pos, pos_to_loc, AutoCompleter},
         error::CompileError,
         schema::{CRef, ExprEntry, MFnType, MType, SType},
-        Compiler, Schema, SchemaRef,
+        Compiler, Schema, SchemaRef, Warning,
     },
-    parser::{error::PrettyError, parse_schema},
+    parser::{error::PrettyError, format::format_schema, parse_schema},
     runtime,
     types::Type as QSType,
 };
@@ -59,6 +59,7 @@ pub struct Symbol {
     pub type_: CRef<SType>,
     pub def: SourceLocation,
     pub public: bool,
+    pub doc: Option<String>,
     pub references: BTreeSet<SourceLocation>,
 }
 
@@ -130,22 +131,24 @@ impl Backend {
 
     pub fn new(client: Client, compiler: Arc<Mutex<Compiler>>) -> Result<Backend> {
         let documents = Arc::new(TokioRwLock::new(BTreeMap::new()));
+        let configuration = Arc::new(RwLock::new(Configuration {
+            has_configuration_capability: false,
+            has_workspace_folder_capability: false,
+            has_diagnostic_related_information_capability: false,
+        }));
         compiler
             .lock()
             .expect("compiler lock")
             .on_schema(Some(Box::new(SchemaRecorder {
                 tokio_handle: tokio::runtime::Handle::current(),
                 client: client.clone(),
+                configuration: configuration.clone(),
                 documents: documents.clone(),
             })))
             .map_err(log_internal_error)?;
         let backend = Backend {
             client,
-            configuration: Arc::new(RwLock::new(Configuration {
-                has_configuration_capability: false,
-                has_workspace_folder_capability: false,
-                has_diagnostic_related_information_capability: false,
-            })),
+            configuration,
             settings: Arc::new(RwLock::new(BTreeMap::new())),
             documents,
             compiler,
@@ -216,6 +219,7 @@ impl LanguageServer for Backend {
                 code_lens_provider: Some(CodeLensOptions {
                     resolve_provider: Some(true),
                 }),
+                document_formatting_provider: Some(OneOf::Left(true)),
                 ..Default::default()
             },
             ..Default::default()
@@ -500,6 +504,25 @@ impl LanguageServer for Backend {
         }
     }
 
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        let text = self.get_text(&uri).await?;
+
+        // Files that don't parse are left alone (their errors are already reported as
+        // diagnostics).
+        let formatted = match format_schema(uri.as_str(), &text) {
+            Ok(formatted) => formatted,
+            Err(_) => return Ok(None),
+        };
+        if formatted == text {
+            return Ok(Some(Vec::new()));
+        }
+        Ok(Some(vec![TextEdit {
+            range: FULL_DOCUMENT_RANGE,
+            new_text: formatted,
+        }]))
+    }
+
     async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
         let uri = params.text_document.uri;
         let schema = match self.get_schema(&uri, true).await? {
@@ -577,6 +600,12 @@ fn get_fn_type(type_: CRef<SType>) -> Result<Option<(MFnType, BTreeSet<ast::Iden
 
 fn format_symbol(symbol: &Symbol) -> Result<String> {
     let mut parts = Vec::<String>::new();
+    if let Some(doc) = &symbol.doc {
+        for line in doc.lines() {
+            parts.push(format!("--! {}", line).trim_end().into());
+            parts.push("\n".into());
+        }
+    }
     if symbol.public {
         parts.push("export ".into());
     }
@@ -710,6 +739,7 @@ impl compile::OnSymbol for SymbolRecorder {
         type_: CRef<SType>,
         def: SourceLocation,
         public: bool,
+        doc: Option<String>,
     ) -> compile::Result<()> {
         let file = name.location().file();
         if let Some(file) = file {
@@ -724,6 +754,7 @@ impl compile::OnSymbol for SymbolRecorder {
                 type_,
                 def,
                 public,
+                doc,
                 references: BTreeSet::new(),
             };
             match self.symbols.entry(uri) {
@@ -1055,6 +1086,7 @@ impl Backend {
 struct SchemaRecorder {
     tokio_handle: tokio::runtime::Handle,
     client: Client,
+    configuration: Arc<RwLock<Configuration>>,
     documents: Arc<TokioRwLock<BTreeMap<Url, Arc<TokioMutex<Document>>>>>,
 }
 
@@ -1072,12 +1104,17 @@ impl compile::OnSchema for SchemaRecorder {
         ast: &ast::Schema,
         schema: compile::schema::Ref<Schema>,
         errors: &Vec<(Option<usize>, CompileError)>,
+        warnings: &Vec<Warning>,
     ) -> compile::error::Result<()> {
         let documents = self.documents.clone();
         let uri = Url::from_file_path(path).map_err(|_| {
             CompileError::external(format!("bad path: {}", path.display()).as_str())
         })?;
         let client = self.client.clone();
+        let related_information = self
+            .configuration
+            .read()?
+            .has_diagnostic_related_information_capability;
 
         let mut diagnostics = Vec::new();
 
@@ -1111,16 +1148,47 @@ impl compile::OnSchema for SchemaRecorder {
             };
 
             if loc.uri == uri {
+                // Clients that don't support related information only get the primary location.
+                let related = err
+                    .related()
+                    .iter()
+                    .filter_map(|related| {
+                        Some(DiagnosticRelatedInformation {
+                            location: related.location.normalize()?,
+                            message: related.message.clone(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
                 diagnostics.push(Diagnostic {
                     severity: Some(DiagnosticSeverity::ERROR),
                     range: loc.range,
                     message: err.pretty(),
+                    related_information: if related_information && !related.is_empty() {
+                        Some(related)
+                    } else {
+                        None
+                    },
                     source: Some("QueryScript".to_string()),
                     ..Default::default()
                 });
             }
         }
 
+        for warning in warnings {
+            if let Some(loc) = warning.location().normalize() {
+                if loc.uri == uri {
+                    diagnostics.push(Diagnostic {
+                        severity: Some(DiagnosticSeverity::WARNING),
+                        range: loc.range,
+                        code: Some(NumberOrString::String(warning.lint.to_string())),
+                        message: warning.message.clone(),
+                        source: Some("QueryScript".to_string()),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
         let handle_document = async move {
             let mut documents = documents.write().await;
             let mut document = documents.get_mut(&uri).unwrap().lock().await;
@@ -1,15 +1,21 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use colored::Colorize;
 use snafu::{prelude::*, whatever};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use queryscript::compile;
+use queryscript::docs;
 use queryscript::error::*;
+use queryscript::graph;
+use queryscript::lineage;
 use queryscript::materialize;
 use queryscript::parser;
 use queryscript::parser::error::PrettyError;
 use queryscript::runtime;
+use queryscript::testing;
 
 mod repl;
 mod rustyline;
@@ -17,7 +23,11 @@ mod rustyline;
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     file: Option<String>,
 
     #[arg(short, long, default_value_t = false)]
@@ -46,20 +56,133 @@ struct Cli {
     /// Save the exported views back to the original database
     #[arg(long)]
     save: bool,
+
+    /// With --save, drop objects that were previously saved from this file but are no longer
+    /// exported
+    #[arg(long)]
+    prune: bool,
+
+    /// With --save, save views in a single transaction, one at a time, instead of concurrently. If a
+    /// view fails, everything saved before it is rolled back, and nothing after it is saved
+    #[arg(long)]
+    atomic: bool,
+
+    /// With --save, the number of times to retry an object that fails with a transient error
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// With --save, write a JSON manifest describing the outcome of each object to this path
+    #[arg(long)]
+    manifest: Option<String>,
+
+    /// Run the data quality checks declared on the file's types (e.g. `id bigint unique not
+    /// null`) and report the rows that fail them. With --save, the checks run before anything is
+    /// saved.
+    #[arg(long)]
+    check: bool,
+
+    /// With --save, print the objects that would be created (and their DDL) without executing
+    /// anything. Objects that have not changed since they were last saved are listed as skipped
+    #[arg(long)]
+    plan: bool,
+
+    /// The format of the --plan output (text, json, or dot)
+    #[arg(long, default_value_t = String::from("text"))]
+    plan_format: String,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the .qs files in a directory and compare the results of their expressions to the
+    /// .expected file next to each one
+    Test {
+        /// The directory to search for .qs files (defaults to the current directory)
+        dir: Option<String>,
+
+        /// Rewrite the .expected files with the current results, and create the missing ones
+        #[arg(long)]
+        bless: bool,
+
+        #[arg(long, default_value_t = String::from("duckdb"))]
+        engine: String,
+    },
+    /// Print the files and database tables that each column of the file's exported relations is
+    /// computed from
+    Lineage {
+        file: String,
+
+        /// The output format (json or dot)
+        #[arg(long, default_value_t = String::from("json"))]
+        format: String,
+    },
+    /// Print the graph of declarations (lets, functions, types, imports, connections, and
+    /// materialized views) across the file and the files it imports
+    Graph {
+        file: String,
+
+        /// The output format (dot, mermaid, or json)
+        #[arg(long, default_value_t = String::from("dot"))]
+        format: String,
+
+        /// Only include exported declarations
+        #[arg(long)]
+        exports: bool,
+
+        /// Only include the given declaration and the declarations it depends on
+        #[arg(long)]
+        upstream: Option<String>,
+
+        /// Only include the given declaration and the declarations that depend on it
+        #[arg(long)]
+        downstream: Option<String>,
+    },
+    /// Generate Markdown documentation for the file's exported types, functions, and relations,
+    /// and those of the files it imports
+    Docs {
+        file: String,
+
+        /// The directory to write the pages to
+        #[arg(long, default_value_t = String::from("docs"))]
+        out: String,
+    },
+    /// Format .qs files in place
+    Fmt {
+        /// The files, or directories of .qs files, to format (defaults to the current directory)
+        paths: Vec<String>,
+
+        /// Don't rewrite any files, but fail if some of them are not formatted
+        #[arg(long)]
+        check: bool,
+    },
+    /// Report lint warnings (e.g. unused lets or implicit cartesian joins) in .qs files. The lints
+    /// are configured by the closest .qslint file to each one.
+    Lint {
+        /// The files, or directories of .qs files, to lint (defaults to the current directory)
+        paths: Vec<String>,
+    },
 }
 
 enum Mode {
     Execute,
     Compile,
     Parse,
-    Save,
+    Save(materialize::SaveOptions, Option<String>),
+    Plan(PlanFormat),
+    Check,
+}
+
+enum PlanFormat {
+    Text,
+    Json,
+    Dot,
 }
 
-fn main() {
+fn main() -> ExitCode {
     match main_result() {
-        Ok(()) => {}
+        Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
             eprintln!("{}", e);
+            ExitCode::FAILURE
         }
     }
 }
@@ -70,6 +193,37 @@ fn main_result() -> Result<(), QSError> {
         std::env::set_var("RUST_BACKTRACE", "1");
     }
 
+    if let Some(Command::Test { dir, bless, engine }) = &cli.command {
+        return run_tests(dir.as_deref().unwrap_or("."), *bless, engine);
+    }
+
+    if let Some(Command::Lineage { file, format }) = &cli.command {
+        return run_lineage(file, format);
+    }
+
+    if let Some(Command::Graph {
+        file,
+        format,
+        exports,
+        upstream,
+        downstream,
+    }) = &cli.command
+    {
+        return run_graph(file, format, *exports, upstream, downstream);
+    }
+
+    if let Some(Command::Docs { file, out }) = &cli.command {
+        return run_docs(file, out);
+    }
+
+    if let Some(Command::Fmt { paths, check }) = &cli.command {
+        return run_fmt(paths, *check);
+    }
+
+    if let Some(Command::Lint { paths }) = &cli.command {
+        return run_lint(paths);
+    }
+
     if cli.compile && cli.parse {
         whatever!("Cannot run with --compile and --parse");
     }
@@ -80,17 +234,47 @@ fn main_result() -> Result<(), QSError> {
         }
     }
 
+    if cli.check && (cli.compile || cli.parse || cli.execute.is_some()) {
+        whatever!("Cannot run with --check and --compile, --parse, or --execute");
+    }
+
+    if cli.plan && !cli.save {
+        whatever!("Cannot run with --plan without --save");
+    }
+
+    if (cli.prune || cli.atomic || cli.manifest.is_some()) && !cli.save {
+        whatever!("Cannot run with --prune, --atomic, or --manifest without --save");
+    }
+
     let mode = if cli.compile {
         Mode::Compile
     } else if cli.parse {
         Mode::Parse
+    } else if cli.plan {
+        Mode::Plan(match cli.plan_format.to_lowercase().as_str() {
+            "text" => PlanFormat::Text,
+            "json" => PlanFormat::Json,
+            "dot" => PlanFormat::Dot,
+            format => whatever!("Unknown plan format: {}", format),
+        })
     } else if cli.save {
-        Mode::Save
+        Mode::Save(
+            materialize::SaveOptions {
+                prune: cli.prune,
+                atomic: cli.atomic,
+                retries: cli.retries,
+                check: cli.check,
+                ..Default::default()
+            },
+            cli.manifest.clone(),
+        )
+    } else if cli.check {
+        Mode::Check
     } else {
         Mode::Execute
     };
 
-    let engine_type = queryscript::runtime::SQLEngineType::from_name(&cli.engine).unwrap();
+    let engine_type = parse_engine(&cli.engine)?;
 
     match cli.file {
         Some(file) => {
@@ -150,6 +334,268 @@ fn main_result() -> Result<(), QSError> {
     }
 }
 
+fn parse_engine(engine: &str) -> Result<queryscript::runtime::SQLEngineType, QSError> {
+    match queryscript::runtime::SQLEngineType::from_name(engine) {
+        Ok(engine_type) => Ok(engine_type),
+        Err(e) => whatever!("Invalid --engine {:?}: {}", engine, e),
+    }
+}
+
+fn run_tests(dir: &str, bless: bool, engine: &str) -> Result<(), QSError> {
+    let path = Path::new(dir);
+    if !path.is_dir() {
+        whatever!("Directory {:?} does not exist", path);
+    }
+
+    let engine_type = parse_engine(engine)?;
+    let rt = runtime::build().context(RuntimeSnafu {
+        file: dir.to_string(),
+    })?;
+    let summary = testing::run_tests(&rt, engine_type, path, bless)?;
+
+    for result in summary.results.iter() {
+        let status = format!("{:<7}", result.status.as_str());
+        let status = match result.status {
+            testing::TestStatus::Passed => status.green(),
+            testing::TestStatus::Failed => status.red(),
+            _ => status.yellow(),
+        };
+        match &result.name {
+            Some(name) => println!("{} {} ({})", status, result.path.display(), name),
+            None => println!("{} {}", status, result.path.display()),
+        }
+        if let Some(message) = &result.message {
+            println!("{}\n", message);
+        }
+    }
+    println!("{}", summary);
+
+    if !summary.success() {
+        whatever!(
+            "{} of {} tests failed",
+            summary.count(testing::TestStatus::Failed),
+            summary.results.len()
+        );
+    }
+    Ok(())
+}
+
+fn run_lineage(file: &str, format: &str) -> Result<(), QSError> {
+    let path = Path::new(file);
+    if !path.exists() {
+        whatever!("Path {:?} does not exist", path);
+    }
+
+    let compiler = compile::Compiler::new()?;
+    let schema = match compiler.compile_schema_from_file(path).as_result() {
+        Ok(schema) => schema.unwrap(),
+        Err(err) => {
+            let contents = compiler.file_contents()?;
+            let err_strs = QSError::from(err)
+                .format_without_backtrace()
+                .iter()
+                .map(|e| e.pretty_with_code(&contents.files))
+                .collect::<Vec<_>>();
+            whatever!("{}", err_strs.join("\n"))
+        }
+    };
+    let lineage = lineage::schema_lineage(&*schema.read()?);
+
+    match format.to_lowercase().as_str() {
+        "json" => match serde_json::to_string_pretty(&lineage) {
+            Ok(json) => println!("{}", json),
+            Err(e) => whatever!("{}", e),
+        },
+        "dot" => print!("{}", lineage.to_dot()),
+        format => whatever!("Unknown lineage format: {}", format),
+    }
+    Ok(())
+}
+
+fn run_graph(
+    file: &str,
+    format: &str,
+    exports: bool,
+    upstream: &Option<String>,
+    downstream: &Option<String>,
+) -> Result<(), QSError> {
+    let path = Path::new(file);
+    if !path.exists() {
+        whatever!("Path {:?} does not exist", path);
+    }
+
+    let compiler = compile::Compiler::new()?;
+    let mut graph = match graph::declaration_graph(&compiler, path) {
+        Ok(graph) => graph,
+        Err(err) => {
+            let contents = compiler.file_contents()?;
+            let err_strs = QSError::from(err)
+                .format_without_backtrace()
+                .iter()
+                .map(|e| e.pretty_with_code(&contents.files))
+                .collect::<Vec<_>>();
+            whatever!("{}", err_strs.join("\n"))
+        }
+    };
+
+    if let Some(name) = upstream {
+        graph = match graph.find(name) {
+            Some(node) => graph.upstream(&node.id),
+            None => whatever!("No such declaration: {}", name),
+        };
+    }
+    if let Some(name) = downstream {
+        graph = match graph.find(name) {
+            Some(node) => graph.downstream(&node.id),
+            None => whatever!("No such declaration: {}", name),
+        };
+    }
+    if exports {
+        graph = graph.exports();
+    }
+
+    match format.to_lowercase().as_str() {
+        "dot" => print!("{}", graph.to_dot()),
+        "mermaid" => print!("{}", graph.to_mermaid()),
+        "json" => match serde_json::to_string_pretty(&graph) {
+            Ok(json) => println!("{}", json),
+            Err(e) => whatever!("{}", e),
+        },
+        format => whatever!("Unknown graph format: {}", format),
+    }
+    Ok(())
+}
+
+fn run_docs(file: &str, out: &str) -> Result<(), QSError> {
+    let path = Path::new(file);
+    if !path.exists() {
+        whatever!("Path {:?} does not exist", path);
+    }
+
+    let compiler = compile::Compiler::new()?;
+    let pages = match docs::schema_docs(&compiler, path) {
+        Ok(pages) => pages,
+        Err(err) => {
+            let contents = compiler.file_contents()?;
+            let err_strs = QSError::from(err)
+                .format_without_backtrace()
+                .iter()
+                .map(|e| e.pretty_with_code(&contents.files))
+                .collect::<Vec<_>>();
+            whatever!("{}", err_strs.join("\n"))
+        }
+    };
+
+    let out = Path::new(out);
+    if let Err(e) = fs::create_dir_all(out) {
+        whatever!("Failed to create {:?}: {}", out, e);
+    }
+    for page in pages.iter() {
+        let page_path = out.join(page.file_name());
+        if let Err(e) = fs::write(&page_path, &page.markdown) {
+            whatever!("Failed to write {:?}: {}", page_path, e);
+        }
+        println!("{}", page_path.display());
+    }
+    Ok(())
+}
+
+// Finds the .qs files in the given files and directories (or the current directory).
+fn source_files(paths: &[String]) -> Result<Vec<PathBuf>, QSError> {
+    let mut files = Vec::new();
+    for path in paths
+        .iter()
+        .map(String::as_str)
+        .chain(paths.is_empty().then(|| "."))
+    {
+        let path = Path::new(path);
+        if path.is_dir() {
+            files.extend(testing::find_test_files(path)?);
+        } else if path.exists() {
+            files.push(path.to_path_buf());
+        } else {
+            whatever!("Path {:?} does not exist", path);
+        }
+    }
+    Ok(files)
+}
+
+fn run_fmt(paths: &[String], check: bool) -> Result<(), QSError> {
+    let files = source_files(paths)?;
+
+    let mut unformatted = 0;
+    for path in files.iter() {
+        let file = path.display().to_string();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => whatever!("Failed to read {:?}: {}", path, e),
+        };
+        let formatted = match parser::format::format_schema(&file, &contents) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                let code = BTreeMap::from([(file.clone(), contents.clone())]);
+                let err_strs = QSError::from(err)
+                    .format_without_backtrace()
+                    .iter()
+                    .map(|e| e.pretty_with_code(&code))
+                    .collect::<Vec<_>>();
+                whatever!("{}", err_strs.join("\n"))
+            }
+        };
+        if formatted == contents {
+            continue;
+        }
+
+        unformatted += 1;
+        if !check {
+            if let Err(e) = fs::write(path, &formatted) {
+                whatever!("Failed to write {:?}: {}", path, e);
+            }
+        }
+        println!("{}", file);
+    }
+
+    if check && unformatted > 0 {
+        whatever!(
+            "{} of {} files are not formatted (run qs fmt to format them)",
+            unformatted,
+            files.len()
+        );
+    }
+    Ok(())
+}
+
+fn run_lint(paths: &[String]) -> Result<(), QSError> {
+    let files = source_files(paths)?;
+
+    let (mut warnings, mut errors) = (0, 0);
+    for path in files.iter() {
+        let compiler = compile::Compiler::new()?;
+        let result = compiler.compile_schema_from_file(path);
+        let contents = compiler.file_contents()?;
+        for warning in result.warnings.iter() {
+            println!("{}\n", warning.pretty_with_code(&contents.files));
+        }
+        for (_, err) in result.errors {
+            for err in QSError::from(err).format_without_backtrace() {
+                println!("{}\n", err.pretty_with_code(&contents.files));
+                errors += 1;
+            }
+        }
+        warnings += result.warnings.len();
+    }
+
+    if warnings > 0 || errors > 0 {
+        whatever!(
+            "Found {} warnings and {} errors in {} files",
+            warnings,
+            errors,
+            files.len()
+        );
+    }
+    Ok(())
+}
+
 fn run_file(
     compiler: compile::Compiler,
     rt: &runtime::Runtime,
@@ -171,7 +617,7 @@ fn run_file(
             Err(e) => whatever!("{}", e),
         };
         let (tokens, eof) = parser::tokenize(file, &contents)?;
-        let mut parser = parser::Parser::new(file, tokens, eof);
+        let mut parser = parser::Parser::new(file, tokens, eof).with_doc_comments(&contents);
         let schema = parser.parse_schema().as_result()?;
         println!("{:#?}", schema);
         return Ok(());
@@ -215,6 +661,7 @@ fn run_file(
         // highlighted context incorrect.
         //
         schema.write()?.exprs = vec![];
+        schema.write()?.asserts = vec![];
         compiler
             .compile_string(schema.clone(), execute.as_str())
             .as_result()?;
@@ -229,8 +676,53 @@ fn run_file(
             println!("{:#?}", schema.read()?.exprs.first().unwrap());
         }
         return Ok(());
-    } else if matches!(mode, Mode::Save) {
-        rt.block_on(async { materialize::save_views(&ctx_pool, schema).await })?;
+    } else if let Mode::Save(options, manifest) = &mode {
+        let report = rt.block_on(async {
+            materialize::save_views_with_report(&ctx_pool, schema, options).await
+        })?;
+        if let Some(manifest) = manifest {
+            let json = match serde_json::to_string_pretty(&report) {
+                Ok(json) => json,
+                Err(e) => whatever!("{}", e),
+            };
+            if let Err(e) = fs::write(manifest, json + "\n") {
+                whatever!("Failed to write manifest {}: {}", manifest, e);
+            }
+        }
+        report.into_result()?;
+        return Ok(());
+    } else if let Mode::Plan(format) = &mode {
+        let plan = rt.block_on(async { materialize::plan_save_views(&ctx_pool, schema).await })?;
+        match format {
+            PlanFormat::Text => print!("{}", plan),
+            PlanFormat::Json => match serde_json::to_string_pretty(&plan) {
+                Ok(json) => println!("{}", json),
+                Err(e) => whatever!("{}", e),
+            },
+            PlanFormat::Dot => print!("{}", plan.to_dot()),
+        }
+        return Ok(());
+    } else if matches!(mode, Mode::Check) {
+        let checks = schema.read()?.checks.clone();
+        let mut ctx = ctx_pool.get();
+        let failures = rt
+            .block_on(async { runtime::checks::run_checks(&mut ctx, &checks).await })
+            .context(RuntimeSnafu {
+                file: file.to_string(),
+            })?;
+
+        let contents = compiler.file_contents()?;
+        for failure in failures.iter() {
+            eprintln!(
+                "{}\n\n{}\n",
+                failure.pretty_with_code(&contents.files),
+                queryscript::types::Value::Relation(failure.rows.clone())
+            );
+        }
+        if !failures.is_empty() {
+            whatever!("{} of {} checks failed", failures.len(), checks.len());
+        }
+        println!("{} checks passed", checks.len());
         return Ok(());
     }
 
@@ -248,5 +740,10 @@ fn run_file(
         println!("{}", value);
     }
 
+    rt.block_on(async { runtime::checks::run_assertions(&mut ctx, &locked_schema.asserts).await })
+        .context(RuntimeSnafu {
+            file: file.to_string(),
+        })?;
+
     Ok(())
 }
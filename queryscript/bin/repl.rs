@@ -142,8 +142,13 @@ fn run_command(
     engine_type: queryscript::runtime::SQLEngineType,
 ) -> Result<RunCommandResult, QSError> {
     let file = "<repl>";
+    if let Some(name) = cmd.trim().strip_prefix("describe ") {
+        describe(compiler, repl_schema, name.trim().trim_end_matches(';'))?;
+        return Ok(RunCommandResult::Done);
+    }
+
     let (tokens, eof) = parser::tokenize(file, &cmd)?;
-    let mut parser = parser::Parser::new(file, tokens, eof);
+    let mut parser = parser::Parser::new(file, tokens, eof).with_doc_comments(cmd);
 
     if parser.consume_token(&parser::Token::Placeholder("?".to_string())) {
         parser.parse_schema();
@@ -154,13 +159,16 @@ fn run_command(
 
     match parser.parse_schema().as_result() {
         Ok(ast) => {
-            let num_exprs = repl_schema.read()?.exprs.len();
+            let (num_exprs, num_asserts) = {
+                let locked_schema = repl_schema.read()?;
+                (locked_schema.exprs.len(), locked_schema.asserts.len())
+            };
 
             compiler
                 .compile_schema_ast(repl_schema.clone(), &ast)
                 .as_result()?;
 
-            let (compiled, folder) = {
+            let (compiled, asserts, folder) = {
                 let locked_schema = repl_schema.read()?;
                 (
                     if locked_schema.exprs.len() > num_exprs {
@@ -168,10 +176,21 @@ fn run_command(
                     } else {
                         None
                     },
+                    locked_schema.asserts[num_asserts..].to_vec(),
                     locked_schema.folder.clone(),
                 )
             };
 
+            if !asserts.is_empty() {
+                let mut ctx = queryscript::runtime::Context::new(folder.clone(), engine_type);
+                rt.block_on(
+                    async move { runtime::checks::run_assertions(&mut ctx, &asserts).await },
+                )
+                .context(RuntimeSnafu {
+                    file: file.to_string(),
+                })?;
+            }
+
             if let Some(compiled) = compiled {
                 let mut ctx = queryscript::runtime::Context::new(folder, engine_type);
                 let expr = compiled.to_runtime_type().context(RuntimeSnafu {
@@ -191,6 +210,40 @@ fn run_command(
     }
 }
 
+// Prints a declaration's doc comment (as `--!` lines) followed by its type.
+fn describe(
+    compiler: compile::Compiler,
+    repl_schema: schema::SchemaRef,
+    name: &str,
+) -> Result<(), QSError> {
+    let path = parser::parse_path("<repl>", name)?;
+    let importer = schema::Importer::Schema(repl_schema);
+    let (doc, type_) = match compile::lookup_path::<schema::ExprEntry>(
+        compiler.clone(),
+        importer.clone(),
+        &path,
+        true,
+        true,
+    )? {
+        (_, Some(decl), r) if r.is_empty() => (decl.doc, format!("{:#?}", decl.value.type_)),
+        _ => {
+            match compile::lookup_path::<schema::TypeEntry>(compiler, importer, &path, true, true)?
+            {
+                (_, Some(decl), r) if r.is_empty() => (decl.doc, format!("{:#?}", decl.value)),
+                _ => whatever!("No such declaration: {}", name),
+            }
+        }
+    };
+
+    if let Some(doc) = doc {
+        for line in doc.lines() {
+            println!("{}", format!("--! {}", line).trim_end());
+        }
+    }
+    println!("{}", type_);
+    Ok(())
+}
+
 fn get_qs_dir() -> Option<std::path::PathBuf> {
     home::home_dir().map(|p| p.join(".qs"))
 }
@@ -187,6 +187,20 @@ impl ToPath for sqlast::ObjectName {
 pub struct NameAndType {
     pub name: Located<Ident>,
     pub def: Type,
+    // Data quality constraints on a record field, e.g. `id bigint unique not null`.
+    pub constraints: Vec<Located<Constraint>>,
+    // The doc comment (`--! ...` or `/** ... */`) written directly before a record field.
+    pub doc: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Constraint {
+    NotNull,
+    Unique,
+    // `in (v1, v2, ...)`
+    AcceptedValues(Vec<sqlast::Expr>),
+    // `check (<expr>)`, which must hold for every row.
+    Check(sqlast::Expr),
 }
 
 #[derive(Clone, Debug)]
@@ -211,6 +225,10 @@ pub enum TypeBody {
         excluded: Vec<Located<Ident>>,
     },
     Generic(Path, Vec<Type>),
+    Fn {
+        args: Vec<Type>,
+        ret: Box<Type>,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -266,15 +284,46 @@ pub enum FnBody {
     SQL,    // A function we expect to exist in the SQL runtime
 }
 
-#[derive(Clone, Debug)]
+// How a materialized expression is persisted in its database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaterializeKind {
+    Table,
+    View,
+}
+
+impl Default for MaterializeKind {
+    fn default() -> MaterializeKind {
+        MaterializeKind::Table
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct MaterializeArgs {
     pub db: Option<Expr>,
+    // A file (or, if it ends with a slash or is partitioned, a directory) to save to instead of
+    // a database.
+    pub file: Option<Located<String>>,
+    pub format: Option<Located<String>>,
+    pub kind: Option<Located<MaterializeKind>>,
+    // If set, later saves only insert rows whose value in this column is greater than the largest
+    // value already in the table, rather than rebuilding it.
+    pub incremental: Option<Located<Ident>>,
+    // If set, later saves merge rows into the table on this key, rather than rebuilding it.
+    pub unique_key: Vec<Located<Ident>>,
+    pub order_by: Vec<Located<Ident>>,
+    pub partition_by: Vec<Located<Ident>>,
+    // Each index is a list of columns.
+    pub indexes: Vec<Vec<Located<Ident>>>,
+    pub comment: Option<String>,
 }
 
 #[derive(Clone, Debug)]
 pub struct FnDef {
     pub name: Located<Ident>,
     pub generics: Vec<Located<Ident>>,
+    // Bounds on generic parameters (e.g. `R: {id int}`), keyed by the name of the generic. Record
+    // bounds are "open", so any type with at least the bound's fields satisfies them.
+    pub bounds: Vec<NameAndType>,
     pub args: Vec<FnArg>,
     pub ret: Option<Type>,
     pub body: FnBody,
@@ -290,7 +339,11 @@ pub enum StmtBody {
         list: ImportList,
         args: Option<Vec<NameAndExpr>>,
     },
-    TypeDef(NameAndType),
+    TypeDef {
+        name: Located<Ident>,
+        generics: Vec<Located<Ident>>,
+        def: Type,
+    },
     FnDef(FnDef),
     Let {
         name: Located<Ident>,
@@ -302,6 +355,20 @@ pub enum StmtBody {
         name: Located<Ident>,
         type_: Type,
     },
+    // A test block, whose `let`s override the module's non-exported lets and externs while its
+    // `expect` statements run.
+    Test {
+        name: Located<Ident>,
+        body: Vec<Stmt>,
+    },
+    Expect {
+        actual: Expr,
+        expected: Expr,
+    },
+    Assert {
+        expr: Expr,
+        message: Option<String>,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -310,6 +377,8 @@ pub struct Stmt {
     pub body: StmtBody,
     pub start: Location,
     pub end: Location,
+    // The doc comment (`--! ...` or `/** ... */`) written directly before a `let`, `fn`, or `type`.
+    pub doc: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -3,8 +3,10 @@ use std::collections::BTreeMap;
 
 use crate::ast::{Ident, SourceLocation};
 use crate::compile::compile::{Compiler, CompilerConfig};
+use crate::compile::error::Result;
 use crate::compile::inference::mkcref;
 use crate::compile::schema::{Decl, Located, MType, Ref, Schema, TypeEntry};
+use crate::runtime::functions::NativeFn;
 use crate::types::{AtomicType, TimeUnit};
 
 pub use crate::compile::generics::GLOBAL_GENERICS;
@@ -86,6 +88,7 @@ lazy_static! {
                     type_.clone(),
                     BUILTIN_LOC.clone()
                 ))),
+                doc: None,
             },
         ))
         .collect();
@@ -113,3 +116,42 @@ lazy_static! {
         ret
     };
 }
+
+// The name a builtin atomic type is written as (e.g. `bigint`), or None if it has no name.
+pub fn builtin_type_name(atom: &AtomicType) -> Option<&'static str> {
+    BUILTIN_TYPES
+        .iter()
+        .find(|(_, t)| t == atom)
+        .map(|(name, _)| *name)
+}
+
+// Returns the schema that a compiler should use for builtins. If its config provides any native
+// functions (see CompilerConfig::with_native_fn), they are compiled into a schema that is nested
+// within the GLOBAL_SCHEMA, so that they are visible alongside the other builtins.
+pub fn builtin_schema(native_fns: &BTreeMap<Ident, NativeFn>) -> Result<Ref<Schema>> {
+    lazy_static::initialize(&GLOBAL_SCHEMA);
+    if native_fns.is_empty() {
+        return Ok(GLOBAL_SCHEMA.clone());
+    }
+
+    let schema = Schema::new("<native>".to_string(), None);
+    schema.write()?.parent_scope = Some(GLOBAL_SCHEMA.clone());
+
+    let native_compiler = Compiler::new_with_builtins(
+        GLOBAL_SCHEMA.clone(),
+        CompilerConfig {
+            allow_native: true,
+            native_fns: native_fns.clone(),
+            ..Default::default()
+        },
+    )?;
+    let text = native_fns
+        .iter()
+        .map(|(name, f)| format!("fn {}{} = native;\n", name, f.signature))
+        .collect::<String>();
+    native_compiler
+        .compile_string(schema.clone(), text.as_str())
+        .as_result()?;
+
+    Ok(schema)
+}
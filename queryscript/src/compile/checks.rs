@@ -0,0 +1,171 @@
+// Compiles the constraints on a `let` declaration's type (e.g. `id bigint unique not null`) into
+// check queries, each of which returns the rows that violate one constraint.
+use sqlparser::ast as sqlast;
+
+use crate::ast::{self, Ident, Located, Range};
+use crate::compile::compile::{compile_expr, Compiler};
+use crate::compile::error::*;
+use crate::compile::schema::{Check, Ref, Schema};
+use crate::compile::sql::{select_from, select_star_from};
+use crate::compile::type_defs::relation_fields;
+
+pub fn compile_checks(
+    compiler: Compiler,
+    schema: Ref<Schema>,
+    name: &Located<Ident>,
+    type_: &ast::Type,
+) -> Result<()> {
+    let fields = relation_fields(compiler.clone(), schema.clone(), type_)?.unwrap_or_default();
+    let constraints = fields.iter().flat_map(|field| {
+        field
+            .constraints
+            .iter()
+            .map(move |constraint| (field.name.get(), constraint))
+    });
+    for (column, constraint) in constraints {
+        let query = check_query(name.get(), column, constraint.get());
+        let (start, end) = match constraint.location().range() {
+            Some(Range { start, end }) => (start, end),
+            None => (type_.start.clone(), type_.end.clone()),
+        };
+        let compiled = compile_expr(
+            compiler.clone(),
+            schema.clone(),
+            &ast::Expr {
+                body: ast::ExprBody::SQLQuery(query),
+                start,
+                end,
+                is_unsafe: false,
+            },
+        )?;
+
+        schema.write()?.checks.push(Check {
+            decl: name.get().clone(),
+            description: format!(
+                "{}.{} {}",
+                name.get(),
+                column,
+                describe_constraint(constraint.get())
+            ),
+            loc: constraint.location().clone(),
+            query: compiled,
+        });
+    }
+
+    Ok(())
+}
+
+fn describe_constraint(constraint: &ast::Constraint) -> String {
+    match constraint {
+        ast::Constraint::NotNull => "is not null".to_string(),
+        ast::Constraint::Unique => "is unique".to_string(),
+        ast::Constraint::AcceptedValues(values) => format!(
+            "is in ({})",
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        ast::Constraint::Check(expr) => format!("satisfies {}", expr),
+    }
+}
+
+// The check queries avoid relying on parentheses (which are not preserved through compilation) by
+// wrapping the user's expressions in CASE expressions.
+fn check_query(table: &Ident, column: &Ident, constraint: &ast::Constraint) -> sqlast::Query {
+    let relation = sqlast::TableFactor::Table {
+        name: table.into(),
+        alias: None,
+        args: None,
+        with_hints: vec![],
+    };
+    let column_expr = sqlast::Expr::Identifier(sqlast::Located::new(column.into(), None));
+
+    let (relation, selection) = match constraint {
+        ast::Constraint::NotNull => (relation, sqlast::Expr::IsNull(Box::new(column_expr))),
+        ast::Constraint::Unique => {
+            // Report each duplicated value once, along with how many times it appears.
+            let occurrences = sqlast::Ident::new("occurrences");
+            let mut counts = select_from(
+                vec![
+                    sqlast::SelectItem::UnnamedExpr(column_expr.clone()),
+                    sqlast::SelectItem::ExprWithAlias {
+                        expr: sqlast::Expr::Function(sqlast::Function {
+                            name: sqlast::ObjectName(vec![sqlast::Ident::new("count")]),
+                            args: vec![sqlast::FunctionArg::Unnamed(
+                                sqlast::FunctionArgExpr::Wildcard,
+                            )],
+                            over: None,
+                            distinct: false,
+                            special: false,
+                        }),
+                        alias: occurrences.clone(),
+                    },
+                ],
+                vec![sqlast::TableWithJoins {
+                    relation,
+                    joins: Vec::new(),
+                }],
+            );
+            if let sqlast::SetExpr::Select(select) = counts.body.as_mut() {
+                select.selection = Some(sqlast::Expr::IsNotNull(Box::new(column_expr.clone())));
+                select.group_by = vec![column_expr];
+            }
+
+            (
+                sqlast::TableFactor::Derived {
+                    lateral: false,
+                    subquery: Box::new(counts),
+                    alias: Some(sqlast::TableAlias {
+                        name: sqlast::Ident::new("counts"),
+                        columns: Vec::new(),
+                    }),
+                },
+                sqlast::Expr::BinaryOp {
+                    left: Box::new(sqlast::Expr::Identifier(occurrences)),
+                    op: sqlast::BinaryOperator::Gt,
+                    right: Box::new(number("1")),
+                },
+            )
+        }
+        // Like `NOT IN`, NULL values are accepted (use `not null` to reject them).
+        ast::Constraint::AcceptedValues(values) => (
+            relation,
+            sqlast::Expr::BinaryOp {
+                left: Box::new(sqlast::Expr::IsNotNull(Box::new(column_expr.clone()))),
+                op: sqlast::BinaryOperator::And,
+                right: Box::new(sqlast::Expr::Case {
+                    operand: Some(Box::new(column_expr)),
+                    conditions: values.clone(),
+                    results: values.iter().map(|_| boolean(false)).collect(),
+                    else_result: Some(Box::new(boolean(true))),
+                }),
+            },
+        ),
+        // Like SQL's CHECK constraints, rows where the expression is NULL pass.
+        ast::Constraint::Check(expr) => (
+            relation,
+            sqlast::Expr::Case {
+                operand: Some(Box::new(expr.clone())),
+                conditions: vec![boolean(false)],
+                results: vec![boolean(true)],
+                else_result: Some(Box::new(boolean(false))),
+            },
+        ),
+    };
+
+    let mut query = select_star_from(relation);
+    if let sqlast::SetExpr::Select(select) = query.body.as_mut() {
+        select.selection = Some(selection);
+    }
+    query
+}
+
+fn boolean(value: bool) -> sqlast::Expr {
+    sqlast::Expr::Value(sqlast::Value::Boolean(value))
+}
+
+fn number(value: &str) -> sqlast::Expr {
+    sqlast::Expr::Value(sqlast::Value::Number(value.to_string(), false))
+}
@@ -5,23 +5,54 @@ use std::fs;
 use std::path::Path as FilePath;
 use std::sync::Arc;
 
-use crate::compile::builtin_types::{BUILTIN_LOC, GLOBAL_GENERICS, GLOBAL_SCHEMA};
+use crate::compile::builtin_types::{builtin_schema, BUILTIN_LOC, GLOBAL_GENERICS};
+use crate::compile::checks::compile_checks;
 use crate::compile::connection::{ConnectionSchema, ConnectionString};
 use crate::compile::error::*;
+use crate::compile::generics::{as_generic, Generic, GenericFactory, TypeAlias};
 use crate::compile::inference::*;
 use crate::compile::inline::inline_params;
+use crate::compile::lint::{lint_schema, resolve_warnings, Lint, LintConfig, Warning};
 use crate::compile::schema::*;
 use crate::compile::scope::SQLScope;
 use crate::compile::sql::*;
+use crate::compile::type_defs::name_values_columns;
 use crate::compile::unsafe_expr::compile_unsafe_expr;
+use crate::runtime::files::FileFormat;
+use crate::runtime::functions::NativeFn;
+use crate::types::FnValue;
 use crate::{
     ast,
     ast::{Ident, Located, Range, SourceLocation, ToIdents},
+    runtime, types,
 };
 use crate::{c_try, error::MultiResult, parser, parser::parse_schema};
 
 type CompileResult<T> = MultiResult<T, CompileError>;
 
+// The result of compiling a file, along with the lint warnings reported for it (which, unlike
+// errors, do not prevent the schema from being used).
+#[derive(Debug)]
+pub struct FileCompileResult<T> {
+    pub result: T,
+    pub errors: Vec<(Option<usize>, CompileError)>,
+    pub warnings: Vec<Warning>,
+}
+
+impl<T> FileCompileResult<T> {
+    pub fn ok(&self) -> &T {
+        &self.result
+    }
+
+    pub fn as_result(self) -> Result<T> {
+        CompileResult {
+            result: self.result,
+            errors: self.errors,
+        }
+        .as_result()
+    }
+}
+
 // This is a fairly crude hack that aims to "order" how we derive
 // external types. As currently implemented, it ensures that we run
 // load commands before we run unsafe expressions, so the latter can
@@ -73,6 +104,10 @@ pub struct CompilerData {
     pub next_external_type: usize,
     pub external_types: BinaryHeap<ExternalTypeHandle>,
     pub files: BTreeMap<String, String>,
+    // Lint warnings reported while compiling, and the definitions that have been referenced (which
+    // are used to find unused declarations). Both are reset when compiling a file.
+    pub warnings: Vec<Warning>,
+    pub references: BTreeSet<SourceLocation>,
 }
 
 #[derive(Clone, Debug)]
@@ -94,6 +129,7 @@ pub trait OnSymbol {
         type_: CRef<SType>,
         def: SourceLocation,
         is_public: bool,
+        doc: Option<String>,
     ) -> Result<()>;
 }
 
@@ -107,6 +143,7 @@ pub trait OnSchema {
         ast: &ast::Schema,
         schema: Ref<Schema>,
         errors: &Vec<(Option<usize>, CompileError)>,
+        warnings: &Vec<Warning>,
     ) -> Result<()>;
 }
 
@@ -115,6 +152,38 @@ pub struct CompilerConfig {
     pub allow_inlining: bool,
     pub on_symbol: Option<Box<dyn OnSymbol + Send + Sync>>,
     pub on_schema: Option<Box<dyn OnSchema + Send + Sync>>,
+    // Custom generic types (e.g. `Currency<T>`), in addition to the built-in ones. These take
+    // precedence over built-in generics with the same name.
+    pub generics: BTreeMap<Ident, Box<dyn GenericFactory>>,
+    // Native (Rust) functions provided by the embedder, which are visible alongside the builtins.
+    pub native_fns: BTreeMap<Ident, NativeFn>,
+    // The lint levels to use. If not set, they are read from the `.qslint` file closest to each
+    // compiled file.
+    pub lints: Option<LintConfig>,
+}
+
+impl CompilerConfig {
+    pub fn with_generic(mut self, factory: Box<dyn GenericFactory>) -> CompilerConfig {
+        self.generics.insert(factory.name().clone(), factory);
+        self
+    }
+
+    // Adds a native (Rust) function. The signature is everything in a QueryScript function
+    // declaration after the name, e.g. `(zip varchar) -> varchar` or `<R>(value R) -> R`, and is
+    // used to typecheck calls to the function.
+    pub fn with_native_fn<F>(mut self, name: &str, signature: &str, builder: F) -> CompilerConfig
+    where
+        F: Fn(&types::Type) -> runtime::Result<Arc<dyn FnValue>> + Send + Sync + 'static,
+    {
+        self.native_fns.insert(
+            name.into(),
+            NativeFn {
+                signature: signature.to_string(),
+                builder: Arc::new(builder),
+            },
+        );
+        self
+    }
 }
 
 impl Default for CompilerConfig {
@@ -124,6 +193,9 @@ impl Default for CompilerConfig {
             allow_inlining: true,
             on_symbol: None,
             on_schema: None,
+            generics: BTreeMap::new(),
+            native_fns: BTreeMap::new(),
+            lints: None,
         }
     }
 }
@@ -133,6 +205,9 @@ impl fmt::Debug for CompilerConfig {
         f.debug_struct("CompilerConfig")
             .field("allow_native", &self.allow_native)
             .field("allow_inlining", &self.allow_inlining)
+            .field("generics", &self.generics.keys().collect::<Vec<_>>())
+            .field("native_fns", &self.native_fns.keys().collect::<Vec<_>>())
+            .field("lints", &self.lints)
             .finish_non_exhaustive()
     }
 }
@@ -156,8 +231,7 @@ impl Compiler {
     }
 
     pub fn new_with_config(config: CompilerConfig) -> Result<Compiler> {
-        lazy_static::initialize(&GLOBAL_SCHEMA);
-        Compiler::new_with_builtins(GLOBAL_SCHEMA.clone(), config)
+        Compiler::new_with_builtins(builtin_schema(&config.native_fns)?, config)
     }
 
     pub fn new_with_builtins(schema: Ref<Schema>, config: CompilerConfig) -> Result<Compiler> {
@@ -191,6 +265,8 @@ impl Compiler {
                 next_external_type: 1,
                 external_types: BinaryHeap::new(),
                 files: BTreeMap::new(),
+                warnings: Vec::new(),
+                references: BTreeSet::new(),
             }),
             builtins: schema.clone(),
         };
@@ -206,10 +282,31 @@ impl Compiler {
         Ok(self.data.read()?.config.allow_native)
     }
 
+    pub fn native_fn(&self, name: &Ident) -> Result<Option<NativeFn>> {
+        Ok(self.data.read()?.config.native_fns.get(name).cloned())
+    }
+
     pub fn allow_inlining(&self) -> Result<bool> {
         Ok(self.data.read()?.config.allow_inlining)
     }
 
+    // Constructs an instance of the generic type with the given name, preferring any custom
+    // generics registered in the config over the built-in ones.
+    pub fn new_generic(
+        &self,
+        loc: &SourceLocation,
+        name: &Ident,
+        args: Vec<CRef<MType>>,
+    ) -> Result<Option<Arc<dyn Generic>>> {
+        if let Some(factory) = self.data.read()?.config.generics.get(name) {
+            return Ok(Some(factory.new(loc, args)?));
+        }
+        match GLOBAL_GENERICS.get(name) {
+            Some(factory) => Ok(Some(factory.new(loc, args)?)),
+            None => Ok(None),
+        }
+    }
+
     pub fn on_symbol(
         &self,
         mut on_symbol: Option<Box<dyn OnSymbol + Send + Sync>>,
@@ -227,6 +324,9 @@ impl Compiler {
         decl: Option<Decl<E>>, // XXX We could just take is_public here as input?
     ) -> Result<()> {
         let mut data = self.data.write()?;
+        if name.location() != &def {
+            data.references.insert(def.clone());
+        }
         let on_symbol = &mut data.config.on_symbol;
         Ok(match on_symbol {
             Some(f) => f.on_symbol(
@@ -235,6 +335,7 @@ impl Compiler {
                 type_,
                 def,
                 decl.as_ref().map_or(false, |decl| decl.public),
+                decl.and_then(|decl| decl.doc),
             )?,
             None => {}
         })
@@ -254,13 +355,14 @@ impl Compiler {
         ast: &ast::Schema,
         schema: Ref<Schema>,
         errors: &Vec<(Option<usize>, CompileError)>,
+        warnings: &Vec<Warning>,
     ) -> Result<()> {
         let mut data = self.data.write()?;
         let path = FilePath::new(&file);
         let text = data.files.get(&file).unwrap().clone();
         let on_schema = &mut data.config.on_schema;
         Ok(match on_schema {
-            Some(f) => f.on_schema(path, text.as_str(), ast, schema, errors)?,
+            Some(f) => f.on_schema(path, text.as_str(), ast, schema, errors, warnings)?,
             None => {}
         })
     }
@@ -269,7 +371,7 @@ impl Compiler {
         let mut result = CompileResult::new(());
         let file = c_try!(result, schema.read()).file.clone();
         let (tokens, eof) = c_try!(result, parser::tokenize(file.as_str(), text));
-        let mut parser = parser::Parser::new(file.as_str(), tokens, eof);
+        let mut parser = parser::Parser::new(file.as_str(), tokens, eof).with_doc_comments(text);
         let schema_ast = result.absorb(parser.parse_schema());
         result.absorb(self.compile_schema_ast(schema.clone(), &schema_ast));
         result
@@ -288,22 +390,51 @@ impl Compiler {
     pub fn compile_schema_from_file(
         &self,
         file_path: &FilePath,
+    ) -> FileCompileResult<Option<Ref<Schema>>> {
+        let mut warnings = Vec::new();
+        let result = self.compile_file_and_lint(file_path, &mut warnings);
+        FileCompileResult {
+            result: result.result,
+            errors: result.errors,
+            warnings,
+        }
+    }
+
+    fn compile_file_and_lint(
+        &self,
+        file_path: &FilePath,
+        warnings: &mut Vec<Warning>,
     ) -> CompileResult<Option<Ref<Schema>>> {
         let mut result = CompileResult::new(None);
         let runtime = c_try!(result, self.runtime.read());
+
+        // Start from a clean slate, so that only this file's warnings (and references from within
+        // it) are considered, even if the compiler was used before.
+        {
+            let mut data = c_try!(result, self.data.write());
+            data.warnings.clear();
+            data.references.clear();
+        }
+
         runtime.block_on(async {
             let (compile_result, parsed_file) = compile_schema_from_file(self.clone(), file_path);
             result.replace(compile_result);
             result.absorb(self.drive().await);
 
-            if let (Some(schema), Some(parsed_file)) = (result.ok(), parsed_file) {
+            if let (Some(schema), Some(parsed_file)) = (result.ok().clone(), parsed_file) {
+                c_try!(result, lint_schema(self, schema.clone(), &parsed_file.ast));
+                c_try!(
+                    result,
+                    self.report_warnings(&parsed_file.file, &mut result, warnings)
+                );
                 c_try!(
                     result,
                     self.run_on_schema(
                         parsed_file.file,
                         &parsed_file.ast,
                         schema.clone(),
-                        &result.errors
+                        &result.errors,
+                        warnings,
                     )
                 );
             }
@@ -447,6 +578,66 @@ impl Compiler {
         Ok(format!("{}{}", kind, placeholder))
     }
 
+    // Runs a task alongside inference (e.g. to check a type once it's known). Its errors are
+    // reported like those of any other compilation task.
+    pub fn spawn(
+        &self,
+        f: impl std::future::Future<Output = Result<()>> + Send + 'static,
+    ) -> Result<()> {
+        let mut data = self.data.write()?;
+        data.handles.push_back(self.runtime.read()?.spawn(f));
+        Ok(())
+    }
+
+    pub fn warn(&self, lint: Lint, loc: SourceLocation, message: String) -> Result<()> {
+        self.data
+            .write()?
+            .warnings
+            .push(Warning { loc, lint, message });
+        Ok(())
+    }
+
+    pub fn add_reference(&self, def: &SourceLocation) -> Result<()> {
+        self.data.write()?.references.insert(def.clone());
+        Ok(())
+    }
+
+    // Whether the symbol defined at `def` has been referenced (anywhere other than its definition).
+    pub fn is_referenced(&self, def: &SourceLocation) -> Result<bool> {
+        Ok(self.data.read()?.references.contains(def))
+    }
+
+    // Claims the warnings reported while compiling `file`, and resolves them against its lint
+    // configuration. Denied lints are added to the result as errors.
+    fn report_warnings<T>(
+        &self,
+        file: &str,
+        result: &mut CompileResult<T>,
+        reported: &mut Vec<Warning>,
+    ) -> Result<()> {
+        let (warnings, text, config) = {
+            let mut data = self.data.write()?;
+            (
+                std::mem::take(&mut data.warnings),
+                data.files.get(file).cloned(),
+                data.config.lints.clone(),
+            )
+        };
+        let config = match (config, FilePath::new(file).parent()) {
+            (Some(config), _) => config,
+            (None, Some(dir)) => LintConfig::find(dir)?,
+            (None, None) => LintConfig::default(),
+        };
+
+        let (warnings, errors) =
+            resolve_warnings(&config, file, text.as_deref().unwrap_or(""), warnings);
+        reported.extend(warnings);
+        for error in errors {
+            result.add_error(None, error);
+        }
+        Ok(())
+    }
+
     pub fn async_cref<T: Constrainable + 'static>(
         &self,
         f: impl std::future::Future<Output = Result<CRef<T>>> + Send + 'static,
@@ -645,6 +836,9 @@ pub fn lookup_path<E: Entry>(
             check_visibility,
             path,
         )? {
+            if ident.location() != imported.name.location() {
+                compiler.add_reference(imported.name.location())?;
+            }
             lookup_schema(compiler.clone(), schema.clone(), &imported.value)?
                 .read()?
                 .schema
@@ -721,14 +915,17 @@ pub fn resolve_type(
         }
         ast::TypeBody::Struct(entries) => {
             let mut fields = Vec::new();
-            let mut seen = BTreeSet::new();
+            let mut seen = BTreeMap::new();
             for e in entries {
                 match e {
                     ast::StructEntry::NameAndType(nt) => {
-                        if seen.contains(&nt.name) {
-                            return Err(CompileError::duplicate_entry(vec![nt.name.clone()]));
+                        if let Some(first) = seen.get(nt.name.get()) {
+                            return Err(CompileError::duplicate_entry_at(
+                                vec![nt.name.clone()],
+                                first,
+                            ));
                         }
-                        seen.insert(nt.name.clone());
+                        seen.insert(nt.name.get().clone(), nt.name.location().clone());
                         fields.push(MField {
                             name: nt.name.get().clone(),
                             type_: resolve_type(compiler.clone(), schema.clone(), &nt.def)?,
@@ -756,21 +953,76 @@ pub fn resolve_type(
                 .map(|t| resolve_type(compiler.clone(), schema.clone(), t))
                 .collect::<Result<Vec<_>>>()?;
 
-            // Since generic names are hardcoded right now, expect the name to be a single element.
-            // Eventually, this should be a decl lookup though.
+            // User-defined generics (parameterized type aliases) are declared like any other type,
+            // so look them up first, and then fall back to the built-in generics.
+            let decl = match lookup_path::<CRef<MType>>(
+                compiler.clone(),
+                Importer::Schema(schema.clone()),
+                &path,
+                true, /* import_global */
+                true, /* resolve_last */
+            ) {
+                Ok((_, Some(decl), r)) if r.is_empty() => Some(decl),
+                Ok(_) | Err(CompileError::NoSuchEntry { .. }) => None,
+                Err(e) => return Err(e),
+            };
+            if let Some(decl) = decl {
+                if let Some(ident) = path.last() {
+                    compiler.run_on_symbol(
+                        ident.clone(),
+                        SymbolKind::Type,
+                        SType::new_mono(decl.value.clone()),
+                        decl.name.location().clone(),
+                        Some(decl.clone()),
+                    )?;
+                }
+
+                let path = path.clone();
+                return decl.value.then(move |alias: Ref<MType>| {
+                    if let MType::Generic(generic) = &*alias.read()? {
+                        if let Some(alias) = as_generic::<TypeAlias>(generic.get().as_ref()) {
+                            return alias.instantiate(&loc, args.clone());
+                        }
+                    }
+                    Err(CompileError::wrong_kind(
+                        path.clone(),
+                        "generic type",
+                        "non-generic type",
+                    ))
+                });
+            }
+
             let name = if path.len() == 1 {
                 path[0].get()
             } else {
-                return Err(CompileError::unimplemented(loc, "Multi-part generic names"));
+                return Err(CompileError::no_such_entry(path.clone()));
             };
 
-            let generic = match GLOBAL_GENERICS.get(name).map(|g| g.new(&loc, args)) {
-                Some(generic) => generic?,
+            let generic = match compiler.new_generic(&loc, name, args)? {
+                Some(generic) => generic,
                 None => return Err(CompileError::no_such_entry(path.clone())),
             };
 
             Ok(MType::Generic(Located::new(generic, loc)).resolve_generics()?)
         }
+        ast::TypeBody::Fn { args, ret } => {
+            // Function types do not name their arguments, so we give them positional names.
+            // Function types are unified positionally, so these names never need to match the
+            // argument names of the function that is eventually passed in.
+            let args = args
+                .iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    Ok(MField::new_nullable(
+                        format!("arg{}", i).into(),
+                        resolve_type(compiler.clone(), schema.clone(), t)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let ret = resolve_type(compiler, schema, ret.as_ref())?;
+
+            Ok(mkcref(MType::Fn(Located::new(MFnType { args, ret }, loc))))
+        }
     }
 }
 
@@ -850,7 +1102,11 @@ pub fn typecheck_path(type_: CRef<MType>, path: &[Located<Ident>]) -> Result<CRe
     })
 }
 
-fn compile_expr(compiler: Compiler, schema: Ref<Schema>, expr: &ast::Expr) -> Result<CTypedExpr> {
+pub fn compile_expr(
+    compiler: Compiler,
+    schema: Ref<Schema>,
+    expr: &ast::Expr,
+) -> Result<CTypedExpr> {
     let loc = SourceLocation::Range(
         schema.read()?.file.clone(),
         Range {
@@ -860,6 +1116,11 @@ fn compile_expr(compiler: Compiler, schema: Ref<Schema>, expr: &ast::Expr) -> Re
     );
 
     if expr.is_unsafe {
+        compiler.warn(
+            Lint::Unsafe,
+            loc.clone(),
+            "Unsafe expressions are not type checked".to_string(),
+        )?;
         compile_unsafe_expr(compiler, schema, &expr.body, &loc)
     } else {
         Ok(match &expr.body {
@@ -1024,8 +1285,11 @@ fn add_decls<E: Entry>(
     stmt: &ast::Stmt,
 ) -> Result<()> {
     for (name, extern_, value) in &entries {
-        if decls.contains_key(name) {
-            return Err(CompileError::duplicate_entry(vec![name.clone()]));
+        if let Some(first) = decls.get(name.get()) {
+            return Err(CompileError::duplicate_entry_at(
+                vec![name.clone()],
+                first.name.location(),
+            ));
         }
 
         decls.insert(
@@ -1037,6 +1301,7 @@ fn add_decls<E: Entry>(
                     fn_arg: false,
                     name: name.clone(),
                     value: value.clone(),
+                    doc: stmt.doc.clone(),
                 },
                 loc.clone(),
             ),
@@ -1241,11 +1506,23 @@ fn declare_schema_entry(compiler: &Compiler, schema: &Ref<Schema>, stmt: &ast::S
                 }
             }
         }
-        ast::StmtBody::TypeDef(nt) => type_decls.push((
-            nt.name.clone(),
-            false, /* extern_ */
-            MType::new_unknown(nt.name.get().as_ref()),
-        )),
+        ast::StmtBody::TypeDef {
+            name,
+            generics,
+            def,
+        } => {
+            if generics.is_empty() {
+                schema
+                    .write()?
+                    .type_defs
+                    .insert(name.get().clone(), def.clone());
+            }
+            type_decls.push((
+                name.clone(),
+                false, /* extern_ */
+                MType::new_unknown(name.get().as_ref()),
+            ))
+        }
         ast::StmtBody::FnDef(ast::FnDef { name, .. }) => expr_decls.push((
             name.clone(),
             false, /* extern_ */
@@ -1261,6 +1538,10 @@ fn declare_schema_entry(compiler: &Compiler, schema: &Ref<Schema>, stmt: &ast::S
             true, /* extern_ */
             STypedExpr::new_unknown(name.get().as_ref()),
         )),
+        // Test blocks are only compiled by the test runner (see crate::testing), which inlines
+        // their statements into the module.
+        ast::StmtBody::Test { .. } | ast::StmtBody::Expect { .. } => {}
+        ast::StmtBody::Assert { .. } => {}
     };
 
     add_decls(&mut schema.write()?.schema_decls, schema_decls, &loc, stmt)?;
@@ -1367,6 +1648,15 @@ fn compile_materialized_expr(
             let type_ = expr.type_;
             let expr = expr.expr.await?;
             let expr = Arc::new(expr.read()?.clone());
+            let options = compile_materialize_options(
+                compiler.clone(),
+                &loc,
+                &args,
+                type_.clone(),
+                &url,
+                expr.as_ref(),
+            )
+            .await?;
 
             Ok(mkcref(Expr::Materialize(MaterializeExpr {
                 decl_name,
@@ -1374,11 +1664,164 @@ fn compile_materialized_expr(
                 expr: TypedExpr { expr, type_ },
                 url,
                 inlined: false,
+                options,
             })))
         }
     })
 }
 
+async fn compile_materialize_options(
+    compiler: Compiler,
+    loc: &SourceLocation,
+    args: &ast::MaterializeArgs,
+    type_: CRef<SType>,
+    url: &Option<Arc<ConnectionString>>,
+    expr: &Expr<CRef<MType>>,
+) -> Result<MaterializeOptions> {
+    let kind = args
+        .kind
+        .as_ref()
+        .map_or(MaterializeKind::Table, |k| *k.get());
+    let file = compile_file_target(args, kind)?;
+    if kind == MaterializeKind::View {
+        let unsupported = if args.incremental.is_some() || !args.unique_key.is_empty() {
+            Some("incremental mat views")
+        } else if !args.partition_by.is_empty() {
+            Some("partitioned mat views")
+        } else if !args.indexes.is_empty() {
+            Some("indexes on mat views")
+        } else {
+            None
+        };
+        if let Some(what) = unsupported {
+            return Err(CompileError::unimplemented(loc.clone(), what));
+        }
+
+        // A view can only be created if its query runs in the database it is saved to.
+        let same_db = match (url, expr) {
+            (Some(url), Expr::SQL(_, Some(sql_url))) => url.as_ref() == sql_url.as_ref(),
+            (None, _) => true,
+            _ => false,
+        };
+        if !same_db {
+            return Err(CompileError::unimplemented(
+                args.kind.as_ref().unwrap().location().clone(),
+                "mat views whose query does not run in the target database",
+            ));
+        }
+    }
+
+    let columns = args
+        .unique_key
+        .iter()
+        .chain(args.incremental.iter())
+        .chain(args.order_by.iter())
+        .chain(args.partition_by.iter())
+        .chain(args.indexes.iter().flatten())
+        .collect::<Vec<_>>();
+
+    // Any columns that the options refer to must exist in the output type. Files are always
+    // written as relations, with the output type as their schema.
+    if !columns.is_empty() || file.is_some() {
+        let body = type_.await?.read()?.body.clone();
+        let rowtype = get_rowtype(compiler, body)?.await?;
+        let rowtype = rowtype.read()?;
+        let fields = match &*rowtype {
+            MType::Record(fields) => fields,
+            _ => {
+                return Err(CompileError::unimplemented(
+                    loc.clone(),
+                    "mat options on non-relations",
+                ))
+            }
+        };
+        for column in columns {
+            if find_field(fields.get(), column.get()).is_none() {
+                return Err(CompileError::no_such_entry(vec![column.clone()]));
+            }
+        }
+    }
+
+    let idents =
+        |columns: &Vec<Located<Ident>>| columns.iter().map(|c| c.get().clone()).collect::<Vec<_>>();
+    Ok(MaterializeOptions {
+        kind,
+        incremental: if args.incremental.is_some() || !args.unique_key.is_empty() {
+            Some(IncrementalOptions {
+                watermark: args.incremental.as_ref().map(|c| c.get().clone()),
+                unique_key: idents(&args.unique_key),
+            })
+        } else {
+            None
+        },
+        order_by: idents(&args.order_by),
+        partition_by: idents(&args.partition_by),
+        indexes: args.indexes.iter().map(idents).collect(),
+        comment: args.comment.clone(),
+        file,
+    })
+}
+
+fn compile_file_target(
+    args: &ast::MaterializeArgs,
+    kind: MaterializeKind,
+) -> Result<Option<FileTarget>> {
+    let file = match &args.file {
+        Some(file) => file,
+        None => {
+            return match &args.format {
+                Some(format) => Err(CompileError::unimplemented(
+                    format.location().clone(),
+                    "mat format without a file",
+                )),
+                None => Ok(None),
+            }
+        }
+    };
+
+    let unsupported = if args.db.is_some() {
+        Some("saving a mat to both a db and a file")
+    } else if kind == MaterializeKind::View {
+        Some("mat views in files")
+    } else if args.incremental.is_some() || !args.unique_key.is_empty() {
+        Some("incremental mat files")
+    } else if !args.indexes.is_empty() {
+        Some("indexes on mat files")
+    } else if args.comment.is_some() {
+        // Comments are recorded in each database's metadata table, which files do not have.
+        Some("comments on mat files")
+    } else {
+        None
+    };
+    if let Some(what) = unsupported {
+        return Err(CompileError::unimplemented(file.location().clone(), what));
+    }
+
+    let directory = file.get().ends_with('/') || !args.partition_by.is_empty();
+    let format = match &args.format {
+        Some(format) => match FileFormat::from_name(format.get()) {
+            Some(format) => format,
+            None => {
+                return Err(CompileError::unimplemented(
+                    format.location().clone(),
+                    &format!(
+                        "mat format '{}' (expected 'csv', 'json' or 'parquet')",
+                        format.get()
+                    ),
+                ))
+            }
+        },
+        None if directory => FileFormat::Parquet,
+        None => FileFormat::from_path(FilePath::new(file.get()), &None),
+    };
+
+    Ok(Some(FileTarget {
+        path: file.get().trim_end_matches('/').to_string(),
+        format,
+        directory,
+    }))
+}
+
 pub fn compile_schema_entries(
     compiler: Compiler,
     schema: Ref<Schema>,
@@ -1399,6 +1842,9 @@ pub fn compile_schema_entries(
 pub enum FnContext {
     Decl,
     Call,
+    // Compiles a generic function's body against the bounds on its generic parameters, to check
+    // it once at definition time.
+    Check,
 }
 
 pub fn compile_fn_body(
@@ -1429,8 +1875,15 @@ pub fn compile_fn_body(
     let has_expr_body = matches!(def.body, ast::FnBody::Expr(_));
     let compile_body = match context {
         FnContext::Decl => def.generics.is_empty() || !has_expr_body,
-        FnContext::Call => !def.generics.is_empty() && has_expr_body,
+        FnContext::Call | FnContext::Check => !def.generics.is_empty() && has_expr_body,
     };
+    if !def.bounds.is_empty() && !has_expr_body {
+        return Err(CompileError::unimplemented(
+            loc.clone(),
+            "bounded generics in functions without an expression body",
+        ));
+    }
+
     let mut unknowns = BTreeMap::new();
     for generic in def.generics.iter() {
         inner_schema.write()?.type_decls.insert(
@@ -1442,6 +1895,7 @@ pub fn compile_fn_body(
                     fn_arg: true,
                     name: generic.clone(),
                     value: mkcref(MType::Name(generic.clone())),
+                    doc: None,
                 },
                 loc.clone(),
             ),
@@ -1457,22 +1911,65 @@ pub fn compile_fn_body(
                     ))
                 }
             })?;
-        } else if context == FnContext::Call {
+        } else if context == FnContext::Call || context == FnContext::Check {
             unknowns.insert(generic.get().clone(), unknown);
         }
     }
 
+    let mut bounds = BTreeMap::new();
+    for bound in def.bounds.iter() {
+        if bounds.contains_key(bound.name.get()) {
+            let first = def.bounds.iter().find(|b| b.name.get() == bound.name.get());
+            return Err(CompileError::duplicate_entry_at(
+                vec![bound.name.clone()],
+                first.unwrap().name.location(),
+            ));
+        }
+        let type_ = resolve_type(compiler.clone(), inner_schema.clone(), &bound.def)?;
+        bounds.insert(bound.name.get().clone(), type_);
+    }
+    // The types that the body is compiled against. These are the same as the signature's, except
+    // that at a call site, a bounded generic only resolves once its bound is satisfied. That way a
+    // call that fails its bound reports just that error, rather than also failing to compile the
+    // body against the wrong type.
+    let mut body_unknowns = unknowns.clone();
+    if context == FnContext::Check {
+        // When checking the body, each bounded generic is replaced by its bound, so that the body
+        // can only reference the fields that the bound guarantees.
+        for (name, bound) in bounds.iter() {
+            let bound = bound.substitute(&unknowns)?;
+            unknowns.insert(name.clone(), bound);
+        }
+        body_unknowns = unknowns.clone();
+    } else if context == FnContext::Call {
+        for (name, bound) in bounds.iter() {
+            let generic = Located::new(name.clone(), loc.clone());
+            let actual = unknowns.get(name).unwrap().clone();
+            let bound = bound.substitute(&unknowns)?;
+            let checked = compiler.async_cref(async move {
+                check_generic_bound(generic, actual.clone(), bound).await?;
+                Ok(actual)
+            })?;
+            body_unknowns.insert(name.clone(), checked);
+        }
+    }
+
     let mut compiled_args = Vec::new();
     for arg in &def.args {
-        if inner_schema.read()?.expr_decls.get(&arg.name).is_some() {
-            return Err(CompileError::duplicate_entry(vec![arg.name.clone()]));
+        if let Some(first) = inner_schema.read()?.expr_decls.get(&arg.name) {
+            return Err(CompileError::duplicate_entry_at(
+                vec![arg.name.clone()],
+                first.name.location(),
+            ));
         }
         let mut type_ = resolve_type(compiler.clone(), inner_schema.clone(), &arg.type_)?;
+        let mut body_type = type_.clone();
         if compile_body {
             type_ = type_.substitute(&unknowns)?;
+            body_type = body_type.substitute(&body_unknowns)?;
         }
 
-        let stype = SType::new_mono(type_.clone());
+        let stype = SType::new_mono(body_type.clone());
         inner_schema.write()?.expr_decls.insert(
             arg.name.get().clone(),
             Located::new(
@@ -1485,21 +1982,26 @@ pub fn compile_fn_body(
                         type_: stype.clone(),
                         expr: mkcref(Expr::ContextRef(arg.name.get().clone())),
                     },
+                    doc: None,
                 },
                 loc.clone(),
             ),
         );
-        compiler.run_on_symbol::<ExprEntry>(
-            arg.name.clone(),
-            SymbolKind::Argument,
-            stype,
-            arg.name.location().clone(),
-            None,
-        )?;
+        // The declaration already registered the arguments, so the check pass must not register
+        // them a second time.
+        if context != FnContext::Check {
+            compiler.run_on_symbol::<ExprEntry>(
+                arg.name.clone(),
+                SymbolKind::Argument,
+                stype,
+                arg.name.location().clone(),
+                None,
+            )?;
+        }
         inner_schema
             .write()?
             .externs
-            .insert(arg.name.get().clone(), type_.clone());
+            .insert(arg.name.get().clone(), body_type);
         compiled_args.push(MField::new_nullable(arg.name.get().clone(), type_.clone()));
     }
 
@@ -1527,7 +2029,10 @@ pub fn compile_fn_body(
                 (
                     CTypedExpr {
                         type_: MType::new_unknown(&format!("__native('{}')", def.name)),
-                        expr: mkcref(Expr::NativeFn(def.name.get().clone())),
+                        expr: mkcref(Expr::NativeFn(
+                            def.name.get().clone(),
+                            compiler.native_fn(def.name.get())?,
+                        )),
                     },
                     false,
                 )
@@ -1577,6 +2082,90 @@ pub fn compile_fn_body(
     ))
 }
 
+fn compile_type_alias(
+    compiler: Compiler,
+    schema: Ref<Schema>,
+    loc: SourceLocation,
+    name: &Located<Ident>,
+    generics: &Vec<Located<Ident>>,
+    def: &ast::Type,
+) -> Result<CRef<MType>> {
+    // Like a generic function, the parameters are declared as named types in an inner schema, and
+    // substituted for their arguments (via MType::substitute) wherever the alias is used.
+    let inner_schema = Schema::new(schema.read()?.file.clone(), schema.read()?.folder.clone());
+    inner_schema.write()?.parent_scope = Some(schema.clone());
+    for generic in generics.iter() {
+        if let Some(first) = inner_schema.read()?.type_decls.get(generic.get()) {
+            return Err(CompileError::duplicate_entry_at(
+                vec![generic.clone()],
+                first.name.location(),
+            ));
+        }
+        inner_schema.write()?.type_decls.insert(
+            generic.get().clone(),
+            Located::new(
+                Decl {
+                    public: false,
+                    extern_: true,
+                    fn_arg: true,
+                    name: generic.clone(),
+                    value: mkcref(MType::Name(generic.clone())),
+                    doc: None,
+                },
+                loc.clone(),
+            ),
+        );
+    }
+
+    let body = resolve_type(compiler, inner_schema, def)?;
+    Ok(mkcref(MType::Generic(Located::new(
+        Arc::new(TypeAlias::new(
+            name.get().clone(),
+            generics.to_idents(),
+            body,
+        )),
+        loc,
+    ))))
+}
+
+// Checks that a type satisfies the bound on a generic parameter. Record bounds are "open", so the
+// actual type may have more fields than the bound (in any order), and those extra fields flow
+// through to the function's output type.
+async fn check_generic_bound(
+    generic: Located<Ident>,
+    actual: CRef<MType>,
+    bound: CRef<MType>,
+) -> Result<()> {
+    let (mut actual, mut bound) = (actual, bound);
+    loop {
+        let actual_type = actual.clone_inner().await?;
+        let bound_type = bound.clone_inner().await?;
+        match (&actual_type, &bound_type) {
+            (MType::List(actual_inner), MType::List(bound_inner)) => {
+                actual = actual_inner.get().clone();
+                bound = bound_inner.get().clone();
+            }
+            (MType::Record(actual_fields), MType::Record(bound_fields)) => {
+                for bound_field in bound_fields.get().iter() {
+                    match find_field(actual_fields.get(), &bound_field.name) {
+                        Some(actual_field) => actual_field.type_.unify(&bound_field.type_)?,
+                        None => {
+                            return Err(CompileError::generic_bound(
+                                generic.location().clone(),
+                                generic.get().clone(),
+                                format!("missing field {} in {:?}", bound_field.name, actual_type)
+                                    .as_str(),
+                            ))
+                        }
+                    }
+                }
+                return Ok(());
+            }
+            _ => return actual.unify(&bound),
+        }
+    }
+}
+
 fn compile_schema_entry(compiler: &Compiler, schema: &Ref<Schema>, stmt: &ast::Stmt) -> Result<()> {
     let loc = SourceLocation::Range(
         schema.read()?.file.clone(),
@@ -1592,9 +2181,24 @@ fn compile_schema_entry(compiler: &Compiler, schema: &Ref<Schema>, stmt: &ast::S
             schema.write()?.exprs.push(Located::new(compiled, loc));
         }
         ast::StmtBody::Import { .. } => {}
-        ast::StmtBody::TypeDef(nt) => {
-            let type_ = resolve_type(compiler.clone(), schema.clone(), &nt.def)?;
-            unify_type_decl(compiler.clone(), schema.clone(), &nt.name, type_)?;
+        ast::StmtBody::TypeDef {
+            name,
+            generics,
+            def,
+        } => {
+            let type_ = if generics.is_empty() {
+                resolve_type(compiler.clone(), schema.clone(), def)?
+            } else {
+                compile_type_alias(
+                    compiler.clone(),
+                    schema.clone(),
+                    loc.clone(),
+                    name,
+                    generics,
+                    def,
+                )?
+            };
+            unify_type_decl(compiler.clone(), schema.clone(), name, type_)?;
         }
         ast::StmtBody::FnDef(def) => {
             let (compiled_fn, generics) = compile_fn_body(
@@ -1605,6 +2209,23 @@ fn compile_schema_entry(compiler: &Compiler, schema: &Ref<Schema>, stmt: &ast::S
                 FnContext::Decl,
             )?;
 
+            // Generic functions with expression bodies are otherwise only compiled when they are
+            // called, so if every generic is bounded, check the body against the bounds once here.
+            let fully_bounded = !def.bounds.is_empty()
+                && def
+                    .generics
+                    .iter()
+                    .all(|g| def.bounds.iter().any(|b| b.name.get() == g.get()));
+            if fully_bounded && matches!(def.body, ast::FnBody::Expr(_)) {
+                compile_fn_body(
+                    compiler.clone(),
+                    schema.clone(),
+                    loc.clone(),
+                    def,
+                    FnContext::Check,
+                )?;
+            }
+
             unify_expr_decl(
                 compiler.clone(),
                 schema.clone(),
@@ -1629,7 +2250,15 @@ fn compile_schema_entry(compiler: &Compiler, schema: &Ref<Schema>, stmt: &ast::S
             } else {
                 MType::new_unknown(format!("typeof {}", name).as_str())
             };
-            let mut compiled = compile_expr(compiler.clone(), schema.clone(), &body)?;
+            let values_body = match type_ {
+                Some(t) => name_values_columns(compiler.clone(), schema.clone(), t, body)?,
+                None => None,
+            };
+            let mut compiled = compile_expr(
+                compiler.clone(),
+                schema.clone(),
+                values_body.as_ref().unwrap_or(body),
+            )?;
 
             compiled.expr = match materialize {
                 None => compiled.expr.clone(),
@@ -1653,6 +2282,10 @@ fn compile_schema_entry(compiler: &Compiler, schema: &Ref<Schema>, stmt: &ast::S
                     expr: compiled.expr,
                 },
             )?;
+
+            if let Some(t) = type_ {
+                compile_checks(compiler.clone(), schema.clone(), name, t)?;
+            }
         }
         ast::StmtBody::Extern { name, type_ } => {
             unify_expr_decl(
@@ -1665,6 +2298,33 @@ fn compile_schema_entry(compiler: &Compiler, schema: &Ref<Schema>, stmt: &ast::S
                 },
             )?;
         }
+        ast::StmtBody::Test { .. } => {}
+        ast::StmtBody::Expect { actual, expected } => {
+            let actual = compile_expr(compiler.clone(), schema.clone(), actual)?;
+            let expected = compile_expr(compiler.clone(), schema.clone(), expected)?;
+            schema.write()?.expectations.push(Expectation {
+                loc,
+                actual,
+                expected,
+            });
+        }
+        ast::StmtBody::Assert { expr, message } => {
+            let compiled = compile_expr(compiler.clone(), schema.clone(), expr)?;
+            compiled
+                .type_
+                .unify(&resolve_global_atom(compiler.clone(), "bool")?)?;
+
+            // Without a message, failures are described by the expression itself.
+            let message = message.clone().unwrap_or_else(|| match &expr.body {
+                ast::ExprBody::SQLQuery(query) => query.to_string(),
+                ast::ExprBody::SQLExpr(expr) => expr.to_string(),
+            });
+            schema.write()?.asserts.push(Assertion {
+                loc,
+                message,
+                expr: compiled,
+            });
+        }
     };
 
     Ok(())
@@ -1702,6 +2362,23 @@ fn optimize_schema(compiler: Compiler, schema: Ref<Schema>) -> CompileResult<()>
         expr.get_mut().expr = c_try!(result, cref_inline_params(compiler.clone(), expr_value));
     }
 
+    for check in s.checks.iter_mut() {
+        let expr_value = check.query.expr.clone();
+        check.query.expr = c_try!(result, cref_inline_params(compiler.clone(), expr_value));
+    }
+
+    for assertion in s.asserts.iter_mut() {
+        let expr_value = assertion.expr.expr.clone();
+        assertion.expr.expr = c_try!(result, cref_inline_params(compiler.clone(), expr_value));
+    }
+
+    for expectation in s.expectations.iter_mut() {
+        for side in [&mut expectation.actual, &mut expectation.expected] {
+            let expr_value = side.expr.clone();
+            side.expr = c_try!(result, cref_inline_params(compiler.clone(), expr_value));
+        }
+    }
+
     result
 }
 
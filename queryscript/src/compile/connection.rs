@@ -99,16 +99,21 @@ impl ConnectionString {
     }
 }
 
-impl std::fmt::Debug for ConnectionString {
+// NOTE: This intentionally omits any credentials or query parameters in the URL.
+impl std::fmt::Display for ConnectionString {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ConnectionString(")?;
         write!(f, "{}://", self.0.scheme())?;
         match self.0.host() {
             Some(h) => write!(f, "{}", h)?,
             None => {}
         };
-        write!(f, "{}", self.0.path())?;
-        write!(f, ")")
+        write!(f, "{}", self.0.path())
+    }
+}
+
+impl std::fmt::Debug for ConnectionString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ConnectionString({})", self)
     }
 }
 
@@ -195,6 +200,7 @@ impl ConnectionSchema {
                             type_: SType::new_mono(external_type),
                             expr,
                         },
+                        doc: None,
                     },
                     self.location.clone(),
                 );
@@ -1,9 +1,12 @@
 use crate::ast;
 use crate::ast::{Pretty, Range};
-use crate::compile::schema::MType;
+use crate::compile::builtin_types::builtin_type_name;
+use crate::compile::inference::CRef;
+use crate::compile::lint::Lint;
+use crate::compile::schema::{MField, MType};
 use crate::error::MultiError;
 pub use crate::parser::error::ErrorLocation;
-use crate::parser::error::{ParserError, PrettyError};
+use crate::parser::error::{ParserError, PrettyError, RelatedLocation};
 use crate::runtime::error::RuntimeError;
 use crate::types::error::TypesystemError;
 use colored::*;
@@ -71,6 +74,7 @@ pub enum CompileError {
     #[snafu(display("Duplicate entry: {}", path.pretty()))]
     DuplicateEntry {
         path: ast::Path,
+        related: Vec<RelatedLocation>,
         backtrace: Option<Backtrace>,
     },
 
@@ -93,7 +97,12 @@ pub enum CompileError {
         backtrace: Option<Backtrace>,
     },
 
-    #[snafu(display("Type mismatch: found {} not {}", rhs.pretty(), lhs.pretty()))]
+    #[snafu(display(
+        "Type mismatch: found {} not {}{}",
+        rhs.pretty(),
+        lhs.pretty(),
+        record_diff(lhs, rhs)
+    ))]
     WrongType {
         lhs: MType,
         rhs: MType,
@@ -128,6 +137,37 @@ pub enum CompileError {
         loc: ErrorLocation,
     },
 
+    #[snafu(display("Type does not satisfy the bound on {}: {}", generic, what))]
+    GenericBoundError {
+        generic: ast::Ident,
+        what: String,
+        backtrace: Option<Backtrace>,
+        loc: ErrorLocation,
+    },
+
+    #[snafu(display("Cannot save {}: {}", name, what))]
+    SaveError {
+        name: ast::Ident,
+        what: String,
+        backtrace: Option<Backtrace>,
+        loc: ErrorLocation,
+    },
+
+    #[snafu(display("{} [{}]", what, lint))]
+    LintError {
+        lint: Lint,
+        what: String,
+        backtrace: Option<Backtrace>,
+        loc: ErrorLocation,
+    },
+
+    #[snafu(display("Invalid lint configuration: {}", what))]
+    LintConfigError {
+        what: String,
+        backtrace: Option<Backtrace>,
+        loc: ErrorLocation,
+    },
+
     #[snafu(display("{}", sources.first().unwrap()))]
     Multiple {
         // This is assumed to be non-empty
@@ -150,7 +190,22 @@ impl CompileError {
     }
 
     pub fn duplicate_entry(path: ast::Path) -> CompileError {
-        return DuplicateEntrySnafu { path }.build();
+        return DuplicateEntrySnafu {
+            path,
+            related: Vec::new(),
+        }
+        .build();
+    }
+
+    // Like duplicate_entry, but also points at the first definition (if it is known and isn't the
+    // same location as the duplicate).
+    pub fn duplicate_entry_at(path: ast::Path, first: &ErrorLocation) -> CompileError {
+        let related = match first {
+            ErrorLocation::Unknown => Vec::new(),
+            first if first == &path_location(&path) => Vec::new(),
+            first => vec![RelatedLocation::new(first.clone(), "first defined here")],
+        };
+        return DuplicateEntrySnafu { path, related }.build();
     }
 
     pub fn wrong_kind(path: ast::Path, expected: &str, kind: &str) -> CompileError {
@@ -202,6 +257,41 @@ impl CompileError {
         .build();
     }
 
+    pub fn generic_bound(loc: ErrorLocation, generic: ast::Ident, what: &str) -> CompileError {
+        return GenericBoundSnafu {
+            loc,
+            generic,
+            what: what.to_string(),
+        }
+        .build();
+    }
+
+    pub fn save_error(loc: ErrorLocation, name: ast::Ident, what: &str) -> CompileError {
+        return SaveSnafu {
+            loc,
+            name,
+            what: what.to_string(),
+        }
+        .build();
+    }
+
+    pub fn lint(loc: ErrorLocation, lint: Lint, what: &str) -> CompileError {
+        return LintSnafu {
+            loc,
+            lint,
+            what: what.to_string(),
+        }
+        .build();
+    }
+
+    pub fn lint_config(loc: ErrorLocation, what: &str) -> CompileError {
+        return LintConfigSnafu {
+            loc,
+            what: what.to_string(),
+        }
+        .build();
+    }
+
     pub fn internal(loc: ErrorLocation, what: &str) -> CompileError {
         return InternalSnafu {
             loc,
@@ -254,6 +344,91 @@ pub fn path_location(path: &Vec<ast::Located<ast::Ident>>) -> ErrorLocation {
     )
 }
 
+// For record types, lists the columns that are missing from, extra in, or typed differently in the
+// actual type (rhs) compared to the expected type (lhs), one per line.
+fn record_diff(lhs: &MType, rhs: &MType) -> String {
+    let (lfields, rfields) = match (lhs, rhs) {
+        (MType::Record(lfields), MType::Record(rfields)) => (lfields.get(), rfields.get()),
+        _ => return String::new(),
+    };
+
+    let mut diff = Vec::new();
+    for lfield in lfields.iter() {
+        match rfields.iter().find(|rfield| rfield.name == lfield.name) {
+            None => diff.push(format!("missing column {}", lfield.name)),
+            Some(rfield) => {
+                // Unknown types will be inferred from the other side, so they are not a mismatch.
+                if !lfield.type_.is_known().unwrap_or(false)
+                    || !rfield.type_.is_known().unwrap_or(false)
+                {
+                    continue;
+                }
+                if !same_type(&lfield.type_, &rfield.type_) {
+                    diff.push(format!(
+                        "column {} has type {} not {}",
+                        lfield.name,
+                        type_name(&rfield.type_),
+                        type_name(&lfield.type_)
+                    ));
+                }
+            }
+        }
+    }
+    for rfield in rfields.iter() {
+        if !lfields.iter().any(|lfield| lfield.name == rfield.name) {
+            diff.push(format!("extra column {}", rfield.name));
+        }
+    }
+
+    diff.iter().map(|line| format!("\n  {}", line)).collect()
+}
+
+// Whether two types have the same structure (regardless of where they are defined). As in
+// record_diff, a type that is not known yet matches anything.
+fn same_type(lhs: &CRef<MType>, rhs: &CRef<MType>) -> bool {
+    let (lhs, rhs) = match (known(lhs), known(rhs)) {
+        (Some(lhs), Some(rhs)) => (lhs, rhs),
+        _ => return true,
+    };
+    match (&lhs, &rhs) {
+        (MType::Atom(l), MType::Atom(r)) => l.get() == r.get(),
+        (MType::Record(l), MType::Record(r)) => same_fields(l.get(), r.get()),
+        (MType::List(l), MType::List(r)) => same_type(l.get(), r.get()),
+        (MType::Fn(l), MType::Fn(r)) => {
+            same_fields(&l.get().args, &r.get().args) && same_type(&l.get().ret, &r.get().ret)
+        }
+        (MType::Name(l), MType::Name(r)) => l.get() == r.get(),
+        (MType::Generic(l), MType::Generic(r)) => l.get().name() == r.get().name(),
+        _ => false,
+    }
+}
+
+fn same_fields(lhs: &[MField], rhs: &[MField]) -> bool {
+    lhs.len() == rhs.len()
+        && lhs.iter().zip(rhs.iter()).all(|(l, r)| {
+            l.name == r.name && l.nullable == r.nullable && same_type(&l.type_, &r.type_)
+        })
+}
+
+// Writes a type the way it would be declared where possible (e.g. `bigint` rather than `Int64`).
+fn type_name(type_: &CRef<MType>) -> String {
+    let type_ = match known(type_) {
+        Some(type_) => type_,
+        None => return "?".to_string(),
+    };
+    match &type_ {
+        MType::Atom(atom) => match builtin_type_name(atom.get()) {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", atom.get()),
+        },
+        _ => type_.pretty(),
+    }
+}
+
+fn known(type_: &CRef<MType>) -> Option<MType> {
+    Some(type_.must().ok()?.read().ok()?.clone())
+}
+
 impl PrettyError for CompileError {
     fn location(&self) -> ErrorLocation {
         match self {
@@ -273,9 +448,32 @@ impl PrettyError for CompileError {
             CompileError::ImportError { path, .. } => path_location(path),
             CompileError::ScalarSubselectError { loc, .. } => loc.clone(),
             CompileError::InvalidConnectionError { loc, .. } => loc.clone(),
+            CompileError::GenericBoundError { loc, .. } => loc.clone(),
+            CompileError::SaveError { loc, .. } => loc.clone(),
+            CompileError::LintError { loc, .. } => loc.clone(),
+            CompileError::LintConfigError { loc, .. } => loc.clone(),
             CompileError::Multiple { sources } => sources.first().unwrap().location(),
         }
     }
+
+    fn related(&self) -> Vec<RelatedLocation> {
+        match self {
+            CompileError::DuplicateEntry { related, .. } => related.clone(),
+            CompileError::WrongType { lhs, rhs, .. } => {
+                let found = rhs.location();
+                if found == ErrorLocation::Unknown || found == lhs.location() {
+                    Vec::new()
+                } else {
+                    vec![RelatedLocation::new(
+                        found,
+                        "conflicting type comes from here",
+                    )]
+                }
+            }
+            CompileError::Multiple { sources } => sources.first().unwrap().related(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl<Guard> From<std::sync::PoisonError<Guard>> for CompileError {
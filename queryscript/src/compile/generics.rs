@@ -44,7 +44,7 @@ pub fn as_generic<T: Generic + 'static>(g: &dyn Generic) -> Option<&T> {
     g.as_any().downcast_ref::<T>()
 }
 
-fn debug_fmt_generic(
+pub fn debug_fmt_generic(
     f: &mut std::fmt::Formatter<'_>,
     name: &Ident,
     args: Vec<CRef<MType>>,
@@ -60,7 +60,7 @@ fn debug_fmt_generic(
     write!(f, ">")
 }
 
-fn validate_args(
+pub fn validate_args(
     loc: &SourceLocation,
     args: &Vec<CRef<MType>>,
     num: usize,
@@ -116,7 +116,7 @@ lazy_static! {
     .collect::<BTreeMap<Ident, Box<dyn GenericFactory>>>();
 }
 
-fn resolve_to_runtime_type<G: Generic + Clone + 'static>(
+pub fn resolve_to_runtime_type<G: Generic + Clone + 'static>(
     loc: &SourceLocation,
     args: Vec<CRef<MType>>,
     g: &G,
@@ -479,3 +479,108 @@ impl Generic for ConnectionType {
         resolve_to_runtime_type(loc, vec![], self)
     }
 }
+
+// A user-defined type alias with type parameters, e.g. `type Paged<T> = {page int, rows [T]}`. The
+// alias itself is stored as the type declaration, and each use of it (e.g. `Paged<int>`) substitutes
+// the arguments for the parameters in its body.
+#[derive(Clone)]
+pub struct TypeAlias {
+    name: Ident,
+    params: Vec<Ident>,
+    body: CRef<MType>,
+}
+
+impl TypeAlias {
+    pub fn new(name: Ident, params: Vec<Ident>, body: CRef<MType>) -> TypeAlias {
+        TypeAlias { name, params, body }
+    }
+
+    pub fn instantiate(&self, loc: &SourceLocation, args: Vec<CRef<MType>>) -> Result<CRef<MType>> {
+        validate_args(loc, &args, self.params.len(), &self.name)?;
+        let variables = self
+            .params
+            .iter()
+            .cloned()
+            .zip(args.into_iter())
+            .collect::<BTreeMap<_, _>>();
+        self.body.substitute(&variables)
+    }
+}
+
+impl std::fmt::Debug for TypeAlias {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}<{}> = ",
+            self.name,
+            self.params
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+        std::fmt::Debug::fmt(&self.body, f)
+    }
+}
+
+impl Generic for TypeAlias {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &Ident {
+        &self.name
+    }
+
+    fn to_runtime_type(&self) -> crate::runtime::error::Result<crate::types::Type> {
+        Err(runtime::error::RuntimeError::new(
+            format!(
+                "{} expects {} type argument(s)",
+                self.name,
+                self.params.len()
+            )
+            .as_str(),
+        ))
+    }
+
+    fn substitute(&self, variables: &BTreeMap<Ident, CRef<MType>>) -> Result<Arc<dyn Generic>> {
+        // The alias's own parameters shadow any variables with the same name.
+        let variables = variables
+            .iter()
+            .filter(|(name, _)| !self.params.contains(name))
+            .map(|(name, type_)| (name.clone(), type_.clone()))
+            .collect();
+        Ok(Arc::new(Self {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            body: self.body.substitute(&variables)?,
+        }))
+    }
+
+    fn unify(&self, other: &MType) -> Result<()> {
+        match other {
+            MType::Generic(other_inner) => {
+                if let Some(other) = as_generic::<Self>(other_inner.get().as_ref()) {
+                    if self.name == other.name && self.params == other.params {
+                        return self.body.unify(&other.body);
+                    }
+                }
+            }
+            _ => {}
+        };
+        Err(CompileError::wrong_type(
+            &MType::Generic(Located::new(
+                Arc::new(self.clone()),
+                SourceLocation::Unknown,
+            )),
+            other,
+        ))
+    }
+
+    fn resolve(&self, loc: &SourceLocation) -> Result<CRef<MType>> {
+        Ok(mkcref(MType::Generic(Located::new(
+            Arc::new(self.clone()),
+            loc.clone(),
+        ))))
+    }
+}
@@ -26,11 +26,75 @@ impl Visitor<CRef<MType>> for ContextInliner {
                     None
                 }
             }
+            Expr::FnCall(FnCallExpr { func, args, .. }) => {
+                let callee = match func.expr.as_ref() {
+                    Expr::ContextRef(name) => self.context.get(name),
+                    _ => None,
+                };
+                match callee {
+                    Some(callee) => self.inline_fn_call(callee.clone(), args).await?,
+                    None => None,
+                }
+            }
             _ => None,
         })
     }
 }
 
+impl ContextInliner {
+    // Calls to function-valued arguments are compiled into FnCall expressions whose callee is a
+    // ContextRef. Once the argument is bound to a function with an expression body, we can inline
+    // the function's body directly, just like a call to a known function.
+    async fn inline_fn_call(
+        &self,
+        callee: Arc<Expr<CRef<MType>>>,
+        args: &Vec<TypedExpr<CRef<MType>>>,
+    ) -> Result<Option<Expr<CRef<MType>>>> {
+        let (arg_names, callee) = match callee.as_ref() {
+            Expr::SchemaEntry(STypedExpr { type_, expr }) => {
+                let stype = type_.await?;
+                let body = stype.read()?.body.clone();
+                let type_ = body.await?;
+                let arg_names = match &*type_.read()? {
+                    MType::Fn(fn_type) => fn_type
+                        .args
+                        .iter()
+                        .map(|a| a.name.clone())
+                        .collect::<Vec<_>>(),
+                    _ => return Ok(None),
+                };
+                (
+                    arg_names,
+                    expr.clone_inner().await?.unwrap_schema_entry().await?,
+                )
+            }
+            _ => return Ok(None),
+        };
+
+        let body = match callee {
+            Expr::Fn(FnExpr {
+                body: FnBody::Expr(body),
+                ..
+            }) => body,
+            Expr::UncompiledFn(def) => {
+                return Err(CompileError::unimplemented(
+                    def.name.location().clone(),
+                    "passing generic functions as arguments",
+                ))
+            }
+            _ => return Ok(None),
+        };
+
+        let mut context = BTreeMap::new();
+        for (name, arg) in arg_names.into_iter().zip(args.iter()) {
+            context.insert(name, Arc::new(arg.expr.visit(self).await?));
+        }
+
+        let body = inline_context(body, context).await?;
+        Ok(Some(inline_params(body.as_ref()).await?))
+    }
+}
+
 pub async fn inline_context(
     expr: Arc<Expr<CRef<MType>>>,
     context: BTreeMap<Ident, Arc<Expr<CRef<MType>>>>,
@@ -159,6 +223,7 @@ impl Visitor<CRef<MType>> for ParamInliner {
                             url,
                             decl_name,
                             inlined: _,
+                            options,
                         }) => {
                             // If we can inline tables, then we can inline materialized expressions (we simply expect them
                             // to have been saved to the database at some point).
@@ -191,6 +256,7 @@ impl Visitor<CRef<MType>> for ParamInliner {
                                         url: url.clone(),
                                         decl_name: decl_name.clone(),
                                         inlined,
+                                        options: options.clone(),
                                     })),
                                 },
                             );
@@ -223,6 +289,7 @@ impl Visitor<CRef<MType>> for ParamInliner {
                 url,
                 decl_name,
                 inlined,
+                options,
             }) => {
                 let expr = TypedExpr {
                     type_: expr.type_.clone(),
@@ -243,6 +310,7 @@ impl Visitor<CRef<MType>> for ParamInliner {
                     url: url.clone(),
                     decl_name: decl_name.clone(),
                     inlined: *inlined,
+                    options: options.clone(),
                 }))
             }
             _ => None,
@@ -0,0 +1,556 @@
+// Lints are warnings about code that compiles, but is likely to be a mistake (e.g. an unused `let`
+// or a join without a condition). Each lint can be allowed, warned about, or denied (reported as an
+// error) in a `.qslint` file, which applies to the files in its directory and its subdirectories:
+//
+//     # Unsafe expressions are fine in this project
+//     unsafe = allow
+//     cartesian_join = deny
+//
+// A lint can also be suppressed on a single line with a `-- qs:allow(unused_let, ...)` comment,
+// either on that line or on the line before it.
+use colored::*;
+use sqlparser::ast as sqlast;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path as FilePath;
+
+use crate::ast::{self, Ident, Location, Pretty, Range, SourceLocation};
+use crate::compile::compile::Compiler;
+use crate::compile::error::*;
+use crate::compile::schema::{Ref, Schema, SchemaPath};
+use crate::compile::traverse::{SQLVisitor, VisitSQL};
+use crate::parser::error::PrettyError;
+
+pub const LINT_CONFIG_FILE: &str = ".qslint";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lint {
+    UnusedImport,
+    UnusedLet,
+    ExportedSelectStar,
+    CartesianJoin,
+    CoercedComparison,
+    Unsafe,
+    ShadowedName,
+}
+
+impl Lint {
+    pub const ALL: &'static [Lint] = &[
+        Lint::UnusedImport,
+        Lint::UnusedLet,
+        Lint::ExportedSelectStar,
+        Lint::CartesianJoin,
+        Lint::CoercedComparison,
+        Lint::Unsafe,
+        Lint::ShadowedName,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedImport => "unused_import",
+            Lint::UnusedLet => "unused_let",
+            Lint::ExportedSelectStar => "exported_select_star",
+            Lint::CartesianJoin => "cartesian_join",
+            Lint::CoercedComparison => "coerced_comparison",
+            Lint::Unsafe => "unsafe",
+            Lint::ShadowedName => "shadowed_name",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().find(|l| l.name() == name).cloned()
+    }
+
+    // Unsafe expressions are an intentional escape hatch, so they are only reported in projects
+    // that opt into it.
+    pub fn default_level(&self) -> LintLevel {
+        match self {
+            Lint::Unsafe => LintLevel::Allow,
+            _ => LintLevel::Warn,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl LintLevel {
+    pub fn from_name(name: &str) -> Option<LintLevel> {
+        match name {
+            "allow" => Some(LintLevel::Allow),
+            "warn" => Some(LintLevel::Warn),
+            "deny" => Some(LintLevel::Deny),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LintConfig {
+    pub levels: BTreeMap<Lint, LintLevel>,
+}
+
+impl LintConfig {
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels
+            .get(&lint)
+            .cloned()
+            .unwrap_or_else(|| lint.default_level())
+    }
+
+    pub fn with_level(mut self, lint: Lint, level: LintLevel) -> LintConfig {
+        self.levels.insert(lint, level);
+        self
+    }
+
+    pub fn parse(file: &str, text: &str) -> Result<LintConfig> {
+        let mut config = LintConfig::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let loc = SourceLocation::Single(
+                file.to_string(),
+                Location {
+                    line: i as u64 + 1,
+                    column: 1,
+                },
+            );
+            let (name, level) = match line.split_once('=') {
+                Some((name, level)) => (name.trim(), level.trim()),
+                None => {
+                    return Err(CompileError::lint_config(
+                        loc,
+                        "expected a line like `unused_let = allow`",
+                    ))
+                }
+            };
+            let lint = Lint::from_name(name).ok_or_else(|| {
+                CompileError::lint_config(loc.clone(), format!("unknown lint {}", name).as_str())
+            })?;
+            let level = LintLevel::from_name(level).ok_or_else(|| {
+                CompileError::lint_config(
+                    loc.clone(),
+                    format!("unknown level {} (expected allow, warn, or deny)", level).as_str(),
+                )
+            })?;
+            config.levels.insert(lint, level);
+        }
+        Ok(config)
+    }
+
+    // Finds the closest `.qslint` file in the given directory or one of its parents.
+    pub fn find(dir: &FilePath) -> Result<LintConfig> {
+        for dir in dir.ancestors() {
+            let path = dir.join(LINT_CONFIG_FILE);
+            if path.is_file() {
+                let text = fs::read_to_string(&path)?;
+                return LintConfig::parse(path.to_str().unwrap(), &text);
+            }
+        }
+        Ok(LintConfig::default())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Warning {
+    pub loc: SourceLocation,
+    pub lint: Lint,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.lint)
+    }
+}
+
+impl PrettyError for Warning {
+    fn location(&self) -> SourceLocation {
+        self.loc.clone()
+    }
+
+    fn pretty(&self) -> String {
+        format!(
+            "{}{} {} {}",
+            self.loc.pretty(),
+            ":".white().bold(),
+            "warning:".bright_yellow(),
+            self.to_string()
+        )
+    }
+}
+
+// Drops the warnings that are not in `file`, allowed by the config, or suppressed by a comment, and
+// splits the rest into warnings and (denied) errors.
+pub fn resolve_warnings(
+    config: &LintConfig,
+    file: &str,
+    text: &str,
+    warnings: Vec<Warning>,
+) -> (Vec<Warning>, Vec<CompileError>) {
+    let suppressed = suppressions(text);
+    let mut ret = Vec::new();
+    let mut errors = Vec::new();
+    for warning in warnings.into_iter().collect::<BTreeSet<_>>() {
+        if warning.loc.file().as_deref() != Some(file) {
+            continue;
+        }
+        let line = warning.loc.range().map(|r| r.start.line);
+        if line.map_or(false, |line| {
+            suppressed
+                .get(&line)
+                .map_or(false, |lints| lints.contains(&warning.lint))
+        }) {
+            continue;
+        }
+        match config.level(warning.lint) {
+            LintLevel::Allow => {}
+            LintLevel::Warn => ret.push(warning),
+            LintLevel::Deny => errors.push(CompileError::lint(
+                warning.loc,
+                warning.lint,
+                &warning.message,
+            )),
+        }
+    }
+    (ret, errors)
+}
+
+// Finds the `-- qs:allow(...)` comments, keyed by the lines they apply to.
+fn suppressions(text: &str) -> BTreeMap<u64, BTreeSet<Lint>> {
+    let mut ret = BTreeMap::<u64, BTreeSet<Lint>>::new();
+    for (i, line) in text.lines().enumerate() {
+        let comment = match line.find("--") {
+            Some(idx) => line[idx + 2..].trim_start(),
+            None => continue,
+        };
+        let lints = match comment
+            .strip_prefix("qs:allow(")
+            .and_then(|rest| rest.split_once(')'))
+        {
+            Some((lints, _)) => lints
+                .split(',')
+                .filter_map(|name| Lint::from_name(name.trim()))
+                .collect::<Vec<_>>(),
+            None => continue,
+        };
+        let line = i as u64 + 1;
+        for line in [line, line + 1] {
+            ret.entry(line).or_default().extend(lints.iter().cloned());
+        }
+    }
+    ret
+}
+
+// Runs the lints that look at a whole (compiled) file. The rest are reported by the compiler as it
+// goes (see `Compiler::warn`).
+pub fn lint_schema(compiler: &Compiler, schema: Ref<Schema>, ast: &ast::Schema) -> Result<()> {
+    let schema = schema.read()?;
+    lint_unused(compiler, &schema, ast)?;
+    lint_shadowed(compiler, &schema, ast)?;
+
+    let loads = ast
+        .stmts
+        .iter()
+        .filter_map(|stmt| match &stmt.body {
+            ast::StmtBody::Let { name, body, .. } if is_load(body) => Some(name.get().clone()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+
+    for stmt in ast.stmts.iter() {
+        let expr = match &stmt.body {
+            ast::StmtBody::Expr(expr) => expr,
+            ast::StmtBody::Let { body, .. } => body,
+            ast::StmtBody::FnDef(ast::FnDef {
+                body: ast::FnBody::Expr(expr),
+                ..
+            }) => expr,
+            ast::StmtBody::Assert { expr, .. } => expr,
+            _ => continue,
+        };
+        let loc = SourceLocation::Range(
+            schema.file.clone(),
+            Range {
+                start: expr.start.clone(),
+                end: expr.end.clone(),
+            },
+        );
+        lint_joins(compiler, &loc, expr)?;
+
+        if let ast::StmtBody::Let {
+            name, type_: None, ..
+        } = &stmt.body
+        {
+            if stmt.export {
+                lint_select_star(compiler, &schema, &loads, &loc, name.get(), expr)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn lint_unused(compiler: &Compiler, schema: &Schema, ast: &ast::Schema) -> Result<()> {
+    let mut imports = BTreeSet::new();
+    let mut lets = BTreeSet::new();
+    for stmt in ast.stmts.iter().filter(|stmt| !stmt.export) {
+        match &stmt.body {
+            // Connections are declared under their database's name, at the location of the URL.
+            ast::StmtBody::Import { path, list, .. } => match list {
+                ast::ImportList::None => {
+                    imports.extend(path.first().map(|p| p.location().clone()));
+                    imports.extend(path.last().map(|p| p.location().clone()));
+                }
+                ast::ImportList::Items(items) => imports.extend(
+                    items
+                        .iter()
+                        .filter_map(|i| i.first())
+                        .map(|i| i.location().clone()),
+                ),
+                ast::ImportList::Star => {}
+            },
+            ast::StmtBody::Let { name, .. } => {
+                lets.insert(name.location().clone());
+            }
+            _ => {}
+        }
+    }
+
+    let decls = schema
+        .schema_decls
+        .values()
+        .map(|d| (&d.name, d.public))
+        .chain(schema.type_decls.values().map(|d| (&d.name, d.public)))
+        .chain(schema.expr_decls.values().map(|d| (&d.name, d.public)));
+    for (name, public) in decls {
+        let loc = name.location();
+        let (lint, what) = if public {
+            continue;
+        } else if imports.contains(loc) {
+            (Lint::UnusedImport, "Unused import")
+        } else if lets.contains(loc) {
+            (Lint::UnusedLet, "Unused let")
+        } else {
+            continue;
+        };
+        if !compiler.is_referenced(loc)? {
+            compiler.warn(lint, loc.clone(), format!("{}: {}", what, name.get()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn lint_shadowed(compiler: &Compiler, schema: &Schema, ast: &ast::Schema) -> Result<()> {
+    let builtins = compiler.builtins();
+    let builtins = builtins.read()?;
+    for stmt in ast.stmts.iter() {
+        let (name, builtin) = match &stmt.body {
+            ast::StmtBody::TypeDef { name, .. } => {
+                (name, builtins.type_decls.contains_key(name.get()))
+            }
+            ast::StmtBody::FnDef(ast::FnDef { name, .. })
+            | ast::StmtBody::Let { name, .. }
+            | ast::StmtBody::Extern { name, .. } => {
+                (name, builtins.expr_decls.contains_key(name.get()))
+            }
+            _ => continue,
+        };
+        if builtin {
+            compiler.warn(
+                Lint::ShadowedName,
+                name.location().clone(),
+                format!("{} shadows a built-in declaration", name.get()),
+            )?;
+        }
+
+        if let ast::StmtBody::FnDef(def) = &stmt.body {
+            for arg in def.args.iter() {
+                let shadowed = if schema.expr_decls.contains_key(arg.name.get()) {
+                    "declaration"
+                } else if builtins.expr_decls.contains_key(arg.name.get()) {
+                    "built-in declaration"
+                } else {
+                    continue;
+                };
+                compiler.warn(
+                    Lint::ShadowedName,
+                    arg.name.location().clone(),
+                    format!("Argument {} shadows a {}", arg.name.get(), shadowed),
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn lint_joins(compiler: &Compiler, loc: &SourceLocation, expr: &ast::Expr) -> Result<()> {
+    for query in queries(expr) {
+        for select in selects(&query.body) {
+            if select.from.len() > 1 && select.selection.is_none() {
+                compiler.warn(
+                    Lint::CartesianJoin,
+                    loc.clone(),
+                    format!(
+                        "Implicit cartesian join between {} (add a WHERE clause or use CROSS JOIN)",
+                        select
+                            .from
+                            .iter()
+                            .map(|t| t.relation.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                )?;
+            }
+            for join in select.from.iter().flat_map(|t| t.joins.iter()) {
+                if matches!(
+                    join.join_operator,
+                    sqlast::JoinOperator::Inner(sqlast::JoinConstraint::None)
+                ) {
+                    compiler.warn(
+                        Lint::CartesianJoin,
+                        loc.clone(),
+                        format!("Join with {} has no condition", join.relation),
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// An exported relation that selects * from an external source (a connection, an extern, or a
+// loaded file) changes shape whenever the source does, so its columns should be listed.
+fn lint_select_star(
+    compiler: &Compiler,
+    schema: &Schema,
+    loads: &BTreeSet<Ident>,
+    loc: &SourceLocation,
+    name: &Ident,
+    expr: &ast::Expr,
+) -> Result<()> {
+    let query = match &expr.body {
+        ast::ExprBody::SQLQuery(query) => query,
+        ast::ExprBody::SQLExpr(_) => return Ok(()),
+    };
+
+    for select in selects(&query.body) {
+        if !select.projection.iter().any(|item| {
+            matches!(
+                item,
+                sqlast::SelectItem::Wildcard(..) | sqlast::SelectItem::QualifiedWildcard(..)
+            )
+        }) {
+            continue;
+        }
+
+        let relations = select
+            .from
+            .iter()
+            .flat_map(|t| std::iter::once(&t.relation).chain(t.joins.iter().map(|j| &j.relation)));
+        for relation in relations {
+            let path = match relation {
+                sqlast::TableFactor::Table { name, .. } => &name.0,
+                _ => continue,
+            };
+            if is_external(schema, loads, path) {
+                compiler.warn(
+                    Lint::ExportedSelectStar,
+                    loc.clone(),
+                    format!(
+                        "Exported {} selects * from external source {}",
+                        name, relation
+                    ),
+                )?;
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_external(
+    schema: &Schema,
+    loads: &BTreeSet<Ident>,
+    path: &Vec<sqlast::Located<sqlast::Ident>>,
+) -> bool {
+    let first: Ident = match path.first() {
+        Some(first) => first.get().into(),
+        None => return false,
+    };
+    if path.len() > 1 {
+        return matches!(
+            schema.schema_decls.get(&first).map(|d| &d.value),
+            Some(SchemaPath::Connection(_))
+        );
+    }
+    loads.contains(&first) || schema.expr_decls.get(&first).map_or(false, |d| d.extern_)
+}
+
+fn is_load(expr: &ast::Expr) -> bool {
+    match &expr.body {
+        ast::ExprBody::SQLExpr(sqlast::Expr::Function(f)) => f
+            .name
+            .0
+            .last()
+            .map_or(false, |n| n.get().value.eq_ignore_ascii_case("load")),
+        _ => false,
+    }
+}
+
+// Collects the expression's queries, including subqueries and CTEs.
+#[derive(Default)]
+struct Queries {
+    queries: RefCell<Vec<sqlast::Query>>,
+}
+
+impl SQLVisitor for Queries {
+    fn visit_sqlquery(&self, query: &sqlast::Query) -> Option<sqlast::Query> {
+        self.queries.borrow_mut().push(query.clone());
+        None
+    }
+}
+
+fn queries(expr: &ast::Expr) -> Vec<sqlast::Query> {
+    let visitor = Queries::default();
+    match &expr.body {
+        ast::ExprBody::SQLQuery(query) => {
+            let _ = query.visit_sql(&visitor);
+        }
+        ast::ExprBody::SQLExpr(expr) => {
+            let _ = expr.visit_sql(&visitor);
+        }
+    }
+    visitor.queries.into_inner()
+}
+
+// The SELECTs that make up a query's body (i.e. the sides of a UNION), excluding subqueries.
+fn selects(body: &sqlast::SetExpr) -> Vec<&sqlast::Select> {
+    match body {
+        sqlast::SetExpr::Select(select) => vec![select.as_ref()],
+        sqlast::SetExpr::Query(query) => selects(&query.body),
+        sqlast::SetExpr::SetOperation { left, right, .. } => {
+            let mut ret = selects(left);
+            ret.extend(selects(right));
+            ret
+        }
+        _ => Vec::new(),
+    }
+}
@@ -1,24 +1,31 @@
 pub mod autocomplete;
 mod builtin_types;
+mod checks;
 mod coerce;
 pub mod compile;
 mod connection;
 pub mod error;
 mod external;
-mod generics;
+pub mod generics;
 pub mod inference;
 pub mod inline;
+pub mod lint;
 pub mod schema;
 mod scope;
 pub mod sql;
 pub mod traverse;
+mod type_defs;
 mod unsafe_expr;
 mod util;
 
+pub use builtin_types::builtin_type_name;
 pub use compile::{
-    lookup_path, lookup_schema, Compiler, CompilerConfig, OnSchema, OnSymbol, SymbolKind,
+    lookup_path, lookup_schema, Compiler, CompilerConfig, FileCompileResult, OnSchema, OnSymbol,
+    SymbolKind,
 };
 pub use connection::ConnectionString;
 pub use error::{CompileError, Result};
+pub use generics::{BuiltinGeneric, Generic, GenericConstructor, GenericFactory};
+pub use lint::{Lint, LintConfig, LintLevel, Warning};
 pub use schema::{mkref, Schema, SchemaRef};
 pub use sql::compile_reference;
@@ -19,6 +19,8 @@ use crate::compile::{
     sql::{select_from, select_no_from, select_star_from, with_table_alias},
 };
 use crate::runtime;
+use crate::runtime::files::FileFormat;
+use crate::runtime::functions::NativeFn;
 use crate::types::{AtomicType, Field, FnType, Type};
 
 pub use crate::compile::inference::CRef;
@@ -62,7 +64,17 @@ impl Constrainable for MFnType {
             args: rargs,
             ret: rret,
         } = other;
-        largs.unify(&rargs)?;
+        // Function arguments are unified positionally (ignoring their names), so that a function
+        // can be passed to an argument declared with a function type like `fn(int) -> int`.
+        if largs.len() != rargs.len() {
+            return Err(CompileError::wrong_type(
+                &MType::Fn(Located::new(self.clone(), SourceLocation::Unknown)),
+                &MType::Fn(Located::new(other.clone(), SourceLocation::Unknown)),
+            ));
+        }
+        for (larg, rarg) in largs.iter().zip(rargs.iter()) {
+            larg.type_.unify(&rarg.type_)?;
+        }
         lret.unify(&rret)?;
         Ok(())
     }
@@ -842,6 +854,45 @@ where
     pub ctx_folder: Option<String>,
 }
 
+// Updates a materialized table in place, instead of rebuilding it each time it is saved.
+#[derive(Clone, Debug)]
+pub struct IncrementalOptions {
+    // Only insert rows whose value in this column is greater than the largest value already in
+    // the table.
+    pub watermark: Option<Ident>,
+    // Replace existing rows that have the same values for these columns.
+    pub unique_key: Vec<Ident>,
+}
+
+pub use ast::MaterializeKind;
+
+// A file (or directory of files) that a materialized expression is saved to, instead of a
+// database.
+#[derive(Clone, Debug)]
+pub struct FileTarget {
+    pub path: String,
+    pub format: FileFormat,
+    // Whether `path` is a directory, which holds one file per partition.
+    pub directory: bool,
+}
+
+// Options that control how a materialized expression is persisted by `save_views`.
+#[derive(Clone, Debug, Default)]
+pub struct MaterializeOptions {
+    pub kind: MaterializeKind,
+    pub incremental: Option<IncrementalOptions>,
+    // The order in which rows are written. Partitioning columns are sorted on first, so that rows
+    // in the same partition are stored together.
+    pub order_by: Vec<Ident>,
+    // Files are written with one directory per partition. Databases do not have partitions, so a
+    // table's rows are clustered by these columns instead.
+    pub partition_by: Vec<Ident>,
+    pub indexes: Vec<Vec<Ident>>,
+    // Recorded in the target database's metadata table, alongside the object it describes.
+    pub comment: Option<String>,
+    pub file: Option<FileTarget>,
+}
+
 #[derive(Clone, Debug)]
 pub struct MaterializeExpr<TypeRef>
 where
@@ -852,6 +903,7 @@ where
     pub expr: TypedExpr<TypeRef>,
     pub url: Option<Arc<ConnectionString>>,
     pub inlined: bool,
+    pub options: MaterializeOptions,
 }
 
 #[derive(Clone, Debug)]
@@ -863,7 +915,9 @@ where
     SchemaEntry(STypedExpr),
     Fn(FnExpr<TypeRef>),
     FnCall(FnCallExpr<TypeRef>),
-    NativeFn(Ident),
+    // Embedder-provided native functions carry their implementation, while the builtin ones
+    // (e.g. load) are looked up by name.
+    NativeFn(Ident, Option<NativeFn>),
     ContextRef(Ident),
     Connection(Arc<ConnectionString>),
     Materialize(MaterializeExpr<TypeRef>),
@@ -908,7 +962,7 @@ impl Expr<CRef<MType>> {
                 ctx_folder: ctx_folder.clone(),
             })),
             Expr::SchemaEntry(e) => e.expr.must()?.read()?.to_runtime_type(),
-            Expr::NativeFn(f) => Ok(Expr::NativeFn(f.clone())),
+            Expr::NativeFn(f, native) => Ok(Expr::NativeFn(f.clone(), native.clone())),
             Expr::ContextRef(r) => Ok(Expr::ContextRef(r.clone())),
             Expr::Connection(c) => Ok(Expr::Connection(c.clone())),
             Expr::Materialize(MaterializeExpr {
@@ -917,12 +971,14 @@ impl Expr<CRef<MType>> {
                 url: target_url,
                 decl_name,
                 inlined,
+                options,
             }) => Ok(Expr::Materialize(MaterializeExpr {
                 key: key.clone(),
                 expr: expr.to_runtime_type()?,
                 url: target_url.clone(),
                 decl_name: decl_name.clone(),
                 inlined: inlined.clone(),
+                options: options.clone(),
             })),
             Expr::UncompiledFn(def) => Ok(Expr::UncompiledFn(def.clone())),
             Expr::Unknown => Ok(Expr::Unknown),
@@ -1131,6 +1187,7 @@ pub struct Decl<Entry: Clone> {
     pub fn_arg: bool,
     pub name: Located<Ident>,
     pub value: Entry,
+    pub doc: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -1227,6 +1284,33 @@ impl<T> Pretty for Located<T> {
     }
 }
 
+// A data quality check on a `let` declaration. The query returns the rows that violate one of the
+// constraints on the declaration's type (e.g. `id bigint unique not null`).
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub decl: Ident,
+    pub description: String,
+    pub loc: SourceLocation,
+    pub query: CTypedExpr,
+}
+
+// An `expect <actual> equals <expected>` statement from a test block.
+#[derive(Clone, Debug)]
+pub struct Expectation {
+    pub loc: SourceLocation,
+    pub actual: CTypedExpr,
+    pub expected: CTypedExpr,
+}
+
+// An `assert <expr> [, 'message']` statement, which fails at runtime unless the expression is
+// true.
+#[derive(Clone, Debug)]
+pub struct Assertion {
+    pub loc: SourceLocation,
+    pub message: String,
+    pub expr: CTypedExpr,
+}
+
 pub type DeclMap<Entry> = BTreeMap<Ident, Located<Decl<Entry>>>;
 
 #[derive(Clone, Debug)]
@@ -1242,6 +1326,13 @@ pub struct Schema {
 
     pub imports: BTreeMap<SchemaPath, Ref<ImportedSchema>>,
     pub exprs: Vec<Located<CTypedExpr>>,
+
+    // The definitions of (non-generic) type declarations, which are used to find the constraints
+    // on their fields, and the checks compiled from them.
+    pub type_defs: BTreeMap<Ident, ast::Type>,
+    pub checks: Vec<Check>,
+    pub expectations: Vec<Expectation>,
+    pub asserts: Vec<Assertion>,
 }
 
 impl Schema {
@@ -1256,6 +1347,10 @@ impl Schema {
             expr_decls: BTreeMap::new(),
             imports: BTreeMap::new(),
             exprs: Vec::new(),
+            type_defs: BTreeMap::new(),
+            checks: Vec::new(),
+            expectations: Vec::new(),
+            asserts: Vec::new(),
         })
     }
 
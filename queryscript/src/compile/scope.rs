@@ -158,11 +158,11 @@ impl SQLScope {
         type_: CRef<MType>,
     ) -> Result<()> {
         match self.relations.entry(name.clone()) {
-            btree_map::Entry::Occupied(_) => {
-                return Err(CompileError::duplicate_entry(vec![Ident::with_location(
-                    loc.clone(),
-                    name.clone(),
-                )]))
+            btree_map::Entry::Occupied(e) => {
+                return Err(CompileError::duplicate_entry_at(
+                    vec![Ident::with_location(loc.clone(), name.clone())],
+                    &e.get().1,
+                ))
             }
             btree_map::Entry::Vacant(e) => {
                 e.insert((type_, loc.clone()));
@@ -7,7 +7,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::sync::Arc;
 
-use crate::compile::coerce::CoerceOp;
+use crate::compile::coerce::{is_numeric, CoerceOp};
 use crate::compile::compile::{
     compile_fn_body, lookup_path, resolve_global_atom, typecheck_path, Compiler, FnContext,
     SymbolKind,
@@ -17,12 +17,13 @@ use crate::compile::generics;
 use crate::compile::generics::{as_generic, ConnectionType, ExternalType, GenericConstructor};
 use crate::compile::inference::*;
 use crate::compile::inline::*;
+use crate::compile::lint::Lint;
 use crate::compile::schema::*;
 use crate::compile::scope::{AvailableReferences, SQLScope};
 use crate::types::{number::parse_numeric_type, AtomicType, Type};
 use crate::{
     ast,
-    ast::{SourceLocation, ToPath, ToSqlIdent},
+    ast::{Pretty, SourceLocation, ToPath, ToSqlIdent},
 };
 
 use super::compile::ExternalTypeRank;
@@ -86,6 +87,54 @@ pub fn get_rowtype(compiler: Compiler, relation: CRef<MType>) -> Result<CRef<MTy
     })?)
 }
 
+fn rename_columns(
+    compiler: Compiler,
+    loc: &SourceLocation,
+    relation: CRef<MType>,
+    columns: &Vec<sqlast::Located<sqlast::Ident>>,
+) -> Result<CRef<MType>> {
+    let rowtype = get_rowtype(compiler, relation)?;
+    let loc = loc.clone();
+    let columns = columns
+        .iter()
+        .map(|c| Ident::from(c.get()))
+        .collect::<Vec<_>>();
+    rowtype.then(move |rowtype: Ref<MType>| {
+        let fields = match &*rowtype.read()? {
+            MType::Record(fields) => fields.get().clone(),
+            other => {
+                return Err(CompileError::wrong_type(
+                    &MType::Record(Located::new(Vec::new(), loc.clone())),
+                    other,
+                ))
+            }
+        };
+        if fields.len() != columns.len() {
+            return Err(CompileError::unimplemented(
+                loc.clone(),
+                &format!(
+                    "renaming {} columns of a relation with {} columns",
+                    columns.len(),
+                    fields.len()
+                ),
+            ));
+        }
+
+        let fields = fields
+            .into_iter()
+            .zip(columns.iter())
+            .map(|(field, name)| MField {
+                name: name.clone(),
+                ..field
+            })
+            .collect();
+        Ok(mkcref(MType::List(Located::new(
+            mkcref(MType::Record(Located::new(fields, loc.clone()))),
+            loc.clone(),
+        ))))
+    })
+}
+
 pub fn param_ident(value: String) -> sqlast::Located<sqlast::Ident> {
     sqlast::Ident::new(value)
 }
@@ -373,6 +422,7 @@ pub fn compile_reference(
                         )))),
                         expr: mkcref(Expr::Connection(url.clone())),
                     },
+                    doc: None,
                 }
             }
         },
@@ -769,6 +819,14 @@ impl CompileSQL for sqlast::TableFactor {
                     ),
                 };
 
+                // A column list (e.g. `AS t(a, b)`) renames the subquery's columns by position.
+                let columns = alias.as_ref().map_or(Vec::new(), |a| a.columns.clone());
+                let subquery_type = if columns.is_empty() {
+                    subquery_type
+                } else {
+                    rename_columns(compiler.clone(), &loc, subquery_type, &columns)?
+                };
+
                 scope
                     .write()?
                     .add_reference(&name.get().into(), &loc, subquery_type.clone())?;
@@ -784,7 +842,7 @@ impl CompileSQL for sqlast::TableFactor {
                             subquery: Box::new(subquery_expr.body),
                             alias: Some(sqlast::TableAlias {
                                 name: name.clone(),
-                                columns: Vec::new(),
+                                columns,
                             }),
                         },
                     ))
@@ -1536,7 +1594,66 @@ pub fn compile_setexpr(
                 })?,
             ))
         }
-        sqlast::SetExpr::Values(_) => Err(CompileError::unimplemented(loc.clone(), "VALUES")),
+        sqlast::SetExpr::Values(values) => {
+            let scope = SQLScope::new(parent_scope);
+            let num_columns = values.rows.first().map_or(0, |row| row.len());
+            if num_columns == 0 || values.rows.iter().any(|row| row.len() != num_columns) {
+                return Err(CompileError::unimplemented(
+                    loc.clone(),
+                    "VALUES rows with different numbers of columns",
+                ));
+            }
+
+            let mut columns = vec![Vec::new(); num_columns];
+            for row in values.rows.iter() {
+                for (i, expr) in row.iter().enumerate() {
+                    columns[i].push(compile_sqlarg(
+                        compiler.clone(),
+                        schema.clone(),
+                        scope.clone(),
+                        loc,
+                        expr,
+                    )?);
+                }
+            }
+
+            // Each column's values are coerced to a common type. The columns are named like
+            // DuckDB names them (col0, col1, ...), and can be renamed with a table alias, e.g.
+            // `(VALUES (1, 'a')) AS t(id, name)`.
+            let mut fields = Vec::new();
+            let mut column_sqls = Vec::new();
+            for (i, column) in columns.into_iter().enumerate() {
+                let (type_, column) =
+                    coerce_all(compiler, &sqlast::BinaryOperator::Eq, loc, column)?;
+                fields.push(MField::new_nullable(format!("col{}", i).into(), type_));
+                column_sqls.push(combine_crefs(column.into_iter().map(|c| c.sql).collect())?);
+            }
+            let type_ = mkcref(MType::List(Located::new(
+                mkcref(MType::Record(Located::new(fields, loc.clone()))),
+                loc.clone(),
+            )));
+
+            let values = values.clone();
+            Ok((
+                scope,
+                type_,
+                compiler.async_cref(async move {
+                    let mut names = CSQLNames::new();
+                    let mut rows = vec![Vec::new(); values.rows.len()];
+                    for column in column_sqls {
+                        let column = combine_sql_exprs(column.await?.read()?.iter(), &mut names)?;
+                        for (row, expr) in rows.iter_mut().zip(column.into_iter()) {
+                            row.push(expr);
+                        }
+                    }
+
+                    Ok(SQLSnippet::wrap(
+                        names,
+                        sqlast::SetExpr::Values(sqlast::Values { rows, ..values }),
+                    ))
+                })?,
+            ))
+        }
         sqlast::SetExpr::Insert(_) => Err(CompileError::unimplemented(loc.clone(), "INSERT")),
         sqlast::SetExpr::Table(_) => Err(CompileError::unimplemented(loc.clone(), "TABLE")),
     }
@@ -1631,6 +1748,48 @@ fn coerce_all(
     Ok((target, ret))
 }
 
+// Comparing different kinds of values (e.g. a text column to a number column) only works because
+// the engine implicitly casts one of them, which is usually a mistake.
+fn lint_comparison(
+    compiler: &Compiler,
+    loc: &SourceLocation,
+    lhs: &CRef<MType>,
+    rhs: &CRef<MType>,
+) -> Result<()> {
+    let (lhs, rhs) = (lhs.clone(), rhs.clone());
+    compiler.spawn({
+        let compiler = compiler.clone();
+        let loc = loc.clone();
+        async move {
+            let (lhs, rhs) = (lhs.await?, rhs.await?);
+            let (lhs, rhs) = (lhs.read()?, rhs.read()?);
+            let mismatched = match (lhs.to_runtime_type(), rhs.to_runtime_type()) {
+                (Ok(Type::Atom(l)), Ok(Type::Atom(r))) => {
+                    use AtomicType::*;
+                    l != r
+                        && !(is_numeric(&l) && is_numeric(&r))
+                        && !(matches!(l, Utf8 | LargeUtf8) && matches!(r, Utf8 | LargeUtf8))
+                        && !matches!(l, Null)
+                        && !matches!(r, Null)
+                }
+                _ => false,
+            };
+            if mismatched {
+                compiler.warn(
+                    Lint::CoercedComparison,
+                    loc,
+                    format!(
+                        "Comparison between {} and {} implicitly casts one of them",
+                        lhs.pretty(),
+                        rhs.pretty()
+                    ),
+                )?;
+            }
+            Ok(())
+        }
+    })
+}
+
 pub fn unify_all<T, C, I>(mut iter: I, unknown_debug_name: &str) -> Result<CRef<T>>
 where
     T: Constrainable + 'static,
@@ -2029,6 +2188,12 @@ pub fn compile_sqlexpr(
                     result_type
                 }
                 Eq | NotEq | Lt | LtEq | Gt | GtEq => {
+                    // Literals (e.g. `created_at > '2020-01-01'`) are cast on purpose.
+                    if !matches!(left.as_ref(), sqlast::Expr::Value(_))
+                        && !matches!(right.as_ref(), sqlast::Expr::Value(_))
+                    {
+                        lint_comparison(&compiler, loc, &cleft.type_, &cright.type_)?;
+                    }
                     let (_, casted) = coerce_all(&compiler, &op, loc, vec![cleft, cright])?;
                     (cleft, cright) = (casted[0].clone(), casted[1].clone());
                     resolve_global_atom(compiler.clone(), "bool")?
@@ -2425,8 +2590,28 @@ pub fn compile_sqlexpr(
                         _ => func_expr,
                     };
 
+                    // If the function is itself an argument (i.e. a higher-order function is calling
+                    // one of its function-valued arguments), then the callee is not known until the
+                    // enclosing function is called. Defer the call, and let inline_context() inline the
+                    // callee's body once the argument is bound.
+                    //
+                    if let Expr::ContextRef(_) = &compiled_func_expr {
+                        let args = args
+                            .iter()
+                            .map(TypedNameAndExpr::to_typed_expr)
+                            .collect::<Vec<_>>();
+                        return Ok(mkcref(Expr::FnCall(FnCallExpr {
+                            func: Arc::new(TypedExpr {
+                                type_: mkcref(MType::Fn(fn_type.clone())),
+                                expr: Arc::new(compiled_func_expr),
+                            }),
+                            args,
+                            ctx_folder: schema.read()?.folder.clone(),
+                        })));
+                    }
+
                     let (fn_kind, fn_body) = match compiled_func_expr {
-                        Expr::NativeFn(_) => (FnKind::Native, None),
+                        Expr::NativeFn(..) => (FnKind::Native, None),
                         Expr::Fn(FnExpr { body, .. }) => match body {
                             FnBody::SQLBuiltin => (FnKind::SQLBuiltin, None),
                             FnBody::Expr(expr) => (FnKind::Expr, Some(expr.clone())),
@@ -964,7 +964,7 @@ impl<V: Visitor<schema::CRef<schema::MType>> + Sync> Visit<V, schema::CRef<schem
                 let expr = (&e.expr).await?.read()?.clone();
                 expr.visit(visitor).await?
             }
-            Expr::NativeFn(f) => Expr::NativeFn(f.clone()),
+            Expr::NativeFn(f, native) => Expr::NativeFn(f.clone(), native.clone()),
             Expr::ContextRef(r) => Expr::ContextRef(r.clone()),
             Expr::Connection(u) => Expr::Connection(u.clone()),
             Expr::Materialize(MaterializeExpr {
@@ -973,12 +973,14 @@ impl<V: Visitor<schema::CRef<schema::MType>> + Sync> Visit<V, schema::CRef<schem
                 url,
                 decl_name,
                 inlined,
+                options,
             }) => Expr::Materialize(MaterializeExpr {
                 key: key.clone(),
                 expr: expr.visit(visitor).await?,
                 url: url.clone(),
                 decl_name: decl_name.clone(),
                 inlined: inlined.clone(),
+                options: options.clone(),
             }),
             Expr::Unknown => Expr::Unknown,
             Expr::UncompiledFn(def) => Expr::UncompiledFn(def.clone()),
@@ -0,0 +1,126 @@
+// Finds the fields of a declared type from its source definition (following references and
+// `...` includes), which keeps details like field constraints that the compiled type does not.
+use sqlparser::ast as sqlast;
+
+use crate::ast;
+use crate::compile::compile::{lookup_path, Compiler};
+use crate::compile::error::*;
+use crate::compile::inference::CRef;
+use crate::compile::schema::{Importer, MType, Ref, Schema};
+use crate::compile::sql::select_star_from;
+
+/// Returns the fields of a relation (list of records) type, or None if the type is not a relation
+/// or its definition cannot be found (e.g. it is imported from a connection).
+pub fn relation_fields(
+    compiler: Compiler,
+    schema: Ref<Schema>,
+    type_: &ast::Type,
+) -> Result<Option<Vec<ast::NameAndType>>> {
+    match &type_.body {
+        ast::TypeBody::List(inner) => {
+            let mut fields = Vec::new();
+            Ok(if record_fields(compiler, schema, inner, &mut fields)? {
+                Some(fields)
+            } else {
+                None
+            })
+        }
+        ast::TypeBody::Reference(path) => match resolve_type_def(compiler.clone(), schema, path)? {
+            Some((schema, def)) => relation_fields(compiler, schema, &def),
+            None => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Names the columns of a bare `VALUES` body after the fields of the declared type, e.g.
+/// `let users [User] = VALUES (1, 'Alice'), (2, 'Bob');`. Returns None if the body is not a
+/// `VALUES` query or the type's fields cannot be found.
+pub fn name_values_columns(
+    compiler: Compiler,
+    schema: Ref<Schema>,
+    type_: &ast::Type,
+    body: &ast::Expr,
+) -> Result<Option<ast::Expr>> {
+    let query = match &body.body {
+        ast::ExprBody::SQLQuery(query)
+            if matches!(query.body.as_ref(), sqlast::SetExpr::Values(_)) =>
+        {
+            query
+        }
+        _ => return Ok(None),
+    };
+    let fields = match relation_fields(compiler, schema, type_)? {
+        Some(fields) => fields,
+        None => return Ok(None),
+    };
+
+    let relation = sqlast::TableFactor::Derived {
+        lateral: false,
+        subquery: Box::new(query.clone()),
+        alias: Some(sqlast::TableAlias {
+            name: sqlast::Ident::new("t"),
+            columns: fields
+                .iter()
+                .map(|field| sqlast::Located::new(field.name.get().into(), None))
+                .collect(),
+        }),
+    };
+    Ok(Some(ast::Expr {
+        body: ast::ExprBody::SQLQuery(select_star_from(relation)),
+        ..body.clone()
+    }))
+}
+
+fn record_fields(
+    compiler: Compiler,
+    schema: Ref<Schema>,
+    type_: &ast::Type,
+    fields: &mut Vec<ast::NameAndType>,
+) -> Result<bool> {
+    match &type_.body {
+        ast::TypeBody::Struct(entries) => {
+            for entry in entries {
+                match entry {
+                    ast::StructEntry::NameAndType(nt) => fields.push(nt.clone()),
+                    ast::StructEntry::Include(path) => {
+                        match resolve_type_def(compiler.clone(), schema.clone(), path)? {
+                            Some((schema, def))
+                                if record_fields(compiler.clone(), schema, &def, fields)? => {}
+                            _ => return Ok(false),
+                        }
+                    }
+                }
+            }
+            Ok(true)
+        }
+        ast::TypeBody::Reference(path) => match resolve_type_def(compiler.clone(), schema, path)? {
+            Some((schema, def)) => record_fields(compiler, schema, &def, fields),
+            None => Ok(false),
+        },
+        _ => Ok(false),
+    }
+}
+
+// Finds the definition of a referenced type, along with the schema it was defined in (which is
+// where any references within it resolve).
+fn resolve_type_def(
+    compiler: Compiler,
+    schema: Ref<Schema>,
+    path: &ast::Path,
+) -> Result<Option<(Ref<Schema>, ast::Type)>> {
+    let (importer, decl, r) = lookup_path::<CRef<MType>>(
+        compiler,
+        Importer::Schema(schema),
+        path,
+        true, /* import_global */
+        true, /* resolve_last */
+    )?;
+    let (schema, decl) = match (importer, decl) {
+        (Importer::Schema(schema), Some(decl)) if r.is_empty() => (schema, decl),
+        _ => return Ok(None),
+    };
+
+    let def = schema.read()?.type_defs.get(decl.name.get()).cloned();
+    Ok(def.map(|def| (schema, def)))
+}
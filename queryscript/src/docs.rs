@@ -0,0 +1,280 @@
+// Generates Markdown documentation for a file and the files it imports: one page per file, listing
+// its exported types, functions, and relations, along with their doc comments.
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+
+use crate::ast::{self, Ident, StructEntry, TypeBody};
+use crate::compile::{
+    builtin_type_name,
+    inference::CRef,
+    schema::{Importer, MField, MType, SchemaPath},
+    CompileError, Compiler, Result,
+};
+use crate::graph::module_name;
+
+/// A generated page, written to `<module>.md`.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub module: String,
+    pub markdown: String,
+}
+
+impl Page {
+    pub fn file_name(&self) -> String {
+        format!("{}.md", self.module)
+    }
+}
+
+/// Compiles a file and documents it and each file it (transitively) imports. The first page is
+/// an index of the others.
+pub fn schema_docs(compiler: &Compiler, path: &Path) -> Result<Vec<Page>> {
+    let schema = match compiler.compile_schema_from_file(path).as_result()? {
+        Some(schema) => schema,
+        None => return Err(CompileError::external("Failed to compile schema")),
+    };
+    let root = schema.read()?.folder.clone();
+
+    let mut pages: Vec<Page> = Vec::new();
+    let mut queue = VecDeque::from([schema]);
+    while let Some(schema) = queue.pop_front() {
+        let schema = schema.read()?;
+        let module = module_name(root.as_deref(), &schema.file);
+        if pages.iter().any(|p| p.module == module) {
+            continue;
+        }
+        let mut md = format!("# {}\n", module);
+
+        let mut imports = Vec::new();
+        for (path, imported) in schema.imports.iter() {
+            match (path, &imported.read()?.schema) {
+                (SchemaPath::Schema(path), Importer::Schema(imported)) => {
+                    let name = path
+                        .iter()
+                        .map(|p| p.get().to_string())
+                        .collect::<Vec<_>>()
+                        .join(".");
+                    let file = module_name(root.as_deref(), &imported.read()?.file);
+                    imports.push(format!("- [`{}`]({}.md)", name, file));
+                    queue.push_back(imported.clone());
+                }
+                (SchemaPath::Connection(url), _) => {
+                    imports.push(format!("- `{}`", url.get()));
+                }
+                _ => {}
+            }
+        }
+        if !imports.is_empty() {
+            md.push_str(&format!("\n## Imports\n\n{}\n", imports.join("\n")));
+        }
+
+        let mut types = Vec::new();
+        for (name, decl) in schema.type_decls.iter() {
+            if !decl.public {
+                continue;
+            }
+            let mut section = heading(name.as_str(), &decl.doc);
+            match known(&decl.value) {
+                Some(MType::Record(fields)) => section.push_str(&fields_table(
+                    fields.get(),
+                    &field_docs(schema.type_defs.get(name)),
+                )),
+                Some(type_) => section.push_str(&format!("`{}`\n", mtype_name(&type_))),
+                None => {}
+            }
+            types.push(section);
+        }
+
+        let mut functions = Vec::new();
+        let mut relations = Vec::new();
+        let mut values = Vec::new();
+        for (name, decl) in schema.expr_decls.iter() {
+            if !decl.public {
+                continue;
+            }
+            let mut section = heading(name.as_str(), &decl.doc);
+            let stype = match decl.value.type_.must() {
+                Ok(stype) => stype.read()?.clone(),
+                Err(_) => {
+                    values.push(section);
+                    continue;
+                }
+            };
+            match known(&stype.body) {
+                MType::Fn(fn_type) => {
+                    let generics = match stype.variables.len() {
+                        0 => String::new(),
+                        _ => format!(
+                            "<{}>",
+                            stype
+                                .variables
+                                .iter()
+                                .map(|v| v.to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    };
+                    let args = fn_type
+                        .get()
+                        .args
+                        .iter()
+                        .map(|a| format!("{} {}", a.name, type_name(&a.type_)))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    section.push_str(&format!(
+                        "```\nfn {}{}({}) -> {}\n```\n",
+                        name,
+                        generics,
+                        args,
+                        type_name(&fn_type.get().ret)
+                    ));
+                    functions.push(section);
+                }
+                Some(type_) => match relation_fields(&type_) {
+                    Some(fields) => {
+                        section.push_str(&fields_table(&fields, &BTreeMap::new()));
+                        relations.push(section);
+                    }
+                    None => {
+                        section.push_str(&format!("`{}`\n", mtype_name(&type_)));
+                        values.push(section);
+                    }
+                },
+                None => values.push(section),
+            }
+        }
+
+        for (title, sections) in [
+            ("Types", types),
+            ("Functions", functions),
+            ("Relations", relations),
+            ("Values", values),
+        ] {
+            if !sections.is_empty() {
+                md.push_str(&format!("\n## {}\n\n{}", title, sections.join("\n")));
+            }
+        }
+
+        pages.push(Page {
+            module,
+            markdown: md,
+        });
+    }
+
+    let index = Page {
+        module: "index".to_string(),
+        markdown: format!(
+            "# Index\n\n{}\n",
+            pages
+                .iter()
+                .map(|p| format!("- [{}]({})", p.module, p.file_name()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    };
+    pages.insert(0, index);
+    Ok(pages)
+}
+
+// Writes a type the way it would be declared, e.g. `{id bigint not null, name text}`.
+fn type_name(type_: &CRef<MType>) -> String {
+    match known(type_) {
+        Some(type_) => mtype_name(&type_),
+        None => "?".to_string(),
+    }
+}
+
+fn mtype_name(type_: &MType) -> String {
+    match type_ {
+        MType::Atom(atom) => match builtin_type_name(atom.get()) {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", atom.get()),
+        },
+        MType::Record(fields) => format!(
+            "{{{}}}",
+            fields
+                .get()
+                .iter()
+                .map(|f| format!(
+                    "{} {}{}",
+                    f.name,
+                    type_name(&f.type_),
+                    if f.nullable { "" } else { " not null" }
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        MType::List(inner) => format!("[{}]", type_name(inner.get())),
+        MType::Fn(fn_type) => format!(
+            "fn({}) -> {}",
+            fn_type
+                .get()
+                .args
+                .iter()
+                .map(|a| format!("{} {}", a.name, type_name(&a.type_)))
+                .collect::<Vec<_>>()
+                .join(", "),
+            type_name(&fn_type.get().ret)
+        ),
+        MType::Name(name) => name.get().to_string(),
+        MType::Generic(generic) => format!("{:?}", generic.get()),
+    }
+}
+
+fn known(type_: &CRef<MType>) -> Option<MType> {
+    Some(type_.must().ok()?.read().ok()?.clone())
+}
+
+fn relation_fields(type_: &MType) -> Option<Vec<MField>> {
+    match type_ {
+        MType::List(inner) => match known(inner.get())? {
+            MType::Record(fields) => Some(fields.get().clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// A declaration's heading, followed by its doc comment (if any).
+fn heading(name: &str, doc: &Option<String>) -> String {
+    let mut ret = format!("### {}\n\n", name);
+    if let Some(doc) = doc {
+        ret.push_str(doc);
+        ret.push_str("\n\n");
+    }
+    ret
+}
+
+// The doc comments on a struct type's fields, keyed by field name.
+fn field_docs(def: Option<&ast::Type>) -> BTreeMap<Ident, String> {
+    let mut ret = BTreeMap::new();
+    if let Some(ast::Type {
+        body: TypeBody::Struct(entries),
+        ..
+    }) = def
+    {
+        for entry in entries.iter() {
+            if let StructEntry::NameAndType(field) = entry {
+                if let Some(doc) = &field.doc {
+                    ret.insert(field.name.get().clone(), doc.clone());
+                }
+            }
+        }
+    }
+    ret
+}
+
+fn fields_table(fields: &[MField], docs: &BTreeMap<Ident, String>) -> String {
+    let escape = |s: String| s.replace('|', "\\|").replace('\n', " ");
+    let mut ret =
+        String::from("| Column | Type | Nullable | Description |\n| --- | --- | --- | --- |\n");
+    for field in fields.iter() {
+        ret.push_str(&format!(
+            "| {} | `{}` | {} | {} |\n",
+            escape(field.name.to_string()),
+            escape(type_name(&field.type_)),
+            if field.nullable { "yes" } else { "no" },
+            escape(docs.get(&field.name).cloned().unwrap_or_default()),
+        ));
+    }
+    ret
+}
@@ -1,4 +1,4 @@
-use crate::parser::error::{ErrorLocation, FormattedError, PrettyError};
+use crate::parser::error::{ErrorLocation, FormattedError, PrettyError, RelatedLocation};
 use snafu::{Backtrace, ErrorCompat, Snafu};
 use std::fmt;
 
@@ -74,7 +74,12 @@ impl QSError {
             _ => {
                 let location = self.location();
                 let text = self.to_string();
-                vec![FormattedError { location, text }]
+                let related = self.related();
+                vec![FormattedError {
+                    location,
+                    text,
+                    related,
+                }]
             }
         }
     }
@@ -85,6 +90,10 @@ impl PrettyError for QSError {
         match self {
             QSError::ParserError { source } => source.location(),
             QSError::CompileError { source } => source.location(),
+            QSError::RuntimeError {
+                source: crate::runtime::error::RuntimeError::AssertionFailed { loc, .. },
+                ..
+            } => loc.clone(),
             QSError::RuntimeError { file, .. } => match file {
                 Some(file) => ErrorLocation::File(file.clone()),
                 None => ErrorLocation::Unknown,
@@ -92,6 +101,13 @@ impl PrettyError for QSError {
             _ => ErrorLocation::Unknown,
         }
     }
+
+    fn related(&self) -> Vec<RelatedLocation> {
+        match self {
+            QSError::CompileError { source } => source.related(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl<Guard> From<std::sync::PoisonError<Guard>> for QSError {
@@ -0,0 +1,498 @@
+// Builds the graph of declarations (lets, functions, types, externs, imports, and materialized
+// views) across a file and the files it imports. Edges come from the symbols that the compiler
+// resolves while compiling, so references through SQL, types, and imports are all included.
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::Path;
+
+use crate::ast::{self, Located, SourceLocation};
+use crate::compile::{
+    inference::CRef,
+    schema::{Importer, SType},
+    CompileError, Compiler, ConnectionString, OnSymbol, Result, SymbolKind,
+};
+use crate::parser::parse_schema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum NodeKind {
+    Let,
+    Fn,
+    Type,
+    Extern,
+    Import,
+    Connection,
+    Materialize,
+}
+
+impl NodeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeKind::Let => "let",
+            NodeKind::Fn => "fn",
+            NodeKind::Type => "type",
+            NodeKind::Extern => "extern",
+            NodeKind::Import => "import",
+            NodeKind::Connection => "connection",
+            NodeKind::Materialize => "materialize",
+        }
+    }
+
+    fn dot_shape(&self) -> &'static str {
+        match self {
+            NodeKind::Let => "box",
+            NodeKind::Fn => "ellipse",
+            NodeKind::Type => "note",
+            NodeKind::Extern => "invhouse",
+            NodeKind::Import => "folder",
+            NodeKind::Connection => "cylinder",
+            NodeKind::Materialize => "box3d",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Node {
+    // The module (the file's path relative to the root file's folder, e.g. `models.orders`)
+    // followed by the declaration's name.
+    pub id: String,
+    pub kind: NodeKind,
+    pub module: String,
+    pub name: String,
+    pub exported: bool,
+}
+
+// An edge from a declaration to one that references it, i.e. `from` is upstream of `to`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+/// The declarations of a file and its transitive imports, and the references between them.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    /// Looks up a node by its id, or else by its name (preferring the root file's declarations).
+    pub fn find(&self, name: &str) -> Option<&Node> {
+        self.nodes
+            .iter()
+            .find(|n| n.id == name)
+            .or_else(|| self.nodes.iter().find(|n| n.name == name))
+    }
+
+    /// Keeps only the exported declarations. Two exported declarations are connected if one
+    /// depends on the other, even if it is through declarations that are not exported.
+    pub fn exports(&self) -> Graph {
+        let exported = self
+            .nodes
+            .iter()
+            .filter(|n| n.exported)
+            .map(|n| n.id.as_str())
+            .collect::<BTreeSet<_>>();
+        let upstream = self.adjacency(|e| (&e.to, &e.from));
+
+        let mut edges = BTreeSet::new();
+        for id in exported.iter() {
+            let mut visited = BTreeSet::new();
+            let mut queue = VecDeque::from([*id]);
+            while let Some(next) = queue.pop_front() {
+                for from in upstream.get(next).into_iter().flatten() {
+                    if !visited.insert(*from) {
+                        continue;
+                    }
+                    if exported.contains(from) {
+                        edges.insert(Edge {
+                            from: from.to_string(),
+                            to: id.to_string(),
+                        });
+                    } else {
+                        queue.push_back(*from);
+                    }
+                }
+            }
+        }
+
+        Graph {
+            nodes: self.nodes.iter().filter(|n| n.exported).cloned().collect(),
+            edges: edges.into_iter().collect(),
+        }
+    }
+
+    /// Keeps the given node and every declaration it depends on.
+    pub fn upstream(&self, id: &str) -> Graph {
+        self.reachable(id, self.adjacency(|e| (&e.to, &e.from)))
+    }
+
+    /// Keeps the given node and every declaration that depends on it.
+    pub fn downstream(&self, id: &str) -> Graph {
+        self.reachable(id, self.adjacency(|e| (&e.from, &e.to)))
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut ret = String::from("digraph declarations {\n    rankdir=LR;\n");
+        for (i, (module, nodes)) in self.modules().iter().enumerate() {
+            ret.push_str(&format!(
+                "    subgraph cluster_{} {{\n        label={:?};\n",
+                i, module
+            ));
+            for node in nodes {
+                ret.push_str(&format!(
+                    "        {:?} [label={:?}, shape={}{}];\n",
+                    node.id,
+                    node.name,
+                    node.kind.dot_shape(),
+                    if node.exported { ", style=bold" } else { "" },
+                ));
+            }
+            ret.push_str("    }\n");
+        }
+        for edge in self.edges.iter() {
+            ret.push_str(&format!("    {:?} -> {:?};\n", edge.from, edge.to));
+        }
+        ret.push_str("}\n");
+        ret
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        // Mermaid ids can't contain most punctuation, so nodes are numbered and labeled instead.
+        let ids = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.id.as_str(), format!("n{}", i)))
+            .collect::<BTreeMap<_, _>>();
+        let escape = |s: &str| s.replace('"', "#quot;");
+
+        let mut ret = String::from("flowchart LR\n");
+        for (i, (module, nodes)) in self.modules().iter().enumerate() {
+            ret.push_str(&format!("    subgraph m{} [\"{}\"]\n", i, escape(module)));
+            for node in nodes {
+                let label = escape(&format!("{} {}", node.kind.as_str(), node.name));
+                ret.push_str(&format!(
+                    "        {}{}\n",
+                    ids[node.id.as_str()],
+                    match node.kind {
+                        NodeKind::Fn => format!("([\"{}\"])", label),
+                        NodeKind::Type => format!("{{{{\"{}\"}}}}", label),
+                        NodeKind::Import | NodeKind::Connection => format!("[(\"{}\")]", label),
+                        _ => format!("[\"{}\"]", label),
+                    }
+                ));
+            }
+            ret.push_str("    end\n");
+        }
+        for edge in self.edges.iter() {
+            ret.push_str(&format!(
+                "    {} --> {}\n",
+                ids[edge.from.as_str()],
+                ids[edge.to.as_str()]
+            ));
+        }
+        ret
+    }
+
+    // The nodes of each module, in the order the modules first appear.
+    fn modules(&self) -> Vec<(&str, Vec<&Node>)> {
+        let mut ret: Vec<(&str, Vec<&Node>)> = Vec::new();
+        for node in self.nodes.iter() {
+            match ret.iter_mut().find(|(m, _)| *m == node.module) {
+                Some((_, nodes)) => nodes.push(node),
+                None => ret.push((node.module.as_str(), vec![node])),
+            }
+        }
+        ret
+    }
+
+    fn adjacency<'a>(
+        &'a self,
+        direction: impl Fn(&'a Edge) -> (&'a String, &'a String),
+    ) -> BTreeMap<&'a str, Vec<&'a str>> {
+        let mut ret = BTreeMap::<&str, Vec<&str>>::new();
+        for edge in self.edges.iter() {
+            let (a, b) = direction(edge);
+            ret.entry(a.as_str()).or_default().push(b.as_str());
+        }
+        ret
+    }
+
+    fn reachable(&self, id: &str, adjacency: BTreeMap<&str, Vec<&str>>) -> Graph {
+        let mut visited = BTreeSet::from([id]);
+        let mut queue = VecDeque::from([id]);
+        while let Some(next) = queue.pop_front() {
+            for other in adjacency.get(next).into_iter().flatten() {
+                if visited.insert(*other) {
+                    queue.push_back(*other);
+                }
+            }
+        }
+
+        Graph {
+            nodes: self
+                .nodes
+                .iter()
+                .filter(|n| visited.contains(n.id.as_str()))
+                .cloned()
+                .collect(),
+            edges: self
+                .edges
+                .iter()
+                .filter(|e| visited.contains(e.from.as_str()) && visited.contains(e.to.as_str()))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Compiles a file and returns the graph of its declarations and those of the files it imports.
+pub fn declaration_graph(compiler: &Compiler, path: &Path) -> Result<Graph> {
+    let orig_on_symbol = compiler.on_symbol(Some(Box::new(ReferenceRecorder::default())))?;
+    let result = compiler.compile_schema_from_file(path);
+    let mut recorder = compiler.on_symbol(orig_on_symbol)?.unwrap();
+    let references = std::mem::take(
+        &mut recorder
+            .as_any_mut()
+            .downcast_mut::<ReferenceRecorder>()
+            .unwrap()
+            .references,
+    );
+    let schema = match result.as_result()? {
+        Some(schema) => schema,
+        None => return Err(CompileError::external("Failed to compile schema")),
+    };
+
+    // Re-parse each (transitively) imported file, so that references can be attributed to the
+    // statement they appear in.
+    let root = schema.read()?.folder.clone();
+    let mut modules = BTreeMap::new();
+    let mut files = Vec::new();
+    let mut queue = VecDeque::from([schema]);
+    while let Some(schema) = queue.pop_front() {
+        let schema = schema.read()?;
+        if modules.contains_key(&schema.file) {
+            continue;
+        }
+        for imported in schema.imports.values() {
+            if let Importer::Schema(imported) = &imported.read()?.schema {
+                queue.push_back(imported.clone());
+            }
+        }
+
+        let text = match compiler.file_contents()?.files.get(&schema.file) {
+            Some(text) => text.clone(),
+            None => continue,
+        };
+        let module = Module {
+            name: module_name(root.as_deref(), &schema.file),
+            folder: schema.folder.clone(),
+            ast: parse_schema(&schema.file, &text).result,
+        };
+        modules.insert(schema.file.clone(), module);
+        files.push(schema.file.clone());
+    }
+
+    let mut nodes = Vec::new();
+    let mut edges = BTreeSet::new();
+    for file in files.iter() {
+        let module = &modules[file];
+        for stmt in module.ast.stmts.iter() {
+            let node = match module.node(stmt) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            // Items imported by name depend on the declarations they import.
+            if let ast::StmtBody::Import {
+                path,
+                list: ast::ImportList::Items(items),
+                ..
+            } = &stmt.body
+            {
+                if let Some(imported) = module.import_file(path).and_then(|f| modules.get(&f)) {
+                    for item in items.iter() {
+                        if let Some(item) = item.last() {
+                            edges.insert(Edge {
+                                from: format!("{}.{}", imported.name, item.as_str()),
+                                to: node.id.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+            nodes.push(node);
+        }
+    }
+
+    for reference in references.iter() {
+        let to = match resolve(&modules, reference.name.location()) {
+            Some(to) => to,
+            None => continue,
+        };
+        let from = match &reference.kind {
+            // A reference to an imported file (e.g. `raw` in `raw.orders`) goes through the
+            // referencing file's import.
+            SymbolKind::File => reference.name.location().file().and_then(|file| {
+                let module = modules.get(&file)?;
+                module.ast.stmts.iter().find_map(|stmt| match &stmt.body {
+                    ast::StmtBody::Import { path, .. }
+                        if path.last().map(|p| p.get()) == Some(reference.name.get()) =>
+                    {
+                        module.node(stmt).map(|n| n.id)
+                    }
+                    _ => None,
+                })
+            }),
+            _ => resolve(&modules, &reference.def),
+        };
+        match from {
+            Some(from) if from != to => {
+                edges.insert(Edge { from, to });
+            }
+            _ => {}
+        }
+    }
+
+    // Drop references to declarations outside of these files (e.g. builtins).
+    let ids = nodes.iter().map(|n| n.id.clone()).collect::<BTreeSet<_>>();
+    Ok(Graph {
+        nodes,
+        edges: edges
+            .into_iter()
+            .filter(|e| ids.contains(&e.from) && ids.contains(&e.to))
+            .collect(),
+    })
+}
+
+struct Module {
+    name: String,
+    folder: Option<String>,
+    ast: ast::Schema,
+}
+
+impl Module {
+    fn node(&self, stmt: &ast::Stmt) -> Option<Node> {
+        let (name, kind) = match &stmt.body {
+            ast::StmtBody::Let {
+                name, materialize, ..
+            } => (
+                name.get().to_string(),
+                match materialize {
+                    Some(_) => NodeKind::Materialize,
+                    None => NodeKind::Let,
+                },
+            ),
+            ast::StmtBody::FnDef(def) => (def.name.get().to_string(), NodeKind::Fn),
+            ast::StmtBody::TypeDef { name, .. } => (name.get().to_string(), NodeKind::Type),
+            ast::StmtBody::Extern { name, .. } => (name.get().to_string(), NodeKind::Extern),
+            ast::StmtBody::Import { path, .. } => {
+                let is_connection = match path.first() {
+                    Some(first) => matches!(
+                        ConnectionString::maybe_parse(
+                            self.folder.clone(),
+                            first.as_str(),
+                            first.location()
+                        ),
+                        Ok(Some(_))
+                    ),
+                    None => false,
+                };
+                (
+                    path.iter()
+                        .map(|p| p.get().to_string())
+                        .collect::<Vec<_>>()
+                        .join("."),
+                    if is_connection {
+                        NodeKind::Connection
+                    } else {
+                        NodeKind::Import
+                    },
+                )
+            }
+            _ => return None,
+        };
+
+        Some(Node {
+            id: format!("{}.{}", self.name, name),
+            kind,
+            module: self.name.clone(),
+            name,
+            exported: stmt.export,
+        })
+    }
+
+    // The file that an import statement refers to (see lookup_schema).
+    fn import_file(&self, path: &ast::Path) -> Option<String> {
+        let mut file = Path::new(self.folder.as_ref()?).to_path_buf();
+        for p in path.iter() {
+            file.push(p.as_str());
+        }
+        file.set_extension("qs");
+        Some(file.to_string_lossy().to_string())
+    }
+}
+
+// The module name of a file, which is its path relative to the root file's folder, without the
+// extension, and separated by `.` (as it would be imported).
+pub(crate) fn module_name(root: Option<&str>, file: &str) -> String {
+    let path = Path::new(file);
+    let path = match root {
+        Some(root) => path.strip_prefix(root).unwrap_or(path),
+        None => path,
+    };
+    path.with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// Finds the declaration whose statement contains the given location.
+fn resolve(modules: &BTreeMap<String, Module>, loc: &SourceLocation) -> Option<String> {
+    let (file, start) = match loc {
+        SourceLocation::Single(file, l) => (file, l),
+        SourceLocation::Range(file, r) => (file, &r.start),
+        SourceLocation::Unknown | SourceLocation::File(_) => return None,
+    };
+    let module = modules.get(file)?;
+    let stmt = module.ast.find_stmt(start.clone())?;
+    module.node(&stmt).map(|n| n.id)
+}
+
+struct Reference {
+    name: Located<ast::Ident>,
+    kind: SymbolKind,
+    def: SourceLocation,
+}
+
+#[derive(Default)]
+struct ReferenceRecorder {
+    references: Vec<Reference>,
+}
+
+impl OnSymbol for ReferenceRecorder {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+    fn on_symbol(
+        &mut self,
+        name: Located<ast::Ident>,
+        kind: SymbolKind,
+        _type_: CRef<SType>,
+        def: SourceLocation,
+        _is_public: bool,
+        _doc: Option<String>,
+    ) -> Result<()> {
+        self.references.push(Reference { name, kind, def });
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
 pub mod ast;
 pub mod compile;
+pub mod docs;
 pub mod error;
+pub mod graph;
+pub mod lineage;
 pub mod materialize;
 pub mod parser;
 pub mod runtime;
+pub mod testing;
 pub mod types;
 
 pub use error::QSError;
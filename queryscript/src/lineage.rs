@@ -0,0 +1,544 @@
+// Traces each column of a schema's exported relations back to the columns of the files (read with
+// `load()`) and database tables that feed it. This works on the compiled SQL after inlining, so
+// references through function calls and imports are followed like any other reference.
+use sqlparser::ast as sqlast;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::compile::{
+    schema::{Expr, FnCallExpr, Ident, MaterializeExpr, Params, Ref, SQLBody, Schema, TypedExpr},
+    traverse::{SQLVisitor, VisitSQL},
+    ConnectionString,
+};
+use crate::types::Type;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum Source {
+    // A file read with `load()`.
+    File { path: String },
+    // A table in a database connection.
+    Table { connection: String, table: String },
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File { path } => write!(f, "{}", path),
+            Source::Table { connection, table } => write!(f, "{}/{}", connection, table),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SourceColumn {
+    pub source: Source,
+    // The column's name, or "*" if the expression depends on the whole relation (e.g. `COUNT(*)`
+    // over a table whose columns are not known).
+    pub column: String,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ColumnLineage {
+    pub name: String,
+    pub sources: Vec<SourceColumn>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RelationLineage {
+    pub name: String,
+    pub columns: Vec<ColumnLineage>,
+}
+
+/// The upstream columns of each column of a schema's exported relations.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Lineage {
+    pub relations: Vec<RelationLineage>,
+}
+
+impl Lineage {
+    pub fn to_dot(&self) -> String {
+        let sources = self
+            .relations
+            .iter()
+            .flat_map(|r| r.columns.iter().flat_map(|c| c.sources.iter()))
+            .fold(BTreeMap::<String, BTreeSet<&str>>::new(), |mut acc, s| {
+                acc.entry(s.source.to_string())
+                    .or_default()
+                    .insert(s.column.as_str());
+                acc
+            });
+
+        let mut ret = String::from("digraph lineage {\n    rankdir=LR;\n");
+        let cluster = |ret: &mut String, name: &str, columns: Vec<&str>| {
+            ret.push_str(&format!(
+                "    subgraph {:?} {{\n        label={:?};\n",
+                format!("cluster_{}", name),
+                name
+            ));
+            for column in columns {
+                ret.push_str(&format!(
+                    "        {:?} [label={:?}];\n",
+                    format!("{}.{}", name, column),
+                    column
+                ));
+            }
+            ret.push_str("    }\n");
+        };
+        for (source, columns) in sources.iter() {
+            cluster(&mut ret, source, columns.iter().cloned().collect());
+        }
+        for relation in self.relations.iter() {
+            cluster(
+                &mut ret,
+                &relation.name,
+                relation.columns.iter().map(|c| c.name.as_str()).collect(),
+            );
+        }
+
+        for relation in self.relations.iter() {
+            for column in relation.columns.iter() {
+                for source in column.sources.iter() {
+                    ret.push_str(&format!(
+                        "    {:?} -> {:?};\n",
+                        format!("{}.{}", source.source, source.column),
+                        format!("{}.{}", relation.name, column.name)
+                    ));
+                }
+            }
+        }
+        ret.push_str("}\n");
+        ret
+    }
+}
+
+/// Computes the lineage of each column of the schema's exported relations.
+pub fn schema_lineage(schema: &Schema) -> Lineage {
+    let mut relations = Vec::new();
+    for (name, decl) in schema.expr_decls.iter() {
+        if !decl.public {
+            continue;
+        }
+
+        // Functions and scalar values have no columns to trace.
+        let expr = match decl.value.to_runtime_type() {
+            Ok(expr) if relation_fields(&expr.type_).is_some() => expr,
+            _ => continue,
+        };
+
+        let relation = expr_relation(&expr);
+        let fields = relation_fields(&expr.type_).unwrap_or_default();
+        let columns = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                // Columns are matched by name, but if the query's names are not known (e.g. for
+                // an expression without an alias), fall back to its position.
+                let sources = relation
+                    .lookup(field)
+                    .or_else(|| relation.columns.get(i).map(|(_, s)| s.clone()))
+                    .unwrap_or_default();
+                ColumnLineage {
+                    name: field.to_string(),
+                    sources: sources.into_iter().collect(),
+                }
+            })
+            .collect();
+        relations.push(RelationLineage {
+            name: name.to_string(),
+            columns,
+        });
+    }
+    Lineage { relations }
+}
+
+fn relation_fields(type_: &Ref<Type>) -> Option<Vec<Ident>> {
+    match &*type_.read().ok()? {
+        Type::List(inner) => match inner.as_ref() {
+            Type::Record(fields) => Some(fields.iter().map(|f| f.name.clone()).collect()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+type Sources = BTreeSet<SourceColumn>;
+
+// The lineage of each column of a relation. A relation may also pass through every column of a
+// table whose columns are not known (e.g. `SELECT * FROM users` against a database), in which case
+// any other column name is traced to that table.
+#[derive(Debug, Clone, Default)]
+struct Relation {
+    columns: Vec<(Ident, Sources)>,
+    open: Vec<Source>,
+}
+
+impl Relation {
+    fn open(source: Source) -> Relation {
+        Relation {
+            columns: Vec::new(),
+            open: vec![source],
+        }
+    }
+
+    fn lookup(&self, column: &Ident) -> Option<Sources> {
+        if let Some((_, sources)) = self.columns.iter().find(|(name, _)| name == column) {
+            return Some(sources.clone());
+        }
+        if self.open.is_empty() {
+            return None;
+        }
+        Some(
+            self.open
+                .iter()
+                .map(|source| SourceColumn {
+                    source: source.clone(),
+                    column: column.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    // Everything the relation depends on, e.g. for a scalar subquery.
+    fn all_sources(&self) -> Sources {
+        let mut sources = self
+            .columns
+            .iter()
+            .flat_map(|(_, sources)| sources.iter().cloned())
+            .collect::<Sources>();
+        sources.extend(self.open.iter().map(|source| SourceColumn {
+            source: source.clone(),
+            column: "*".to_string(),
+        }));
+        sources
+    }
+
+    fn extend(&mut self, other: Relation) {
+        self.columns.extend(other.columns);
+        self.open.extend(other.open);
+    }
+
+    // Applies a table alias's column list (e.g. `AS t(a, b)`), which renames columns by position.
+    fn rename(mut self, alias: &Option<sqlast::TableAlias>) -> Relation {
+        if let Some(alias) = alias {
+            for ((name, _), column) in self.columns.iter_mut().zip(alias.columns.iter()) {
+                *name = column.get().into();
+            }
+        }
+        self
+    }
+}
+
+fn expr_relation(expr: &TypedExpr<Ref<Type>>) -> Relation {
+    match expr.expr.as_ref() {
+        Expr::SQL(sql, url) => {
+            let scope = Scope {
+                params: &sql.names.params,
+                url: url.as_ref(),
+                ctes: BTreeMap::new(),
+            };
+            match &sql.body {
+                SQLBody::Query(query) => scope.query(query),
+                SQLBody::Table(table) => scope.table(table).1,
+                SQLBody::Expr(_) => Relation::default(),
+            }
+        }
+        Expr::FnCall(call) => match load_path(call) {
+            Some(path) => {
+                let source = Source::File { path };
+                match relation_fields(&expr.type_) {
+                    Some(fields) => Relation {
+                        columns: fields
+                            .into_iter()
+                            .map(|field| {
+                                let column = SourceColumn {
+                                    source: source.clone(),
+                                    column: field.to_string(),
+                                };
+                                (field, Sources::from([column]))
+                            })
+                            .collect(),
+                        open: Vec::new(),
+                    },
+                    None => Relation::open(source),
+                }
+            }
+            None => Relation::default(),
+        },
+        Expr::Materialize(MaterializeExpr { expr, .. }) => expr_relation(expr),
+        _ => Relation::default(),
+    }
+}
+
+// The file a `load()` call reads, relative to the folder of the schema that calls it.
+fn load_path(call: &FnCallExpr<Ref<Type>>) -> Option<String> {
+    match call.func.expr.as_ref() {
+        Expr::NativeFn(name, None) if name.as_str() == "load" => {}
+        _ => return None,
+    }
+    let path = match call.args.first()?.expr.as_ref() {
+        Expr::SQL(sql, _) => match &sql.body {
+            SQLBody::Expr(sqlast::Expr::Value(sqlast::Value::SingleQuotedString(path))) => {
+                path.clone()
+            }
+            body => body.as_expr().to_string(),
+        },
+        _ => "?".to_string(),
+    };
+    Some(match &call.ctx_folder {
+        Some(folder) if Path::new(&path).is_relative() => {
+            Path::new(folder).join(&path).to_string_lossy().to_string()
+        }
+        _ => path,
+    })
+}
+
+struct Scope<'a> {
+    params: &'a Params<Ref<Type>>,
+    url: Option<&'a Arc<ConnectionString>>,
+    ctes: BTreeMap<Ident, Relation>,
+}
+
+impl<'a> Scope<'a> {
+    fn query(&self, query: &sqlast::Query) -> Relation {
+        match &query.with {
+            Some(with) => {
+                let mut scope = Scope {
+                    params: self.params,
+                    url: self.url,
+                    ctes: self.ctes.clone(),
+                };
+                for cte in with.cte_tables.iter() {
+                    let relation = scope.query(&cte.query).rename(&Some(cte.alias.clone()));
+                    scope.ctes.insert(cte.alias.name.get().into(), relation);
+                }
+                scope.set_expr(&query.body)
+            }
+            None => self.set_expr(&query.body),
+        }
+    }
+
+    fn set_expr(&self, body: &sqlast::SetExpr) -> Relation {
+        match body {
+            sqlast::SetExpr::Select(select) => self.select(select),
+            sqlast::SetExpr::Query(query) => self.query(query),
+            sqlast::SetExpr::SetOperation { left, right, .. } => {
+                // The columns of a set operation are named after the left side, and each one
+                // depends on the columns in the same position on either side.
+                let mut left = self.set_expr(left);
+                let right = self.set_expr(right);
+                for ((_, sources), (_, other)) in left.columns.iter_mut().zip(right.columns) {
+                    sources.extend(other);
+                }
+                left.open.extend(right.open);
+                left
+            }
+            sqlast::SetExpr::Values(values) => Relation {
+                columns: (0..values.rows.first().map_or(0, |row| row.len()))
+                    .map(|i| (format!("col{}", i).into(), Sources::new()))
+                    .collect(),
+                open: Vec::new(),
+            },
+            _ => Relation::default(),
+        }
+    }
+
+    fn select(&self, select: &sqlast::Select) -> Relation {
+        let mut from = Vec::new();
+        for table in select.from.iter() {
+            from.push(self.table(&table.relation));
+            for join in table.joins.iter() {
+                from.push(self.table(&join.relation));
+            }
+        }
+
+        let mut ret = Relation::default();
+        for item in select.projection.iter() {
+            match item {
+                sqlast::SelectItem::UnnamedExpr(expr) => {
+                    let name = match expr {
+                        sqlast::Expr::Identifier(ident) => ident.get().into(),
+                        sqlast::Expr::CompoundIdentifier(path) => path.last().unwrap().get().into(),
+                        _ => expr.to_string().into(),
+                    };
+                    ret.columns.push((name, self.expr_sources(expr, &from)));
+                }
+                sqlast::SelectItem::ExprWithAlias { expr, alias } => {
+                    ret.columns
+                        .push((alias.get().into(), self.expr_sources(expr, &from)));
+                }
+                sqlast::SelectItem::Wildcard(_) => {
+                    for (_, relation) in from.iter() {
+                        ret.extend(relation.clone());
+                    }
+                }
+                sqlast::SelectItem::QualifiedWildcard(name, _) => {
+                    let name: Ident = name.0.last().unwrap().get().into();
+                    for (alias, relation) in from.iter() {
+                        if alias.as_ref() == Some(&name) {
+                            ret.extend(relation.clone());
+                        }
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    // Returns the name that the table can be referred to by, along with its columns.
+    fn table(&self, table: &sqlast::TableFactor) -> (Option<Ident>, Relation) {
+        match table {
+            sqlast::TableFactor::Table { name, alias, .. } => {
+                let table_name: Ident = name.0.last().unwrap().get().into();
+                let relation = if name.0.len() == 1 {
+                    self.named_relation(&table_name)
+                } else {
+                    None
+                };
+                let relation = relation.unwrap_or_else(|| match self.url {
+                    Some(url) => Relation::open(Source::Table {
+                        connection: url.to_string(),
+                        table: name.to_string(),
+                    }),
+                    None => Relation::default(),
+                });
+                (
+                    Some(
+                        alias
+                            .as_ref()
+                            .map_or(table_name, |alias| alias.name.get().into()),
+                    ),
+                    relation.rename(alias),
+                )
+            }
+            sqlast::TableFactor::Derived {
+                subquery, alias, ..
+            } => (
+                alias.as_ref().map(|alias| alias.name.get().into()),
+                self.query(subquery).rename(alias),
+            ),
+            sqlast::TableFactor::NestedJoin {
+                table_with_joins,
+                alias,
+            } => {
+                let mut relation = self.table(&table_with_joins.relation).1;
+                for join in table_with_joins.joins.iter() {
+                    relation.extend(self.table(&join.relation).1);
+                }
+                (
+                    alias.as_ref().map(|alias| alias.name.get().into()),
+                    relation.rename(alias),
+                )
+            }
+            sqlast::TableFactor::TableFunction { alias, .. }
+            | sqlast::TableFactor::UNNEST { alias, .. } => (
+                alias.as_ref().map(|alias| alias.name.get().into()),
+                Relation::default(),
+            ),
+        }
+    }
+
+    // Looks up a CTE or a parameter (i.e. a reference to another expression that was not inlined).
+    // Materialized expressions are inlined as a reference to their saved table, but can still be
+    // traced through their parameter.
+    fn named_relation(&self, name: &Ident) -> Option<Relation> {
+        if let Some(relation) = self.ctes.get(name) {
+            return Some(relation.clone());
+        }
+        if let Some(param) = self.params.get(name) {
+            return Some(expr_relation(param));
+        }
+        self.params
+            .values()
+            .find_map(|param| match param.expr.as_ref() {
+                Expr::Materialize(MaterializeExpr {
+                    decl_name, expr, ..
+                }) if decl_name == name => Some(expr_relation(expr)),
+                _ => None,
+            })
+    }
+
+    fn expr_sources(&self, expr: &sqlast::Expr, from: &Vec<(Option<Ident>, Relation)>) -> Sources {
+        let references = References::default();
+        let _ = expr.visit_sql(&references);
+
+        let mut sources = Sources::new();
+        for path in references.paths.into_inner() {
+            let (qualifier, column) = match path.as_slice() {
+                [column] => (None, column),
+                [.., qualifier, column] => (Some(qualifier), column),
+                [] => continue,
+            };
+
+            let found = from
+                .iter()
+                .filter(|(alias, _)| qualifier.is_none() || alias.as_ref() == qualifier)
+                .find_map(|(_, relation)| {
+                    relation
+                        .columns
+                        .iter()
+                        .find(|(name, _)| name == column)
+                        .map(|(_, sources)| sources.clone())
+                });
+            let found = found.or_else(|| {
+                from.iter()
+                    .filter(|(alias, _)| qualifier.is_none() || alias.as_ref() == qualifier)
+                    .find(|(_, relation)| !relation.open.is_empty())
+                    .and_then(|(_, relation)| relation.lookup(column))
+            });
+            let found = found.or_else(|| match qualifier {
+                None => self.named_relation(column).map(|r| r.all_sources()),
+                Some(_) => None,
+            });
+            sources.extend(found.unwrap_or_default());
+        }
+        for query in references.queries.into_inner() {
+            sources.extend(self.query(&query).all_sources());
+        }
+        sources
+    }
+}
+
+// Collects the column references (and subqueries) within an expression.
+#[derive(Default)]
+struct References {
+    paths: RefCell<Vec<Vec<Ident>>>,
+    queries: RefCell<Vec<sqlast::Query>>,
+}
+
+impl SQLVisitor for References {
+    fn visit_sqlexpr(&self, expr: &sqlast::Expr) -> Option<sqlast::Expr> {
+        match expr {
+            // Skip the function's name, which is not a column.
+            sqlast::Expr::Function(f) => {
+                let _ = f.args.visit_sql(self);
+                let _ = f.over.visit_sql(self);
+                Some(expr.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn visit_sqlquery(&self, query: &sqlast::Query) -> Option<sqlast::Query> {
+        self.queries.borrow_mut().push(query.clone());
+        Some(query.clone())
+    }
+
+    fn visit_sqlpath(
+        &self,
+        path: &Vec<sqlast::Located<sqlast::Ident>>,
+    ) -> Option<Vec<sqlast::Located<sqlast::Ident>>> {
+        self.paths
+            .borrow_mut()
+            .push(path.iter().map(|p| p.get().into()).collect());
+        Some(path.clone())
+    }
+}
@@ -0,0 +1,141 @@
+// Materialized expressions can be saved to files (e.g. `mat(file => 'out/users.parquet')`) rather
+// than a database. They are evaluated locally and written with their declared type as the schema,
+// so that they can be read back with `load()` under the same type.
+use snafu::prelude::*;
+use std::path::PathBuf;
+
+use crate::ast::SourceLocation;
+use crate::compile::schema::{
+    Decl, Expr, FileTarget, Ident, MaterializeExpr, MaterializeOptions, Ref, STypedExpr, Schema,
+    TypedExpr,
+};
+use crate::compile::{error::RuntimeSnafu, Result};
+use crate::runtime::{
+    self,
+    error::fail,
+    files::{relation_schema, write_relation},
+    Context,
+};
+use crate::types::{Type, Value};
+
+use super::{PlanAction, PlanStep};
+
+pub(super) struct FileStep {
+    pub name: Ident,
+    pub loc: SourceLocation,
+    expr: TypedExpr<Ref<Type>>,
+    type_: Type,
+    target: FileTarget,
+    partition_by: Vec<String>,
+    order_by: Vec<String>,
+}
+
+impl FileStep {
+    pub fn plan_step(&self) -> PlanStep {
+        let mut ddl = format!(
+            "-- write {} {} {}",
+            self.target.format.extension(),
+            if self.target.directory {
+                "files to"
+            } else {
+                "file"
+            },
+            self.target.path
+        );
+        if !self.partition_by.is_empty() {
+            ddl.push_str(&format!(
+                ", partitioned by {}",
+                self.partition_by.join(", ")
+            ));
+        }
+        if !self.order_by.is_empty() {
+            ddl.push_str(&format!(", ordered by {}", self.order_by.join(", ")));
+        }
+
+        PlanStep {
+            name: self.name.to_string(),
+            target: self.target.path.clone(),
+            action: PlanAction::WriteFile,
+            dependencies: Vec::new(),
+            ddl,
+            incremental_ddl: None,
+            comment: None,
+        }
+    }
+}
+
+fn plan_file(name: &Ident, decl: &Decl<STypedExpr>) -> runtime::Result<Option<FileStep>> {
+    let expr = decl.value.expr.must()?;
+    let expr = expr.read()?.to_runtime_type()?;
+    Ok(match expr {
+        Expr::Materialize(MaterializeExpr {
+            expr,
+            options:
+                MaterializeOptions {
+                    file: Some(target),
+                    partition_by,
+                    order_by,
+                    ..
+                },
+            ..
+        }) => {
+            let type_ = expr.type_.read()?.clone();
+            let names = |columns: Vec<Ident>| -> Vec<String> {
+                columns.iter().map(|c| c.to_string()).collect()
+            };
+            Some(FileStep {
+                name: name.clone(),
+                loc: decl.location().clone(),
+                expr,
+                type_,
+                target,
+                partition_by: names(partition_by),
+                order_by: names(order_by),
+            })
+        }
+        _ => None,
+    })
+}
+
+pub(super) fn plan_files(schema: &Schema) -> Result<Vec<FileStep>> {
+    let mut steps = Vec::new();
+    for (name, decl) in schema.expr_decls.iter() {
+        if !decl.public {
+            continue;
+        }
+        if let Some(step) = plan_file(name, decl).context(RuntimeSnafu {
+            loc: decl.location().clone(),
+        })? {
+            steps.push(step);
+        }
+    }
+    Ok(steps)
+}
+
+pub(super) async fn write_file(ctx: &mut Context, step: &FileStep) -> runtime::Result<()> {
+    let relation = match runtime::eval(ctx, &step.expr).await? {
+        Value::Relation(relation) => relation,
+        _ => return fail!("Expected \"{}\" to evaluate to a relation", step.name),
+    };
+    let schema = relation_schema(&step.type_)?;
+
+    // Like load(), paths are relative to the schema's folder.
+    let mut path = PathBuf::new();
+    if let Some(folder) = &ctx.folder {
+        path.push(folder);
+    }
+    path.push(&step.target.path);
+
+    eprintln!("Writing \"{}\" to {}", step.name, path.display());
+    runtime::expensive(|| {
+        write_relation(
+            &path,
+            step.target.format,
+            step.target.directory,
+            schema,
+            relation.as_ref(),
+            &step.partition_by,
+            &step.order_by,
+        )
+    })
+}
@@ -0,0 +1,112 @@
+// Incremental materializations update an existing table in place, rather than rebuilding it. The
+// new rows are first staged into a temporary table (either by running the materialized query, or
+// by loading locally computed data), and then merged into the target table.
+use sqlparser::ast as sqlast;
+
+use crate::compile::schema::{Ident, IncrementalOptions};
+use crate::compile::sql::create_table_as;
+use crate::runtime::{error::fail, Result};
+
+use super::metadata::{parse_statement, quote_ident};
+use super::options::order_by_clause;
+
+pub struct IncrementalUpdate {
+    // The temporary table that the new rows should be staged into.
+    pub staging_table: Ident,
+    // Selects the rows of `__qs_new` that are newer than the target table's watermark, if the
+    // materialization has one.
+    watermark_query: Option<sqlast::Query>,
+    // Loaded rows are computed locally, so they can only be filtered by the watermark once they
+    // are staged.
+    pub load_filter: Option<sqlast::Statement>,
+    // The statements that merge the staged rows into the target table.
+    pub statements: Vec<sqlast::Statement>,
+}
+
+impl IncrementalUpdate {
+    // Stages the rows of the materialized query. The watermark filter is pushed into the query, so
+    // that only the new rows are computed and copied, rather than the whole query.
+    pub fn stage_query(&self, query: sqlast::Query) -> sqlast::Statement {
+        let query = match &self.watermark_query {
+            Some(watermark_query) => {
+                let mut filtered = watermark_query.clone();
+                if let sqlast::SetExpr::Select(select) = filtered.body.as_mut() {
+                    select.from[0].relation = sqlast::TableFactor::Derived {
+                        lateral: false,
+                        subquery: Box::new(query),
+                        alias: Some(sqlast::TableAlias {
+                            name: sqlast::Ident::new("__qs_new"),
+                            columns: Vec::new(),
+                        }),
+                    };
+                }
+                filtered
+            }
+            None => query,
+        };
+        create_table_as((&self.staging_table).into(), query, true)
+    }
+}
+
+// The new rows are inserted in the order given by `order_by`, although (unlike a full rebuild)
+// they are appended after the existing ones.
+pub fn incremental_update(
+    table: &Ident,
+    options: &IncrementalOptions,
+    order_by: &[Ident],
+) -> Result<IncrementalUpdate> {
+    let staging_table: Ident = format!("__qs_incremental_{}", table).into();
+    let staging = quote_ident(staging_table.as_str());
+    let target = quote_ident(table.as_str());
+
+    // The rows are staged before any existing rows are replaced, so the watermark is always
+    // computed from the rows that were in the table before this update. If the table is empty,
+    // every row is new.
+    let (watermark_query, load_filter) = match &options.watermark {
+        Some(watermark) => {
+            let watermark = quote_ident(watermark.as_str());
+            let max = format!("(SELECT MAX({watermark}) FROM {target})");
+            let watermark_query = match parse_statement(&format!(
+                "SELECT * FROM __qs_new WHERE {max} IS NULL OR {watermark} > {max}"
+            ))? {
+                sqlast::Statement::Query(query) => *query,
+                stmt => return fail!("Expected a query: {}", stmt),
+            };
+            let load_filter =
+                parse_statement(&format!("DELETE FROM {staging} WHERE {watermark} <= {max}"))?;
+            (Some(watermark_query), Some(load_filter))
+        }
+        None => (None, None),
+    };
+
+    let mut sql = Vec::new();
+    if !options.unique_key.is_empty() {
+        let matches = options
+            .unique_key
+            .iter()
+            .map(|column| {
+                let column = quote_ident(column.as_str());
+                format!("{staging}.{column} = {target}.{column}")
+            })
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        sql.push(format!(
+            "DELETE FROM {target} WHERE EXISTS (SELECT 1 FROM {staging} WHERE {matches})"
+        ));
+    }
+    sql.push(format!(
+        "INSERT INTO {target} SELECT * FROM {staging}{}",
+        order_by_clause(order_by)
+    ));
+    sql.push(format!("DROP TABLE {staging}"));
+
+    Ok(IncrementalUpdate {
+        staging_table,
+        watermark_query,
+        load_filter,
+        statements: sql
+            .iter()
+            .map(|s| parse_statement(s))
+            .collect::<Result<_>>()?,
+    })
+}
@@ -0,0 +1,218 @@
+// Each target database has a metadata table that records the objects `save_views` created in it,
+// along with a hash of the DDL used to create them. This lets us skip objects that have not
+// changed, and clean up objects that are no longer exported. It also holds each object's comment,
+// since not every database supports COMMENT ON.
+use sqlparser::{ast as sqlast, dialect::GenericDialect, parser::Parser};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::ast::Ident;
+use crate::runtime::{error::fail, Result, SQLEngine};
+use crate::types::{Relation, Value};
+
+pub const METADATA_TABLE: &str = "__qs_objects";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    View,
+    Table,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::View => "view",
+            ObjectKind::Table => "table",
+        }
+    }
+
+    fn parse(kind: &str) -> Result<ObjectKind> {
+        match kind {
+            "view" => Ok(ObjectKind::View),
+            "table" => Ok(ObjectKind::Table),
+            _ => fail!("Invalid object kind {:?} in {}", kind, METADATA_TABLE),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectRecord {
+    pub name: Ident,
+    pub kind: ObjectKind,
+    pub hash: String,
+    pub comment: Option<String>,
+}
+
+// This is a 64-bit FNV-1a hash, which (unlike std's DefaultHasher) is guaranteed to be stable
+// across builds, since the hashes are persisted in the database.
+pub fn content_hash(kind: ObjectKind, ddl: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in kind.as_str().bytes().chain([0]).chain(ddl.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+// The source that owns the objects saved from a schema file. This is the file's canonical path, so
+// that the same file is recognized however it is referred to (e.g. `./a.qs` vs `a.qs`).
+pub fn owner(file: &str) -> String {
+    match std::fs::canonicalize(file) {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => file.to_string(),
+    }
+}
+
+fn quote_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+pub(super) fn quote_ident(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+pub(super) fn parse_statement(sql: &str) -> Result<sqlast::Statement> {
+    match Parser::parse_sql(&GenericDialect {}, sql) {
+        Ok(mut stmts) if stmts.len() == 1 => Ok(stmts.swap_remove(0)),
+        Ok(_) => fail!("Expected a single statement: {}", sql),
+        Err(e) => fail!("Failed to parse {:?}: {}", sql, e),
+    }
+}
+
+async fn run(engine: &mut (dyn SQLEngine + 'static), sql: &str) -> Result<Arc<dyn Relation>> {
+    engine.eval(&parse_statement(sql)?, HashMap::new()).await
+}
+
+/// Creates the metadata table if it does not exist.
+pub async fn create_metadata_table(engine: &mut (dyn SQLEngine + 'static)) -> Result<()> {
+    run(
+        engine,
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (source VARCHAR, name VARCHAR, kind VARCHAR, hash VARCHAR, comment VARCHAR)",
+            METADATA_TABLE
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Returns the objects that were previously saved from `source` into this database. This does not
+/// modify the database, so that it can be used to plan a save.
+pub async fn load_metadata(
+    engine: &mut (dyn SQLEngine + 'static),
+    source: &str,
+) -> Result<BTreeMap<Ident, ObjectRecord>> {
+    let table: Ident = METADATA_TABLE.into();
+    if !engine.table_exists(&(&table).into()).await? {
+        return Ok(BTreeMap::new());
+    }
+
+    let rows = run(
+        engine,
+        &format!(
+            "SELECT name, kind, hash, comment FROM {} WHERE source = {}",
+            METADATA_TABLE,
+            quote_literal(source)
+        ),
+    )
+    .await?;
+
+    let mut ret = BTreeMap::new();
+    for row in rows.records() {
+        let name: Ident = row.column(0).to_string().into();
+        let kind = ObjectKind::parse(&row.column(1).to_string())?;
+        let hash = row.column(2).to_string();
+        let comment = match row.column(3) {
+            Value::Null => None,
+            comment => Some(comment.to_string()),
+        };
+        ret.insert(
+            name.clone(),
+            ObjectRecord {
+                name,
+                kind,
+                hash,
+                comment,
+            },
+        );
+    }
+    Ok(ret)
+}
+
+async fn delete_record(
+    engine: &mut (dyn SQLEngine + 'static),
+    source: &str,
+    name: &Ident,
+) -> Result<()> {
+    run(
+        engine,
+        &format!(
+            "DELETE FROM {} WHERE source = {} AND name = {}",
+            METADATA_TABLE,
+            quote_literal(source),
+            quote_literal(&name.to_string())
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Records that `source` created (or replaced) an object.
+pub async fn record_object(
+    engine: &mut (dyn SQLEngine + 'static),
+    source: &str,
+    record: &ObjectRecord,
+) -> Result<()> {
+    delete_record(engine, source, &record.name).await?;
+    run(
+        engine,
+        &format!(
+            "INSERT INTO {} VALUES ({}, {}, {}, {}, {})",
+            METADATA_TABLE,
+            quote_literal(source),
+            quote_literal(&record.name.to_string()),
+            quote_literal(record.kind.as_str()),
+            quote_literal(&record.hash),
+            match &record.comment {
+                Some(comment) => quote_literal(comment),
+                None => "NULL".to_string(),
+            }
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Drops an object that was previously saved from `source`, along with its metadata. If another
+/// source has since saved an object with the same name, only the metadata is removed, since the
+/// object now belongs to that source.
+pub async fn drop_object(
+    engine: &mut (dyn SQLEngine + 'static),
+    source: &str,
+    record: &ObjectRecord,
+) -> Result<()> {
+    let others = run(
+        engine,
+        &format!(
+            "SELECT source FROM {} WHERE name = {} AND source <> {}",
+            METADATA_TABLE,
+            quote_literal(&record.name.to_string()),
+            quote_literal(source)
+        ),
+    )
+    .await?;
+    if !others.records().is_empty() {
+        return delete_record(engine, source, &record.name).await;
+    }
+
+    run(
+        engine,
+        &format!(
+            "DROP {} IF EXISTS {}",
+            record.kind.as_str().to_uppercase(),
+            quote_ident(&record.name.to_string())
+        ),
+    )
+    .await?;
+    delete_record(engine, source, &record.name).await
+}
@@ -1,29 +1,51 @@
 // This file is responsible for the QueryScript equivalent of orchestration: saving "views"
 // back to the original database.
-use snafu::prelude::*;
+use snafu::{prelude::*, IntoError};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::Arc;
-use std::{collections::HashMap, sync::RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::compile::inference::mkcref;
-use crate::compile::schema::{CRef, Decl, DeclMap, SQLBody, STypedExpr};
-use crate::runtime::context::ContextPool;
-use crate::types::Type;
+use crate::compile::schema::{
+    Decl, DeclMap, MaterializeKind, Params, Ref, STypedExpr, SchemaPath, TypedExpr,
+};
+use crate::runtime::checks::run_checks;
+use crate::runtime::context::{Context, ContextPool};
+use crate::runtime::{error::fail, SQLEngine};
+use crate::types::{Type, Value};
 use crate::{
     ast::SourceLocation,
     compile::{
-        error::RuntimeSnafu,
-        schema::{Expr, Ident, MaterializeExpr, SQLSnippet},
-        sql::{create_table_as, create_view_as},
+        error::{CompileError, RuntimeSnafu},
+        schema::{Expr, Ident, MaterializeExpr, Schema},
+        sql::{create_table_as, create_view_as, select_star_from},
         ConnectionString, Result, SchemaRef,
     },
     runtime,
 };
-use tokio::task::JoinHandle;
+use tokio::task::JoinSet;
+
+mod files;
+mod incremental;
+mod metadata;
+mod options;
+mod plan;
+mod report;
+use files::{plan_files, write_file, FileStep};
+use incremental::{incremental_update, IncrementalUpdate};
+pub use metadata::METADATA_TABLE;
+use metadata::{ObjectKind, ObjectRecord};
+use options::{create_indexes, ordered_load, ordered_query, sort_columns, OrderedLoad};
+use plan::topological_order;
+pub use plan::{Plan, PlanAction, PlanStep};
+pub use report::{SaveReport, SaveStatus, StepReport};
 
-type Signals = HashMap<Ident, CRef<()>>;
+// The exported declarations that are saved to a database.
+type Candidates = BTreeSet<Ident>;
 
-fn gather_materialize_candidates(decls: &DeclMap<STypedExpr>) -> Result<Signals> {
-    let mut signals = Signals::new();
+fn gather_materialize_candidates(decls: &DeclMap<STypedExpr>) -> Result<Candidates> {
+    let mut candidates = Candidates::new();
+    let mut targets = HashMap::new();
+    let mut views = Vec::new();
     for (name, decl) in decls.iter() {
         if !decl.public {
             continue;
@@ -37,187 +59,1244 @@ fn gather_materialize_candidates(decls: &DeclMap<STypedExpr>) -> Result<Signals>
         })?;
 
         match &expr {
-            Expr::SQL(_, url) | Expr::Materialize(MaterializeExpr { url, .. }) => {
-                if url.is_some() {
-                    signals.insert(name.clone(), CRef::new_unknown(&format!("{}", name)));
-                }
+            Expr::SQL(sql, Some(url)) => {
+                views.push((
+                    name.clone(),
+                    decl.location().clone(),
+                    url.clone(),
+                    sql.clone(),
+                ));
+                targets.insert(name.clone(), url.clone());
+            }
+            Expr::Materialize(MaterializeExpr { url: Some(url), .. }) => {
+                targets.insert(name.clone(), url.clone());
             }
             _ => continue,
         }
+        candidates.insert(name.clone());
+    }
+
+    // A view is only useful if everything it reads is stored in the same database. Any other
+    // relation it references (e.g. a mat that is not exported) would only exist for the lifetime
+    // of this process, so the saved view would break as soon as it exits.
+    for (name, loc, url, sql) in views {
+        for param in sql.names.params.values() {
+            match param.expr.as_ref() {
+                Expr::Materialize(MaterializeExpr {
+                    inlined: true,
+                    decl_name,
+                    ..
+                }) => match targets.get(decl_name) {
+                    Some(dep_url) if dep_url.as_ref() == url.as_ref() => {}
+                    _ => {
+                        return Err(CompileError::save_error(
+                            loc,
+                            name.clone(),
+                            &format!(
+                                "it is a view in {}, but depends on {}, which is not saved there (export {} to the same database, or make {} a mat)",
+                                url, decl_name, decl_name, name
+                            ),
+                        ))
+                    }
+                },
+                _ if matches!(*param.type_.read()?, Type::List(_)) => {
+                    return Err(CompileError::save_error(
+                        loc,
+                        name,
+                        "it is a view, but depends on a relation that is computed locally (make it a mat to save a copy of its data instead)",
+                    ))
+                }
+                _ => {}
+            }
+        }
     }
 
-    Ok(signals)
+    Ok(candidates)
 }
 
-fn execute_create_view(
-    ctx_pool: &ContextPool,
-    url: Option<Arc<ConnectionString>>,
+// Identifies a step by its target database as well as its name, since exports with the same name
+// can be saved to different databases.
+type StepKey = (Arc<ConnectionString>, Ident);
+
+// The databases that `save_views` reads and modifies, along with the location that refers to each
+// one (for error messages).
+type Targets = BTreeMap<Arc<ConnectionString>, SourceLocation>;
+
+// The objects that were previously saved into each target database.
+type PreviousObjects = BTreeMap<Arc<ConnectionString>, BTreeMap<Ident, ObjectRecord>>;
+
+// A single object that `save_views` creates in a target database.
+struct SaveStep {
+    name: Ident,
+    loc: SourceLocation,
+    url: Arc<ConnectionString>,
+    dependencies: Vec<Ident>,
+    action: SaveAction,
+    incremental: Option<IncrementalUpdate>,
+    // Statements that run after the object is created or updated.
+    indexes: Vec<sqlparser::ast::Statement>,
+    comment: Option<String>,
+}
+
+enum SaveAction {
+    // A CREATE VIEW or CREATE TABLE AS statement that runs directly against the target database.
+    Query {
+        query: sqlparser::ast::Statement,
+        params: Params<Ref<Type>>,
+        action: PlanAction,
+    },
+    // An expression that is evaluated locally, and whose result is then loaded into the target
+    // database.
+    Load {
+        expr: TypedExpr<Ref<Type>>,
+        type_: Type,
+        ordered: Option<OrderedLoad>,
+    },
+}
+
+impl SaveStep {
+    fn key(&self) -> StepKey {
+        (self.url.clone(), self.name.clone())
+    }
+
+    // A step's dependencies are always saved to the same database as the step itself (see
+    // `gather_materialize_candidates`).
+    fn dependency_keys(&self) -> Vec<StepKey> {
+        self.dependencies
+            .iter()
+            .map(|dep| (self.url.clone(), dep.clone()))
+            .collect()
+    }
+
+    // Whether an existing object created from the same DDL can be reused as-is. This is only true
+    // for views whose query does not depend on any values computed at runtime.
+    fn is_reusable(&self) -> bool {
+        match &self.action {
+            SaveAction::Query {
+                action: PlanAction::CreateView,
+                params,
+                ..
+            } => params.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn object_kind(&self) -> ObjectKind {
+        match &self.action {
+            SaveAction::Query {
+                action: PlanAction::CreateView,
+                ..
+            } => ObjectKind::View,
+            _ => ObjectKind::Table,
+        }
+    }
+
+    fn plan_action(&self) -> PlanAction {
+        match &self.action {
+            SaveAction::Query { action, .. } => *action,
+            SaveAction::Load { .. } => PlanAction::LoadTable,
+        }
+    }
+
+    fn plan_step(&self) -> PlanStep {
+        PlanStep {
+            name: self.name.to_string(),
+            target: format!("{}", self.url),
+            action: self.plan_action(),
+            dependencies: self.dependencies.iter().map(|d| d.to_string()).collect(),
+            ddl: self.ddl(),
+            incremental_ddl: self.incremental_ddl(),
+            comment: self.comment.clone(),
+            unchanged: false,
+        }
+    }
+
+    // The metadata that is recorded once this step has been saved. The hash covers everything that
+    // the step runs, so that a change to any of it recreates the object.
+    fn object_record(&self) -> ObjectRecord {
+        let kind = self.object_kind();
+        let mut ddl = match self.incremental_ddl() {
+            Some(incremental_ddl) => format!("{};\n{}", self.ddl(), incremental_ddl),
+            None => self.ddl(),
+        };
+        if let Some(comment) = &self.comment {
+            ddl.push_str(&format!("\n-- {}", comment));
+        }
+        ObjectRecord {
+            name: self.name.clone(),
+            kind,
+            hash: metadata::content_hash(kind, &ddl),
+            comment: self.comment.clone(),
+        }
+    }
+
+    // The DDL that this step runs. Loaded tables are created from a relation parameter that
+    // holds the locally computed data (see `SQLEngine::load`).
+    fn ddl(&self) -> String {
+        let stmts = match &self.action {
+            SaveAction::Query { query, .. } => vec![query.clone()],
+            SaveAction::Load {
+                ordered: Some(ordered),
+                ..
+            } => std::iter::once(load_table_as(&ordered.staging_table, true))
+                .chain(ordered.statements.iter().cloned())
+                .collect(),
+            SaveAction::Load { .. } => vec![load_table_as(&self.name, false)],
+        };
+        self.format_ddl(stmts)
+    }
+
+    // The DDL that this step runs instead of `ddl()` if the table already exists.
+    fn incremental_ddl(&self) -> Option<String> {
+        let incremental = self.incremental.as_ref()?;
+        let staging = match &self.action {
+            SaveAction::Query {
+                query:
+                    sqlparser::ast::Statement::CreateTable {
+                        query: Some(query), ..
+                    },
+                ..
+            } => vec![incremental.stage_query(query.as_ref().clone())],
+            SaveAction::Load { .. } => {
+                std::iter::once(load_table_as(&incremental.staging_table, true))
+                    .chain(incremental.load_filter.iter().cloned())
+                    .collect()
+            }
+            _ => return None,
+        };
+
+        Some(
+            self.format_ddl(
+                staging
+                    .into_iter()
+                    .chain(incremental.statements.iter().cloned())
+                    .collect(),
+            ),
+        )
+    }
+
+    fn format_ddl(&self, stmts: Vec<sqlparser::ast::Statement>) -> String {
+        stmts
+            .iter()
+            .chain(self.indexes.iter())
+            .map(|stmt| format!("{}", stmt))
+            .collect::<Vec<_>>()
+            .join(";\n")
+    }
+}
+
+fn load_table_as(name: &Ident, temporary: bool) -> sqlparser::ast::Statement {
+    create_table_as(
+        name.into(),
+        select_star_from(sqlparser::ast::TableFactor::Table {
+            name: sqlparser::ast::ObjectName(vec![sqlparser::ast::Located::new(
+                "__qs_load".into(),
+                None,
+            )]),
+            alias: None,
+            args: None,
+            with_hints: vec![],
+        }),
+        temporary,
+    )
+}
+
+fn plan_query(
+    url: Arc<ConnectionString>,
     name: &Ident,
-    sql: &Arc<SQLSnippet<Arc<RwLock<Type>>, SQLBody>>,
+    loc: &SourceLocation,
+    params: &Params<Ref<Type>>,
     query: sqlparser::ast::Statement,
-    signals: &Signals,
-    handles: &mut Vec<JoinHandle<crate::runtime::Result<()>>>,
-) -> crate::runtime::Result<()> {
+    action: PlanAction,
+    candidates: &Candidates,
+) -> SaveStep {
     let mut dependencies = Vec::new();
-    let mut dependency_names = Vec::new();
-    let mut params = crate::compile::schema::Params::new();
-    for (name, param) in &sql.names.params {
+    let mut remaining_params = Params::new();
+    for (name, param) in params {
         match param.expr.as_ref() {
             Expr::Materialize(MaterializeExpr {
                 inlined: true,
                 decl_name,
                 ..
-            }) => {
-                if let Some(signal) = signals.get(decl_name) {
-                    dependency_names.push(format!("{}", decl_name));
-                    dependencies.push(signal.clone());
-                } else {
-                    params.insert(name.clone(), param.clone());
-                }
+            }) if candidates.contains(decl_name) => {
+                dependencies.push(decl_name.clone());
             }
             _ => {
-                params.insert(name.clone(), param.clone());
+                remaining_params.insert(name.clone(), param.clone());
             }
         }
     }
 
-    let completed_ref = signals
-        .get(name)
-        .expect("Should only call execute_create_view() on a pre-approved view")
-        .clone();
-
-    let mut ctx = ctx_pool.get();
-    let url = url.clone();
-    let name = name.clone();
-    handles.push(tokio::spawn(async move {
-        eprintln!(
-            "View \"{}\"{}",
-            name,
-            if dependency_names.len() > 0 {
-                format!(" (depends on: {})", dependency_names.join(", "))
-            } else {
-                " has no dependencies".into()
-            }
-        );
-
-        for dep in dependencies {
-            dep.await?;
-        }
-
-        eprintln!("Creating view \"{}\"", name);
-        let sql_params = runtime::eval_params(&mut ctx, &params).await?;
-
-        let result = ctx.sql_engine(url.clone())?.eval(&query, sql_params).await;
-
-        completed_ref.unify(&mkcref(()))?;
-        result?;
-        Ok(())
-    }));
-    Ok(())
+    SaveStep {
+        name: name.clone(),
+        loc: loc.clone(),
+        url,
+        dependencies,
+        action: SaveAction::Query {
+            query,
+            params: remaining_params,
+            action,
+        },
+        incremental: None,
+        indexes: Vec::new(),
+        comment: None,
+    }
 }
 
-async fn process_view(
-    ctx_pool: &ContextPool,
+fn plan_view(
     name: &Ident,
     decl: &Decl<STypedExpr>,
-    signals: &Signals,
-    handles: &mut Vec<tokio::task::JoinHandle<crate::runtime::Result<()>>>,
-) -> crate::runtime::Result<()> {
+    loc: &SourceLocation,
+    candidates: &Candidates,
+) -> crate::runtime::Result<Option<SaveStep>> {
     let object_name = name.into();
 
     let expr = decl.value.expr.must()?;
     let expr = expr.read()?.to_runtime_type()?;
-    match expr {
-        Expr::SQL(ref sql, url) => {
+    Ok(match expr {
+        Expr::SQL(ref sql, Some(url)) => {
             let query = create_view_as(object_name, sql.body.as_query());
-            execute_create_view(ctx_pool, url.clone(), &name, &sql, query, signals, handles)?;
+            Some(plan_query(
+                url,
+                name,
+                loc,
+                &sql.names.params,
+                query,
+                PlanAction::CreateView,
+                candidates,
+            ))
         }
-        Expr::Materialize(MaterializeExpr { expr, url, .. }) => {
-            match (url, expr.expr.as_ref()) {
+        Expr::Materialize(MaterializeExpr {
+            expr, url, options, ..
+        }) => {
+            let order_by = sort_columns(&options);
+            let step = match (url, expr.expr.as_ref()) {
                 (Some(url), Expr::SQL(sql, Some(sql_url))) if url.as_ref() == sql_url.as_ref() => {
-                    let query = create_table_as(object_name.into(), sql.body.as_query(), false);
-                    execute_create_view(
-                        ctx_pool,
-                        Some(url.clone()),
-                        &name,
-                        &sql,
+                    let query = ordered_query(sql.body.as_query(), &order_by);
+                    let (query, action) = match options.kind {
+                        MaterializeKind::Table => (
+                            create_table_as(object_name, query, false),
+                            PlanAction::CreateTable,
+                        ),
+                        MaterializeKind::View => {
+                            (create_view_as(object_name, query), PlanAction::CreateView)
+                        }
+                    };
+                    plan_query(
+                        url.clone(),
+                        name,
+                        loc,
+                        &sql.names.params,
                         query,
-                        signals,
-                        handles,
-                    )?;
+                        action,
+                        candidates,
+                    )
                 }
                 (Some(url), _) => {
-                    let completed_ref = signals.get(name).unwrap().clone();
-
-                    handles.push(tokio::spawn({
-                        let mut ctx = ctx_pool.get();
-                        let url = url.clone();
-                        let type_ = expr.type_.read()?.clone();
-                        async move {
-                            let data =
-                                runtime::eval(&mut ctx, &expr).await.context(RuntimeSnafu {
-                                    loc: SourceLocation::Unknown,
-                                })?;
-                            let engine = ctx.sql_engine(Some(url)).context(RuntimeSnafu {
-                                loc: SourceLocation::Unknown,
-                            })?;
-
-                            let result = engine
-                                .load(&object_name, data, type_, false /*temporary*/)
-                                .await
-                                .context(RuntimeSnafu {
-                                    loc: SourceLocation::Unknown,
-                                });
-                            completed_ref.unify(&mkcref(()))?;
-                            result?;
-                            Ok(())
-                        }
-                    }));
+                    if options.kind == MaterializeKind::View {
+                        return fail!(
+                            "Cannot save \"{}\" as a view, because its query does not run in {}",
+                            name,
+                            url
+                        );
+                    }
+                    let type_ = expr.type_.read()?.clone();
+                    SaveStep {
+                        name: name.clone(),
+                        loc: loc.clone(),
+                        url: url.clone(),
+                        dependencies: Vec::new(),
+                        action: SaveAction::Load {
+                            expr,
+                            type_,
+                            ordered: ordered_load(name, &order_by)?,
+                        },
+                        incremental: None,
+                        indexes: Vec::new(),
+                        comment: None,
+                    }
                 }
                 _ => {
                     eprintln!(
                         "Skipping \"{}\" because it does not belong to a database",
                         name
                     );
+                    return Ok(None);
                 }
             };
+
+            Some(SaveStep {
+                incremental: match &options.incremental {
+                    Some(incremental) => Some(incremental_update(name, incremental, &order_by)?),
+                    None => None,
+                },
+                indexes: create_indexes(name, &options)?,
+                comment: options.comment.clone(),
+                ..step
+            })
+        }
+        _ => {
+            return fail!(
+                "Cannot save \"{}\", because it is not a query that runs in a database",
+                name
+            )
+        }
+    })
+}
+
+// The databases that the steps save to. When pruning, this also includes every other database
+// that the schema imports, since they may still hold objects that are no longer exported.
+fn save_targets(schema: &Schema, steps: &[SaveStep], prune: bool) -> Targets {
+    let mut targets = Targets::new();
+    for step in steps.iter() {
+        targets
+            .entry(step.url.clone())
+            .or_insert_with(|| step.loc.clone());
+    }
+    if prune {
+        for path in schema.imports.keys() {
+            if let SchemaPath::Connection(url) = path {
+                targets
+                    .entry(url.get().clone())
+                    .or_insert_with(|| url.location().clone());
+            }
         }
-        e => {
-            panic!("Should have filtered out {:?} from materializations", e)
+    }
+    targets
+}
+
+fn plan_views(schema: &Schema) -> Result<Vec<SaveStep>> {
+    let candidates = gather_materialize_candidates(&schema.expr_decls)?;
+
+    let mut steps = Vec::new();
+    for (name, decl) in schema.expr_decls.iter() {
+        if !candidates.contains(name) {
+            continue;
+        }
+        let loc = decl.location().clone();
+        if let Some(step) =
+            plan_view(name, decl, &loc, &candidates).context(RuntimeSnafu { loc: loc.clone() })?
+        {
+            steps.push(step);
+        }
+    }
+
+    Ok(topological_order(
+        steps,
+        SaveStep::key,
+        SaveStep::dependency_keys,
+    ))
+}
+
+async fn run_step(ctx: &mut Context, step: &SaveStep) -> crate::runtime::Result<()> {
+    let SaveStep {
+        name,
+        url,
+        action,
+        incremental,
+        indexes,
+        ..
+    } = step;
+    let object_name = name.into();
+    match action {
+        SaveAction::Query { query, params, .. } => {
+            let sql_params = runtime::eval_params(ctx, params).await?;
+            let engine = ctx.sql_engine(Some(url.clone()))?;
+            let exists = incremental.is_some() && engine.table_exists(&object_name).await?;
+            match (query, incremental) {
+                (
+                    sqlparser::ast::Statement::CreateTable {
+                        query: Some(query), ..
+                    },
+                    Some(incremental),
+                ) if exists => {
+                    eprintln!("Updating table \"{}\"", name);
+                    let staging = incremental.stage_query(query.as_ref().clone());
+                    engine.eval(&staging, sql_params).await?;
+                    run_statements(engine, &incremental.statements).await?;
+                }
+                (query, _) => {
+                    eprintln!("Creating view \"{}\"", name);
+                    engine.eval(query, sql_params).await?;
+                }
+            }
+        }
+        SaveAction::Load {
+            expr,
+            type_,
+            ordered,
+        } => {
+            let data = runtime::eval(ctx, expr).await?;
+            let engine = ctx.sql_engine(Some(url.clone()))?;
+            let exists = incremental.is_some() && engine.table_exists(&object_name).await?;
+            match (incremental, ordered) {
+                (Some(incremental), _) if exists => {
+                    eprintln!("Updating table \"{}\"", name);
+                    engine
+                        .load(
+                            &(&incremental.staging_table).into(),
+                            data,
+                            type_.clone(),
+                            true, /*temporary*/
+                        )
+                        .await?;
+                    if let Some(load_filter) = &incremental.load_filter {
+                        engine.eval(load_filter, HashMap::new()).await?;
+                    }
+                    run_statements(engine, &incremental.statements).await?;
+                }
+                (_, Some(ordered)) => {
+                    engine
+                        .load(
+                            &(&ordered.staging_table).into(),
+                            data,
+                            type_.clone(),
+                            true, /*temporary*/
+                        )
+                        .await?;
+                    run_statements(engine, &ordered.statements).await?;
+                }
+                _ => {
+                    engine
+                        .load(&object_name, data, type_.clone(), false /*temporary*/)
+                        .await?;
+                }
+            }
         }
     };
 
+    run_statements(ctx.sql_engine(Some(url.clone()))?, indexes).await
+}
+
+async fn run_statements(
+    engine: &mut (dyn SQLEngine + 'static),
+    stmts: &[sqlparser::ast::Statement],
+) -> crate::runtime::Result<()> {
+    for stmt in stmts {
+        engine.eval(stmt, HashMap::new()).await?;
+    }
     Ok(())
 }
 
+// Decides whether a failed attempt should be retried. Only transient errors are retried, and the
+// wait between attempts doubles each time.
+struct Retry {
+    attempts: u32,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Retry {
+    fn new(options: &SaveOptions) -> Retry {
+        Retry {
+            attempts: 1,
+            retries: options.retries,
+            backoff: options.retry_backoff,
+        }
+    }
+
+    // Returns how long to wait before retrying, or None if the error should not be retried.
+    fn delay(&mut self, name: &str, e: &runtime::RuntimeError) -> Option<Duration> {
+        if !e.is_transient() || self.attempts > self.retries {
+            return None;
+        }
+
+        let delay = self.backoff * 2u32.pow(self.attempts - 1);
+        eprintln!(
+            "Retrying \"{}\" in {}ms after a transient error: {}",
+            name,
+            delay.as_millis(),
+            e
+        );
+        self.attempts += 1;
+        Some(delay)
+    }
+}
+
+async fn run_step_with_retries(
+    ctx: &mut Context,
+    step: &SaveStep,
+    options: &SaveOptions,
+) -> (crate::runtime::Result<()>, u32) {
+    let name = step.name.to_string();
+    let mut retry = Retry::new(options);
+    loop {
+        let result = run_step(ctx, step).await;
+        let delay = match &result {
+            Err(e) => retry.delay(&name, e),
+            Ok(()) => None,
+        };
+        match delay {
+            Some(delay) => tokio::time::sleep(delay).await,
+            None => return (result, retry.attempts),
+        }
+    }
+}
+
+struct StepOutcome {
+    step: SaveStep,
+    result: crate::runtime::Result<()>,
+    attempts: u32,
+    duration: Duration,
+}
+
+fn execute_step(
+    join_set: &mut JoinSet<StepOutcome>,
+    ctx_pool: &ContextPool,
+    step: SaveStep,
+    options: &SaveOptions,
+) {
+    if let SaveAction::Query { .. } = &step.action {
+        eprintln!(
+            "View \"{}\"{}",
+            step.name,
+            if step.dependencies.len() > 0 {
+                format!(
+                    " (depends on: {})",
+                    step.dependencies
+                        .iter()
+                        .map(|d| d.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            } else {
+                " has no dependencies".into()
+            }
+        );
+    }
+
+    let mut ctx = ctx_pool.get();
+    let options = options.clone();
+    join_set.spawn(async move {
+        let start = Instant::now();
+        let (result, attempts) = run_step_with_retries(&mut ctx, &step, &options).await;
+        StepOutcome {
+            step,
+            result,
+            attempts,
+            duration: start.elapsed(),
+        }
+    });
+}
+
+/// Computes the objects that `save_views` would create, in dependency order, without executing
+/// anything. The target databases are only read, to find the objects that have not changed since
+/// they were last saved, which `save_views` would skip.
+pub async fn plan_save_views(ctx_pool: &ContextPool, schema: SchemaRef) -> Result<Plan> {
+    let (source, steps, files, targets) = {
+        let locked_schema = schema.read()?;
+        let steps = plan_views(&locked_schema)?;
+        let targets = save_targets(&locked_schema, &steps, false);
+        (
+            locked_schema.file.clone(),
+            steps,
+            plan_files(&locked_schema)?,
+            targets,
+        )
+    };
+
+    let mut ctx = ctx_pool.get();
+    let previous = load_previous(&mut ctx, &metadata::owner(&source), &targets).await?;
+    let unchanged = unchanged_steps(&mut ctx, &steps, &previous).await?;
+
+    Ok(Plan::new(
+        steps
+            .iter()
+            .map(|step| PlanStep {
+                unchanged: unchanged.contains(&step.key()),
+                ..step.plan_step()
+            })
+            .chain(files.iter().map(|step| step.plan_step()))
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Clone)]
+pub struct SaveOptions {
+    // Drop objects that were previously saved from this schema, but are no longer exported.
+    pub prune: bool,
+    // Apply all of the changes to each target database in a single transaction, so that a failure
+    // leaves the database untouched. This requires that the engine support transactional DDL, and
+    // runs the steps one at a time. Since a failure aborts the transaction, nothing is saved after
+    // it, including the objects that do not depend on it. This is off by default, so that a
+    // failure only blocks the objects that depend on it, and everything else is still saved.
+    pub atomic: bool,
+    // The number of times to retry an object that fails with a transient error (e.g. a lock held by
+    // another process). In atomic mode, the whole transaction is retried.
+    pub retries: u32,
+    // How long to wait before the first retry. The wait doubles after each attempt.
+    pub retry_backoff: Duration,
+    // Run the schema's data quality checks before saving anything, and fail without making any
+    // changes if one of them fails.
+    pub check: bool,
+}
+
+impl Default for SaveOptions {
+    fn default() -> SaveOptions {
+        SaveOptions {
+            prune: false,
+            atomic: false,
+            retries: 3,
+            retry_backoff: Duration::from_millis(500),
+            check: false,
+        }
+    }
+}
+
 pub async fn save_views(ctx_pool: &ContextPool, schema: SchemaRef) -> Result<()> {
-    let locked_schema = schema.read()?;
-    let signals = gather_materialize_candidates(&locked_schema.expr_decls)?;
+    save_views_with_options(ctx_pool, schema, &SaveOptions::default()).await
+}
 
-    eprintln!("Processing views...\n--");
-    let mut handles = Vec::new();
-    let mut locations = Vec::new();
-    for (name, decl) in locked_schema.expr_decls.iter() {
-        if !signals.contains_key(name) {
+pub async fn save_views_with_options(
+    ctx_pool: &ContextPool,
+    schema: SchemaRef,
+    options: &SaveOptions,
+) -> Result<()> {
+    save_views_with_report(ctx_pool, schema, options)
+        .await?
+        .into_result()
+}
+
+// The error that caused a step to fail.
+struct StepFailure {
+    loc: SourceLocation,
+    error: runtime::RuntimeError,
+}
+
+/// Saves the exported views, and returns a report of what happened to each one. Unlike
+/// `save_views_with_options`, this only returns an error if the views could not be planned (or the
+/// target databases could not be reached). If an object fails to save, the report includes the
+/// error.
+pub async fn save_views_with_report(
+    ctx_pool: &ContextPool,
+    schema: SchemaRef,
+    options: &SaveOptions,
+) -> Result<SaveReport> {
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let start = Instant::now();
+
+    let plan = |schema: &SchemaRef| -> Result<(String, Vec<SaveStep>, Vec<FileStep>)> {
+        let locked_schema = schema.read()?;
+        Ok((
+            locked_schema.file.clone(),
+            plan_views(&locked_schema)?,
+            plan_files(&locked_schema)?,
+        ))
+    };
+    let (source, steps, files) = plan(&schema)?;
+    let source_loc = SourceLocation::File(source.clone());
+    let owner = metadata::owner(&source);
+    let targets = save_targets(&*schema.read()?, &steps, options.prune);
+
+    let mut ctx = ctx_pool.get();
+    if options.check {
+        let checks = schema.read()?.checks.clone();
+        let failures = run_checks(&mut ctx, &checks).await.context(RuntimeSnafu {
+            loc: source_loc.clone(),
+        })?;
+        if !failures.is_empty() {
+            let mut sources = Vec::new();
+            for failure in failures {
+                eprintln!("{}\n{}", failure, Value::Relation(failure.rows.clone()));
+                sources.push(CompileError::save_error(
+                    failure.check.loc.clone(),
+                    failure.check.decl.clone(),
+                    &failure.to_string(),
+                ));
+            }
+            return Err(if sources.len() == 1 {
+                sources.pop().unwrap()
+            } else {
+                CompileError::Multiple { sources }
+            });
+        }
+    }
+
+    let mut atomic = options.atomic;
+    for url in targets.keys() {
+        let engine = ctx.sql_engine(Some(url.clone())).context(RuntimeSnafu {
+            loc: source_loc.clone(),
+        })?;
+        if atomic && !engine.supports_transactional_ddl() {
+            eprintln!(
+                "WARNING: {} does not support transactional DDL, so views will not be saved atomically",
+                url
+            );
+            atomic = false;
+        }
+    }
+
+    // In atomic mode, a failed statement aborts the whole transaction, so rather than retrying
+    // individual objects, the transaction is retried from the beginning (with a fresh plan).
+    let mut transaction = Retry::new(options);
+    let mut planned = Some((steps, files));
+    let (reports, failure) = loop {
+        let (steps, files) = match planned.take() {
+            Some(planned) => planned,
+            None => {
+                let (_, steps, files) = plan(&schema)?;
+                (steps, files)
+            }
+        };
+
+        if atomic {
+            for url in targets.keys() {
+                ctx.sql_engine(Some(url.clone()))
+                    .context(RuntimeSnafu {
+                        loc: source_loc.clone(),
+                    })?
+                    .begin_transaction()
+                    .await
+                    .context(RuntimeSnafu {
+                        loc: source_loc.clone(),
+                    })?;
+            }
+        }
+
+        // Files are written once everything else has been saved, so that (in atomic mode) a failure
+        // in either one leaves the databases untouched.
+        let result = async {
+            let (mut reports, failure) =
+                save_steps(ctx_pool, &mut ctx, &owner, &targets, steps, options, atomic).await?;
+            let (file_reports, file_failure) =
+                write_files(&mut ctx, files, options, &reports).await;
+            let failure = failure.or(file_failure);
+
+            // In atomic mode, a failure rolls back the objects that were saved (but not the files
+            // that were written).
+            if atomic && failure.is_some() {
+                for report in reports.iter_mut() {
+                    if report.status == SaveStatus::Created {
+                        report.status = SaveStatus::RolledBack;
+                    }
+                }
+            }
+            reports.extend(file_reports);
+            Ok::<_, CompileError>((reports, failure))
+        }
+        .await;
+
+        if atomic {
+            match &result {
+                Ok((_, None)) => {
+                    for url in targets.keys() {
+                        ctx.sql_engine(Some(url.clone()))
+                            .context(RuntimeSnafu {
+                                loc: source_loc.clone(),
+                            })?
+                            .commit()
+                            .await
+                            .context(RuntimeSnafu {
+                                loc: source_loc.clone(),
+                            })?;
+                    }
+                }
+                _ => {
+                    eprintln!("--\nRolling back all changes...");
+                    for url in targets.keys() {
+                        let rollback = match ctx.sql_engine(Some(url.clone())) {
+                            Ok(engine) => engine.rollback().await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = rollback {
+                            eprintln!("Failed to roll back changes to {}: {}", url, e);
+                        }
+                    }
+                }
+            }
+        }
+
+        let (reports, failure) = result?;
+        let delay = match &failure {
+            Some(failure) if atomic => transaction.delay(&source, &failure.error),
+            _ => None,
+        };
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+            continue;
+        }
+        break (reports, failure);
+    };
+
+    let report = SaveReport {
+        source,
+        atomic,
+        success: failure.is_none(),
+        started_at,
+        duration_ms: start.elapsed().as_millis() as u64,
+        attempts: transaction.attempts,
+        steps: reports,
+        error: failure.map(|f| RuntimeSnafu { loc: f.loc }.into_error(f.error)),
+    };
+    eprint!("--\n{}", report);
+    Ok(report)
+}
+
+// Writes the files, unless any of the database objects failed to save (file targets may read from
+// them, but their dependencies are not tracked).
+async fn write_files(
+    ctx: &mut Context,
+    files: Vec<FileStep>,
+    options: &SaveOptions,
+    reports: &[StepReport],
+) -> (Vec<StepReport>, Option<StepFailure>) {
+    let failed = reports
+        .iter()
+        .filter(|r| matches!(r.status, SaveStatus::Failed | SaveStatus::Blocked))
+        .map(|r| r.name.clone())
+        .collect::<Vec<_>>();
+
+    let mut file_reports = Vec::new();
+    let mut failure = None;
+    for step in files {
+        let plan_step = step.plan_step();
+        let mut report = StepReport::new(plan_step.name, plan_step.target, plan_step.action);
+        if !failed.is_empty() {
+            report.blocked_by = failed.clone();
+            file_reports.push(report);
             continue;
         }
-        process_view(ctx_pool, name, decl, &signals, &mut handles)
+
+        let start = Instant::now();
+        let name = step.name.to_string();
+        let mut retry = Retry::new(options);
+        let result = loop {
+            let result = write_file(ctx, &step).await;
+            let delay = match &result {
+                Err(e) => retry.delay(&name, e),
+                Ok(()) => None,
+            };
+            match delay {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => break result,
+            }
+        };
+
+        match result {
+            Ok(()) => report.finish(SaveStatus::Created, retry.attempts, start.elapsed()),
+            Err(error) => {
+                eprintln!("Failed to save \"{}\"", name);
+                report.finish(SaveStatus::Failed, retry.attempts, start.elapsed());
+                report.error = Some(error.to_string());
+                if failure.is_none() {
+                    failure = Some(StepFailure {
+                        loc: step.loc.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+        file_reports.push(report);
+    }
+    (file_reports, failure)
+}
+
+// Loads the objects that were previously saved from `source` into each of the target databases.
+async fn load_previous(
+    ctx: &mut Context,
+    source: &str,
+    targets: &Targets,
+) -> Result<PreviousObjects> {
+    let mut previous = BTreeMap::new();
+    for (url, loc) in targets.iter() {
+        let engine = ctx
+            .sql_engine(Some(url.clone()))
+            .context(RuntimeSnafu { loc: loc.clone() })?;
+        let records = metadata::load_metadata(engine, source)
             .await
-            .context(RuntimeSnafu {
-                loc: decl.location().clone(),
-            })?;
-        locations.push(decl.location().clone());
-    }
-    eprintln!("--\nWaiting for all views to complete...\n--");
-    for (i, handle) in handles.into_iter().enumerate() {
-        handle
+            .context(RuntimeSnafu { loc: loc.clone() })?;
+        previous.insert(url.clone(), records);
+    }
+    Ok(previous)
+}
+
+// Finds the steps that can be skipped. An object can be skipped if it was created from the same
+// DDL, and still exists. Tables are always rebuilt, because their contents depend on the data they
+// were created from.
+async fn unchanged_steps(
+    ctx: &mut Context,
+    steps: &[SaveStep],
+    previous: &PreviousObjects,
+) -> Result<BTreeSet<StepKey>> {
+    let mut unchanged = BTreeSet::new();
+    for step in steps.iter() {
+        let reusable = match previous[&step.url].get(&step.name) {
+            Some(prev) => prev.hash == step.object_record().hash && step.is_reusable(),
+            None => false,
+        };
+        if reusable
+            && ctx
+                .sql_engine(Some(step.url.clone()))
+                .context(RuntimeSnafu {
+                    loc: step.loc.clone(),
+                })?
+                .table_exists(&(&step.name).into())
+                .await
+                .context(RuntimeSnafu {
+                    loc: step.loc.clone(),
+                })?
+        {
+            unchanged.insert(step.key());
+        }
+    }
+
+    // A view must also be recreated if anything it depends on is recreated.
+    loop {
+        let invalidated = steps
+            .iter()
+            .filter(|step| {
+                unchanged.contains(&step.key())
+                    && step
+                        .dependency_keys()
+                        .iter()
+                        .any(|dep| !unchanged.contains(dep))
+            })
+            .map(SaveStep::key)
+            .collect::<Vec<_>>();
+        if invalidated.is_empty() {
+            break;
+        }
+        for key in invalidated {
+            unchanged.remove(&key);
+        }
+    }
+    Ok(unchanged)
+}
+
+async fn save_steps(
+    ctx_pool: &ContextPool,
+    ctx: &mut Context,
+    source: &str,
+    targets: &Targets,
+    steps: Vec<SaveStep>,
+    options: &SaveOptions,
+    atomic: bool,
+) -> Result<(Vec<StepReport>, Option<StepFailure>)> {
+    let previous = load_previous(ctx, source, targets).await?;
+    let skipped = unchanged_steps(ctx, &steps, &previous).await?;
+    for (url, loc) in targets.iter() {
+        let engine = ctx
+            .sql_engine(Some(url.clone()))
+            .context(RuntimeSnafu { loc: loc.clone() })?;
+        metadata::create_metadata_table(engine)
             .await
-            .expect("Failed to join task")
-            .context(RuntimeSnafu {
-                loc: locations[i].clone(),
-            })?;
+            .context(RuntimeSnafu { loc: loc.clone() })?;
     }
-    eprintln!("--\nSuccess!");
 
-    Ok(())
+    // If an object changes kind (e.g. from a view to a table), the old object has to be dropped
+    // before the new one can replace it.
+    for step in steps.iter() {
+        match previous[&step.url].get(&step.name) {
+            Some(prev) if prev.kind != step.object_kind() => {
+                let engine = ctx
+                    .sql_engine(Some(step.url.clone()))
+                    .context(RuntimeSnafu {
+                        loc: step.loc.clone(),
+                    })?;
+                metadata::drop_object(engine, source, prev)
+                    .await
+                    .context(RuntimeSnafu {
+                        loc: step.loc.clone(),
+                    })?;
+            }
+            _ => {}
+        }
+    }
+
+    let current = steps.iter().map(SaveStep::key).collect::<BTreeSet<_>>();
+
+    let mut reports = steps
+        .iter()
+        .map(|step| {
+            let plan_step = step.plan_step();
+            StepReport::new(plan_step.name, plan_step.target, plan_step.action)
+        })
+        .collect::<Vec<_>>();
+    let index = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| (step.key(), i))
+        .collect::<BTreeMap<_, _>>();
+    let mut failure = None;
+
+    eprintln!("Processing views...\n--");
+    if atomic {
+        // All of the steps run on the same connection (and therefore, transaction), so they run
+        // one at a time, in dependency order. Once a step fails, the transaction is aborted, so
+        // nothing after it can be saved. The steps that depend on a failure are blocked by it,
+        // and the rest are aborted.
+        let mut failed = BTreeSet::new();
+        for step in steps {
+            let key = step.key();
+            let report = &mut reports[index[&key]];
+            if skipped.contains(&key) {
+                eprintln!("Skipping \"{}\" because it has not changed", step.name);
+                report.status = SaveStatus::Skipped;
+                continue;
+            }
+            if !failed.is_empty() {
+                report.blocked_by = step
+                    .dependency_keys()
+                    .into_iter()
+                    .filter(|dep| failed.contains(dep))
+                    .map(|(_, dep)| dep.to_string())
+                    .collect();
+                if report.blocked_by.is_empty() {
+                    report.status = SaveStatus::Aborted;
+                } else {
+                    failed.insert(key);
+                }
+                continue;
+            }
+
+            let start = Instant::now();
+            let mut result = run_step(ctx, &step).await;
+            if result.is_ok() {
+                result = match ctx.sql_engine(Some(step.url.clone())) {
+                    Ok(engine) => {
+                        metadata::record_object(engine, source, &step.object_record()).await
+                    }
+                    Err(e) => Err(e),
+                };
+            }
+
+            match result {
+                Ok(()) => report.finish(SaveStatus::Created, 1, start.elapsed()),
+                Err(error) => {
+                    eprintln!("Failed to save \"{}\"", step.name);
+                    report.finish(SaveStatus::Failed, 1, start.elapsed());
+                    report.error = Some(error.to_string());
+                    failed.insert(key);
+                    failure = Some(StepFailure {
+                        loc: step.loc.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+    } else {
+        // Each step starts as soon as everything it depends on has been saved. If a step fails,
+        // the steps that depend on it are blocked, but everything else keeps running.
+        let mut statuses = BTreeMap::new();
+        let mut pending = Vec::new();
+        for step in steps {
+            if skipped.contains(&step.key()) {
+                eprintln!("Skipping \"{}\" because it has not changed", step.name);
+                reports[index[&step.key()]].status = SaveStatus::Skipped;
+                statuses.insert(step.key(), SaveStatus::Skipped);
+            } else {
+                pending.push(step);
+            }
+        }
+
+        let mut join_set = JoinSet::new();
+        loop {
+            let mut waiting = Vec::new();
+            for step in pending {
+                let dependencies = step.dependency_keys();
+                let blocked_by = dependencies
+                    .iter()
+                    .filter(|dep| {
+                        matches!(
+                            statuses.get(*dep),
+                            Some(SaveStatus::Failed | SaveStatus::Blocked)
+                        )
+                    })
+                    .map(|(_, dep)| dep.to_string())
+                    .collect::<Vec<_>>();
+                let ready = dependencies.iter().all(|dep| {
+                    !index.contains_key(dep)
+                        || matches!(
+                            statuses.get(dep),
+                            Some(SaveStatus::Created | SaveStatus::Skipped)
+                        )
+                });
+
+                if !blocked_by.is_empty() {
+                    eprintln!(
+                        "Skipping \"{}\" because it depends on {}, which could not be saved",
+                        step.name,
+                        blocked_by.join(", ")
+                    );
+                    reports[index[&step.key()]].blocked_by = blocked_by;
+                    statuses.insert(step.key(), SaveStatus::Blocked);
+                } else if ready {
+                    execute_step(&mut join_set, ctx_pool, step, options);
+                } else {
+                    waiting.push(step);
+                }
+            }
+            pending = waiting;
+
+            let StepOutcome {
+                step,
+                mut result,
+                attempts,
+                duration,
+            } = match join_set.join_next().await {
+                Some(outcome) => outcome.expect("Failed to join task"),
+                None => break,
+            };
+
+            if result.is_ok() {
+                result = match ctx.sql_engine(Some(step.url.clone())) {
+                    Ok(engine) => {
+                        metadata::record_object(engine, source, &step.object_record()).await
+                    }
+                    Err(e) => Err(e),
+                };
+            }
+
+            let report = &mut reports[index[&step.key()]];
+            match result {
+                Ok(()) => {
+                    report.finish(SaveStatus::Created, attempts, duration);
+                    statuses.insert(step.key(), SaveStatus::Created);
+                }
+                Err(error) => {
+                    eprintln!("Failed to save \"{}\"", step.name);
+                    report.finish(SaveStatus::Failed, attempts, duration);
+                    report.error = Some(error.to_string());
+                    statuses.insert(step.key(), SaveStatus::Failed);
+                    if failure.is_none() {
+                        failure = Some(StepFailure {
+                            loc: step.loc.clone(),
+                            error,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // Objects are only pruned if everything else was saved, so that a failed run does not leave
+    // the database with fewer objects than before. The objects to drop come from the metadata, so
+    // that databases that no longer have any exports are cleaned up too.
+    if options.prune && failure.is_none() {
+        for (url, records) in previous.iter() {
+            let loc = &targets[url];
+            for (name, record) in records.iter() {
+                if current.contains(&(url.clone(), name.clone())) {
+                    continue;
+                }
+
+                eprintln!(
+                    "Dropping {} \"{}\" from {} because it is no longer exported",
+                    record.kind.as_str(),
+                    name,
+                    url
+                );
+                let engine = ctx
+                    .sql_engine(Some(url.clone()))
+                    .context(RuntimeSnafu { loc: loc.clone() })?;
+                metadata::drop_object(engine, source, record)
+                    .await
+                    .context(RuntimeSnafu { loc: loc.clone() })?;
+            }
+        }
+    }
+
+    Ok((reports, failure))
 }
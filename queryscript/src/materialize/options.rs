@@ -0,0 +1,127 @@
+// Applies the layout options of a materialized expression (the order of its rows, its partitions
+// and its indexes) to the DDL that saves it.
+use sqlparser::ast as sqlast;
+
+use crate::compile::schema::{Ident, MaterializeOptions};
+use crate::compile::sql::{create_table_as, select_star_from};
+use crate::runtime::Result;
+
+use super::metadata::{parse_statement, quote_ident};
+
+// Databases do not have partitions, so a table's partitioning columns cluster its rows instead:
+// they are sorted on before the `order_by` columns, so that the rows of each partition are stored
+// together.
+pub fn sort_columns(options: &MaterializeOptions) -> Vec<Ident> {
+    options
+        .partition_by
+        .iter()
+        .chain(options.order_by.iter())
+        .cloned()
+        .collect()
+}
+
+pub fn order_by_clause(columns: &[Ident]) -> String {
+    if columns.is_empty() {
+        return String::new();
+    }
+    format!(
+        " ORDER BY {}",
+        columns
+            .iter()
+            .map(|c| quote_ident(c.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+pub fn ordered_query(query: sqlast::Query, columns: &[Ident]) -> sqlast::Query {
+    if columns.is_empty() {
+        return query;
+    }
+
+    let mut ordered = select_star_from(sqlast::TableFactor::Derived {
+        lateral: false,
+        subquery: Box::new(query),
+        alias: Some(sqlast::TableAlias {
+            name: sqlast::Ident::new("__qs_ordered"),
+            columns: Vec::new(),
+        }),
+    });
+    ordered.order_by = columns
+        .iter()
+        .map(|c| sqlast::OrderByExpr {
+            expr: sqlast::Expr::Identifier(sqlast::Located::new(c.into(), None)),
+            asc: None,
+            nulls_first: None,
+        })
+        .collect();
+    ordered
+}
+
+pub fn create_indexes(
+    table: &Ident,
+    options: &MaterializeOptions,
+) -> Result<Vec<sqlast::Statement>> {
+    options
+        .indexes
+        .iter()
+        .map(|columns| {
+            let index_name = format!(
+                "__qs_idx_{}_{}",
+                table,
+                columns
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join("_")
+            );
+            parse_statement(&format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+                quote_ident(&index_name),
+                quote_ident(table.as_str()),
+                columns
+                    .iter()
+                    .map(|c| quote_ident(c.as_str()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+        .collect()
+}
+
+// Loaded data cannot be sorted as it is written, so it is first loaded into a temporary table, and
+// then copied into the target table in order.
+pub struct OrderedLoad {
+    pub staging_table: Ident,
+    pub statements: Vec<sqlast::Statement>,
+}
+
+pub fn ordered_load(table: &Ident, columns: &[Ident]) -> Result<Option<OrderedLoad>> {
+    if columns.is_empty() {
+        return Ok(None);
+    }
+
+    let staging_table: Ident = format!("__qs_load_{}", table).into();
+    let copy = create_table_as(
+        table.into(),
+        ordered_query(
+            select_star_from(sqlast::TableFactor::Table {
+                name: (&staging_table).into(),
+                alias: None,
+                args: None,
+                with_hints: vec![],
+            }),
+            columns,
+        ),
+        false,
+    );
+    let drop = parse_statement(&format!(
+        "DROP TABLE {}",
+        quote_ident(staging_table.as_str())
+    ))?;
+
+    Ok(Some(OrderedLoad {
+        staging_table,
+        statements: vec![copy, drop],
+    }))
+}
@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PlanAction {
+    CreateView,
+    CreateTable,
+    LoadTable,
+    WriteFile,
+}
+
+impl fmt::Display for PlanAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanAction::CreateView => write!(f, "create or replace view"),
+            PlanAction::CreateTable => write!(f, "create or replace table"),
+            PlanAction::LoadTable => write!(f, "load table"),
+            PlanAction::WriteFile => write!(f, "write file"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PlanStep {
+    pub name: String,
+    pub target: String,
+    pub action: PlanAction,
+    pub dependencies: Vec<String>,
+    pub ddl: String,
+    // For incremental materializations, the DDL that runs instead of `ddl` if the table already
+    // exists.
+    pub incremental_ddl: Option<String>,
+    pub comment: Option<String>,
+    // Whether the object has not changed since it was last saved, in which case `save_views` skips
+    // it.
+    pub unchanged: bool,
+}
+
+/// The set of objects that `save_views` would create, in an order that respects their
+/// dependencies.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Plan {
+    pub steps: Vec<PlanStep>,
+}
+
+impl Plan {
+    pub fn new(steps: Vec<PlanStep>) -> Plan {
+        Plan {
+            steps: topological_order(
+                steps,
+                |step| (step.target.clone(), step.name.clone()),
+                |step| {
+                    step.dependencies
+                        .iter()
+                        .map(|dep| (step.target.clone(), dep.clone()))
+                        .collect()
+                },
+            ),
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        // Nodes are identified by their target as well as their name, since objects with the same
+        // name can be saved to different targets.
+        let node = |target: &str, name: &str| format!("{}/{}", target, name);
+        let mut ret = String::from("digraph plan {\n");
+        for step in self.steps.iter() {
+            let action = if step.unchanged {
+                "skip (unchanged)".to_string()
+            } else {
+                step.action.to_string()
+            };
+            ret.push_str(&format!(
+                "    {:?} [label={:?}];\n",
+                node(&step.target, &step.name),
+                format!("{}\n{}", step.name, action)
+            ));
+        }
+        for step in self.steps.iter() {
+            for dep in step.dependencies.iter() {
+                ret.push_str(&format!(
+                    "    {:?} -> {:?};\n",
+                    node(&step.target, dep),
+                    node(&step.target, &step.name)
+                ));
+            }
+        }
+        ret.push_str("}\n");
+        ret
+    }
+}
+
+// Orders the items so that each one comes after its dependencies, breaking ties by key so that the
+// output is stable.
+pub(super) fn topological_order<K: Ord + Clone, T>(
+    items: Vec<T>,
+    key: impl Fn(&T) -> K,
+    dependencies: impl Fn(&T) -> Vec<K>,
+) -> Vec<T> {
+    let mut remaining = items
+        .into_iter()
+        .map(|item| (key(&item), item))
+        .collect::<BTreeMap<_, _>>();
+    let mut done = BTreeSet::new();
+    let mut ordered = Vec::new();
+    while !remaining.is_empty() {
+        let ready = remaining
+            .iter()
+            .filter(|(_, item)| {
+                dependencies(item)
+                    .iter()
+                    .all(|dep| done.contains(dep) || !remaining.contains_key(dep))
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        // This should never happen (references between views cannot be cyclic), but if it does,
+        // emit the remaining items as-is rather than looping forever.
+        let ready = if ready.is_empty() {
+            remaining.keys().cloned().collect()
+        } else {
+            ready
+        };
+
+        for key in ready {
+            ordered.push(remaining.remove(&key).unwrap());
+            done.insert(key);
+        }
+    }
+    ordered
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.steps.is_empty() {
+            return writeln!(f, "Nothing to save");
+        }
+
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            if step.unchanged {
+                writeln!(
+                    f,
+                    "{}. skip \"{}\" in {}, because it has not changed since it was last saved",
+                    i + 1,
+                    step.name,
+                    step.target
+                )?;
+                continue;
+            }
+            writeln!(
+                f,
+                "{}. {} \"{}\" in {}",
+                i + 1,
+                step.action,
+                step.name,
+                step.target
+            )?;
+            if !step.dependencies.is_empty() {
+                writeln!(f, "   depends on: {}", step.dependencies.join(", "))?;
+            }
+            if let Some(comment) = &step.comment {
+                writeln!(f, "   comment: {}", comment)?;
+            }
+            writeln!(f, "   {};", step.ddl.replace('\n', "\n   "))?;
+            if let Some(incremental_ddl) = &step.incremental_ddl {
+                writeln!(f, "   -- or, if \"{}\" already exists:", step.name)?;
+                writeln!(f, "   {};", incremental_ddl.replace('\n', "\n   "))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+// A summary of what `save_views` did to each object, which is printed at the end of a run, and can
+// be serialized as a manifest for schedulers to consume.
+use std::fmt;
+use std::time::Duration;
+
+use crate::compile::{CompileError, Result};
+
+use super::PlanAction;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SaveStatus {
+    Created,
+    // The object was not recreated, because it has not changed since it was last saved.
+    Skipped,
+    Failed,
+    // The object was not saved, because something it depends on failed.
+    Blocked,
+    // The object was saved, but the transaction it was saved in was rolled back.
+    RolledBack,
+    // The object was not saved, because an unrelated object failed first, and aborted the
+    // transaction that it would have been saved in.
+    Aborted,
+}
+
+impl SaveStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SaveStatus::Created => "created",
+            SaveStatus::Skipped => "skipped",
+            SaveStatus::Failed => "failed",
+            SaveStatus::Blocked => "blocked",
+            SaveStatus::RolledBack => "rolled back",
+            SaveStatus::Aborted => "aborted",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StepReport {
+    pub name: String,
+    pub target: String,
+    pub action: PlanAction,
+    pub status: SaveStatus,
+    pub attempts: u32,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+    // For blocked objects, the failed (or blocked) objects that they depend on.
+    pub blocked_by: Vec<String>,
+}
+
+impl StepReport {
+    pub(super) fn new(name: String, target: String, action: PlanAction) -> StepReport {
+        StepReport {
+            name,
+            target,
+            action,
+            status: SaveStatus::Blocked,
+            attempts: 0,
+            duration_ms: 0,
+            error: None,
+            blocked_by: Vec::new(),
+        }
+    }
+
+    pub(super) fn finish(&mut self, status: SaveStatus, attempts: u32, duration: Duration) {
+        self.status = status;
+        self.attempts = attempts;
+        self.duration_ms = duration.as_millis() as u64;
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SaveReport {
+    pub source: String,
+    pub atomic: bool,
+    pub success: bool,
+    // Seconds since the Unix epoch.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    // In atomic mode, the number of times the transaction was attempted.
+    pub attempts: u32,
+    pub steps: Vec<StepReport>,
+
+    // The first error that caused the run to fail.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(super) error: Option<CompileError>,
+}
+
+impl SaveReport {
+    pub fn count(&self, status: SaveStatus) -> usize {
+        self.steps.iter().filter(|s| s.status == status).count()
+    }
+
+    /// Returns the error that caused the run to fail, if any.
+    pub fn into_result(self) -> Result<()> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for SaveReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Finished saving {} in {}ms: {} created, {} skipped, {} failed, {} blocked",
+            self.source,
+            self.duration_ms,
+            self.count(SaveStatus::Created),
+            self.count(SaveStatus::Skipped),
+            self.count(SaveStatus::Failed),
+            self.count(SaveStatus::Blocked),
+        )?;
+        let rolled_back = self.count(SaveStatus::RolledBack);
+        if rolled_back > 0 {
+            write!(f, ", {} rolled back", rolled_back)?;
+        }
+        let aborted = self.count(SaveStatus::Aborted);
+        if aborted > 0 {
+            write!(f, ", {} aborted", aborted)?;
+        }
+        writeln!(f)?;
+
+        for step in self.steps.iter() {
+            write!(
+                f,
+                "  {:<11} \"{}\" in {}",
+                step.status.as_str(),
+                step.name,
+                step.target
+            )?;
+            match step.status {
+                SaveStatus::Created | SaveStatus::Failed | SaveStatus::RolledBack => {
+                    write!(f, " ({}ms", step.duration_ms)?;
+                    if step.attempts > 1 {
+                        write!(f, ", {} attempts", step.attempts)?;
+                    }
+                    write!(f, ")")?;
+                }
+                _ => {}
+            }
+            if !step.blocked_by.is_empty() {
+                write!(f, " (depends on: {})", step.blocked_by.join(", "))?;
+            }
+            if let Some(error) = &step.error {
+                write!(f, ": {}", error)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -7,9 +7,39 @@ use crate::ast::{Location, Pretty};
 use crate::error::MultiError;
 use colored::*;
 
+// A secondary location that helps explain an error, e.g. where a conflicting definition lives.
+#[derive(Clone, Debug)]
+pub struct RelatedLocation {
+    pub location: ErrorLocation,
+    pub message: String,
+}
+
+impl RelatedLocation {
+    pub fn new(location: ErrorLocation, message: &str) -> RelatedLocation {
+        RelatedLocation {
+            location,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn pretty(&self) -> String {
+        format!(
+            "{}{} {} {}",
+            self.location.pretty(),
+            ":".white().bold(),
+            "note:".bright_cyan(),
+            self.message
+        )
+    }
+}
+
 pub trait PrettyError: ToString {
     fn location(&self) -> ErrorLocation;
 
+    fn related(&self) -> Vec<RelatedLocation> {
+        Vec::new()
+    }
+
     fn pretty(&self) -> String {
         format!(
             "{}{} {} {}",
@@ -21,24 +51,35 @@ pub trait PrettyError: ToString {
     }
 
     fn pretty_with_code(&self, code: &BTreeMap<String, String>) -> String {
-        let pretty = self.pretty();
-        let location = self.location();
-        let file = location.file();
-        if let Some(file) = file {
-            if let Some(contents) = code.get(&file) {
-                if let Some(annotated) = self.location().annotate(contents) {
-                    return format!("{}\n\n{}", pretty, annotated);
-                }
+        let mut pretty = annotate_with_code(self.pretty(), &self.location(), code);
+        for related in self.related() {
+            pretty += "\n\n";
+            pretty += annotate_with_code(related.pretty(), &related.location, code).as_str();
+        }
+        pretty
+    }
+}
+
+fn annotate_with_code(
+    pretty: String,
+    location: &ErrorLocation,
+    code: &BTreeMap<String, String>,
+) -> String {
+    if let Some(file) = location.file() {
+        if let Some(contents) = code.get(&file) {
+            if let Some(annotated) = location.annotate(contents) {
+                return format!("{}\n\n{}", pretty, annotated);
             }
         }
-        return pretty;
     }
+    pretty
 }
 
 #[derive(Clone, Debug)]
 pub struct FormattedError {
     pub location: ErrorLocation,
     pub text: String,
+    pub related: Vec<RelatedLocation>,
 }
 
 impl fmt::Display for FormattedError {
@@ -51,6 +92,10 @@ impl PrettyError for FormattedError {
     fn location(&self) -> ErrorLocation {
         self.location.clone()
     }
+
+    fn related(&self) -> Vec<RelatedLocation> {
+        self.related.clone()
+    }
 }
 
 #[derive(Debug, Snafu)]
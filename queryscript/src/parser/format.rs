@@ -0,0 +1,1044 @@
+// Formats QueryScript files. The QueryScript statements are laid out from the AST, while the SQL
+// inside them is laid out from its tokens, since the SQL AST's Display loses the way a query was
+// written (including its comments). Within SQL only the whitespace between tokens changes, and
+// comments stay next to the statement, record field, or token they were written next to, so
+// formatting a file a second time leaves it unchanged.
+use std::ops::Range;
+
+use super::error::Result;
+use super::parser::{parse_schema, Cursor};
+use crate::ast::{
+    Constraint, Expr, ExprBody, FnBody, FnDef, Ident, ImportList, Located, Location,
+    MaterializeArgs, MaterializeKind, NameAndType, Path, SourceLocation, Stmt, StmtBody,
+    StructEntry, Type, TypeBody,
+};
+
+const WIDTH: usize = 100;
+const INDENT: &str = "    ";
+
+// Words after which a `(` does not start a function's arguments, and a `-` or `+` is unary.
+const KEYWORDS: &[&str] = &[
+    "ALL",
+    "AND",
+    "ANY",
+    "AS",
+    "BETWEEN",
+    "BY",
+    "CASE",
+    "DISTINCT",
+    "ELSE",
+    "EXCEPT",
+    "EXCLUDE",
+    "EXISTS",
+    "FILTER",
+    "FROM",
+    "GROUP",
+    "HAVING",
+    "ILIKE",
+    "IN",
+    "INTERSECT",
+    "INTERVAL",
+    "IS",
+    "JOIN",
+    "LATERAL",
+    "LIKE",
+    "LIMIT",
+    "NOT",
+    "OFFSET",
+    "ON",
+    "OR",
+    "OVER",
+    "QUALIFY",
+    "SELECT",
+    "SOME",
+    "THEN",
+    "UNION",
+    "USING",
+    "VALUES",
+    "WHEN",
+    "WHERE",
+    "WINDOW",
+    "WITH",
+];
+
+const JOIN_MODIFIERS: &[&str] = &[
+    "ANTI",
+    "ASOF",
+    "CROSS",
+    "FULL",
+    "INNER",
+    "LEFT",
+    "NATURAL",
+    "OUTER",
+    "POSITIONAL",
+    "RIGHT",
+    "SEMI",
+];
+
+// Multi-character operators, longest first.
+const OPERATORS: &[&str] = &[
+    "->>", "::", "->", "=>", "<=", ">=", "<>", "!=", "||", "<<", ">>",
+];
+
+/// Formats a file's source. Files that don't parse can't be formatted, and return the parser's
+/// errors instead.
+pub fn format_schema(file: &str, text: &str) -> Result<String> {
+    let schema = parse_schema(file, text).as_result()?;
+
+    let lexemes = lex(text);
+    let mut formatter = Formatter {
+        emitted: vec![false; lexemes.len()],
+        lexemes,
+        next: 0,
+        out: String::new(),
+    };
+    formatter.stmts(&schema.stmts, 0);
+    let eof = Location {
+        line: u64::MAX,
+        column: u64::MAX,
+    };
+    formatter.comments(&eof, 0);
+    Ok(formatter.out)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Word,
+    // A string literal or quoted identifier, e.g. `'abc'` or `"Name"`.
+    Quoted,
+    Number,
+    Punct,
+    Comment,
+}
+
+#[derive(Clone, Debug)]
+struct Lexeme {
+    kind: Kind,
+    text: String,
+    start: Location,
+    // The number of line breaks between the previous lexeme and this one.
+    newlines: usize,
+}
+
+impl Lexeme {
+    fn is_line_comment(&self) -> bool {
+        self.kind == Kind::Comment && self.text.starts_with("--")
+    }
+
+    fn is_keyword(&self) -> bool {
+        self.kind == Kind::Word && KEYWORDS.contains(&self.text.to_uppercase().as_str())
+    }
+
+    fn is_punct(&self, text: &str) -> bool {
+        self.kind == Kind::Punct && self.text == text
+    }
+}
+
+fn lex(text: &str) -> Vec<Lexeme> {
+    let mut cursor = Cursor::new(text);
+    let mut lexemes: Vec<Lexeme> = Vec::new();
+    let mut newlines = 0;
+    while let Some(c) = cursor.peek(0) {
+        if c.is_whitespace() {
+            if c == '\n' {
+                newlines += 1;
+            }
+            cursor.advance(1);
+            continue;
+        }
+
+        let start = cursor.location();
+        let is_qualifier = lexemes
+            .last()
+            .map_or(false, |l| l.kind != Kind::Punct || l.text == ")");
+        let (kind, text) = if cursor.starts_with("--") {
+            let comment = cursor.take(2, "\n", false);
+            (Kind::Comment, comment.trim_end().to_string())
+        } else if cursor.starts_with("/*") {
+            (Kind::Comment, cursor.take(2, "*/", true))
+        } else if c == '\'' || c == '"' || c == '`' {
+            (Kind::Quoted, take_quoted(&mut cursor, c))
+        } else if c.is_ascii_digit()
+            || (c == '.' && !is_qualifier && cursor.peek(1).map_or(false, |c| c.is_ascii_digit()))
+        {
+            (Kind::Number, take_number(&mut cursor))
+        } else if c.is_alphabetic() || c == '_' || c == '$' || c == '@' {
+            let mut word = take_while(&mut cursor, |c| c.is_alphanumeric() || c == '_' || c == '$');
+            // A prefixed string, e.g. `E'\n'`
+            if matches!(word.to_uppercase().as_str(), "B" | "E" | "N" | "X")
+                && cursor.peek(0) == Some('\'')
+            {
+                word.push_str(&take_quoted(&mut cursor, '\''));
+                (Kind::Quoted, word)
+            } else {
+                (Kind::Word, word)
+            }
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| cursor.starts_with(op))
+                .map_or(1, |op| op.len());
+            let mut punct = String::new();
+            for _ in 0..op {
+                punct.extend(cursor.peek(0));
+                cursor.advance(1);
+            }
+            (Kind::Punct, punct)
+        };
+
+        lexemes.push(Lexeme {
+            kind,
+            text,
+            start,
+            newlines,
+        });
+        newlines = 0;
+    }
+    lexemes
+}
+
+fn take_while<F: Fn(char) -> bool>(cursor: &mut Cursor, f: F) -> String {
+    let mut ret = String::new();
+    while let Some(c) = cursor.peek(0).filter(|c| f(*c)) {
+        ret.push(c);
+        cursor.advance(1);
+    }
+    ret
+}
+
+// A quoted string, in which the quote is escaped by doubling it.
+fn take_quoted(cursor: &mut Cursor, quote: char) -> String {
+    let quote = quote.to_string();
+    let mut ret = cursor.take(1, &quote, true);
+    while cursor.starts_with(&quote) {
+        ret.push_str(&cursor.take(1, &quote, true));
+    }
+    ret
+}
+
+fn take_number(cursor: &mut Cursor) -> String {
+    let mut ret = String::new();
+    while let Some(c) = cursor.peek(0) {
+        let exponent = (c == 'e' || c == 'E')
+            && matches!(cursor.peek(1), Some('+') | Some('-'))
+            && cursor.peek(2).map_or(false, |c| c.is_ascii_digit());
+        if exponent {
+            ret.extend(cursor.peek(0));
+            ret.extend(cursor.peek(1));
+            cursor.advance(2);
+        } else if c.is_alphanumeric() || c == '.' || c == '_' {
+            ret.push(c);
+            cursor.advance(1);
+        } else {
+            break;
+        }
+    }
+    ret
+}
+
+struct Formatter {
+    lexemes: Vec<Lexeme>,
+    // Whether each comment has been written out.
+    emitted: Vec<bool>,
+    // Every comment before this lexeme has been written out.
+    next: usize,
+    out: String,
+}
+
+impl Formatter {
+    fn stmts(&mut self, stmts: &[Stmt], indent: usize) {
+        for stmt in stmts.iter() {
+            if matches!(stmt.body, StmtBody::Noop) {
+                continue;
+            }
+            self.comments(&stmt.start, indent);
+            self.blank_line_before(&stmt.start);
+            self.out.push_str(&INDENT.repeat(indent));
+            self.stmt(stmt, indent);
+            self.trailing(&stmt.end);
+            self.out.push('\n');
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, indent: usize) {
+        let export = if stmt.export { "export " } else { "" };
+        match &stmt.body {
+            StmtBody::Noop | StmtBody::Unparsed => {}
+            StmtBody::Expr(expr) => self.assign(String::new(), expr, ";", indent),
+            StmtBody::Import { path, list, args } => {
+                // `export` can stand in for `import`, e.g. `export * from schema;`
+                let mut text = if stmt.export { "export " } else { "import " }.to_string();
+                match list {
+                    ImportList::None => {}
+                    ImportList::Star => text.push_str("* from "),
+                    ImportList::Items(items) => {
+                        let items = items.iter().map(|i| self.path(i)).collect::<Vec<_>>();
+                        text.push_str(&format!("{} from ", items.join(", ")));
+                    }
+                }
+                text.push_str(&self.path(path));
+                if let Some(args) = args {
+                    let args = args
+                        .iter()
+                        .map(|arg| match &arg.expr {
+                            Some(expr) => format!(
+                                "{}: {}",
+                                self.ident(&arg.name),
+                                self.inline_expr(expr, indent)
+                            ),
+                            None => self.ident(&arg.name),
+                        })
+                        .collect::<Vec<_>>();
+                    text.push_str(&format!(" {{ {} }}", args.join(", ")));
+                }
+                text.push(';');
+                self.out.push_str(&text);
+            }
+            StmtBody::TypeDef {
+                name,
+                generics,
+                def,
+            } => {
+                let mut text = format!("{}type {}", export, self.ident(name));
+                if !generics.is_empty() {
+                    let generics = generics.iter().map(|g| self.ident(g)).collect::<Vec<_>>();
+                    text.push_str(&format!("<{}>", generics.join(", ")));
+                }
+                text.push(' ');
+                text.push_str(&self.type_(def, indent, true));
+                // A struct's closing brace ends the statement
+                if !matches!(def.body, TypeBody::Struct(_)) {
+                    text.push(';');
+                }
+                self.out.push_str(&text);
+            }
+            StmtBody::FnDef(def) => self.fn_def(export, def, &stmt.end, indent),
+            StmtBody::Let {
+                name,
+                type_,
+                body,
+                materialize,
+            } => {
+                let mut head = export.to_string();
+                match materialize {
+                    Some(args) => head.push_str(&format!("{} ", mat_args(args))),
+                    None => head.push_str("let "),
+                }
+                head.push_str(&self.ident(name));
+                if let Some(type_) = type_ {
+                    head.push(' ');
+                    head.push_str(&self.type_(type_, indent, false));
+                }
+                head.push_str(" =");
+                self.assign(head, body, ";", indent);
+            }
+            StmtBody::Extern { name, type_ } => {
+                let text = format!(
+                    "{}extern {} {};",
+                    export,
+                    self.ident(name),
+                    self.type_(type_, indent, false)
+                );
+                self.out.push_str(&text);
+            }
+            StmtBody::Test { name, body } => {
+                let text = format!("test {} {{\n", self.ident(name));
+                self.out.push_str(&text);
+                self.stmts(body, indent + 1);
+                self.comments(&stmt.end, indent + 1);
+                self.out.push_str(&format!("{}}}", INDENT.repeat(indent)));
+            }
+            StmtBody::Expect { actual, expected } => {
+                let text = format!(
+                    "expect {} equals {};",
+                    self.inline_expr(actual, indent),
+                    self.inline_expr(expected, indent)
+                );
+                self.out.push_str(&text);
+            }
+            StmtBody::Assert { expr, message } => {
+                let mut text = format!("assert {}", self.inline_expr(expr, indent));
+                if let Some(message) = message {
+                    text.push_str(&format!(", {}", quote(message)));
+                }
+                text.push(';');
+                self.out.push_str(&text);
+            }
+        }
+    }
+
+    fn fn_def(&mut self, export: &str, def: &FnDef, end: &Location, indent: usize) {
+        let mut sig = format!("{}fn {}", export, self.ident(&def.name));
+        if !def.generics.is_empty() {
+            let generics = def
+                .generics
+                .iter()
+                .map(
+                    |g| match def.bounds.iter().find(|b| b.name.get() == g.get()) {
+                        Some(bound) => {
+                            format!(
+                                "{}: {}",
+                                self.ident(g),
+                                self.type_(&bound.def, indent, false)
+                            )
+                        }
+                        None => self.ident(g),
+                    },
+                )
+                .collect::<Vec<_>>();
+            sig.push_str(&format!("<{}>", generics.join(", ")));
+        }
+        let args = def
+            .args
+            .iter()
+            .map(|arg| {
+                format!(
+                    "{} {}",
+                    self.ident(&arg.name),
+                    self.type_(&arg.type_, indent, false)
+                )
+            })
+            .collect::<Vec<_>>();
+        sig.push_str(&format!("({})", args.join(", ")));
+        if let Some(ret) = &def.ret {
+            sig.push_str(&format!(" -> {}", self.type_(ret, indent, false)));
+        }
+
+        match &def.body {
+            FnBody::Native => self.out.push_str(&format!("{} = native;", sig)),
+            FnBody::SQL => self.out.push_str(&format!("{} = sql;", sig)),
+            FnBody::Expr(expr) => {
+                let range = self.expr_range(expr);
+                let tokens = &self.lexemes[range.clone()];
+                let text = format!("{} {{ {} }}", sig, inline(tokens, indent + 1));
+                if !has_comments(tokens) && fits(indent, &text, "") {
+                    self.out.push_str(&text);
+                } else {
+                    let body = block(tokens, is_query(expr), indent + 1);
+                    self.out.push_str(&format!("{} {{\n{}\n", sig, body));
+                    self.mark(range);
+                    self.comments(end, indent + 1);
+                    self.out.push_str(&format!("{}}}", INDENT.repeat(indent)));
+                }
+            }
+        }
+    }
+
+    // Writes `<head> <expr><end>`, moving a query that doesn't fit onto the following lines.
+    fn assign(&mut self, head: String, expr: &Expr, end: &str, indent: usize) {
+        let range = self.expr_range(expr);
+        let tokens = &self.lexemes[range.clone()];
+        let query = is_query(expr);
+        let inline = inline(tokens, indent + 1);
+        let text = if head.is_empty() {
+            inline
+        } else {
+            format!("{} {}", head, inline)
+        };
+
+        let text = if !has_comments(tokens) && (!query || fits(indent, &text, end)) {
+            text
+        } else if head.is_empty() {
+            block(tokens, query, indent).trim_start().to_string()
+        } else {
+            format!("{}\n{}", head, block(tokens, query, indent + 1))
+        };
+        self.out.push_str(&text);
+        self.out.push_str(end);
+        self.mark(range);
+    }
+
+    fn inline_expr(&mut self, expr: &Expr, indent: usize) -> String {
+        let range = self.expr_range(expr);
+        let text = inline(&self.lexemes[range.clone()], indent + 1);
+        self.mark(range);
+        text
+    }
+
+    fn type_(&mut self, type_: &Type, indent: usize, block: bool) -> String {
+        if !block {
+            if let Some(text) = self.inline_type(type_) {
+                if fits(indent, &text, "") {
+                    return text;
+                }
+            }
+        }
+        match &type_.body {
+            TypeBody::Reference(path) => self.path(path),
+            TypeBody::Struct(entries) => self.struct_(type_, entries, indent),
+            TypeBody::List(inner) => format!("[{}]", self.type_(inner, indent, false)),
+            TypeBody::Exclude { inner, excluded } => {
+                let excluded = excluded.iter().map(|e| self.ident(e)).collect::<Vec<_>>();
+                format!(
+                    "{} exclude {}",
+                    self.type_(inner, indent, block),
+                    excluded.join(", ")
+                )
+            }
+            TypeBody::Generic(path, args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.type_(a, indent, false))
+                    .collect::<Vec<_>>();
+                format!("{}<{}>", self.path(path), args.join(", "))
+            }
+            TypeBody::Fn { args, ret } => {
+                let args = args
+                    .iter()
+                    .map(|a| self.type_(a, indent, false))
+                    .collect::<Vec<_>>();
+                format!(
+                    "fn({}) -> {}",
+                    args.join(", "),
+                    self.type_(ret, indent, false)
+                )
+            }
+        }
+    }
+
+    // A type on a single line, unless there are comments inside of it.
+    fn inline_type(&self, type_: &Type) -> Option<String> {
+        if self.has_comments(&type_.start, &type_.end) {
+            return None;
+        }
+        Some(match &type_.body {
+            TypeBody::Reference(path) => self.path(path),
+            TypeBody::Struct(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|entry| match entry {
+                        StructEntry::NameAndType(field) => Some(format!(
+                            "{} {}{}",
+                            self.ident(&field.name),
+                            self.inline_type(&field.def)?,
+                            self.constraints(field)
+                        )),
+                        StructEntry::Include(path) => Some(format!("...{}", self.path(path))),
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                format!("{{{}}}", entries.join(", "))
+            }
+            TypeBody::List(inner) => format!("[{}]", self.inline_type(inner)?),
+            TypeBody::Exclude { inner, excluded } => {
+                let excluded = excluded.iter().map(|e| self.ident(e)).collect::<Vec<_>>();
+                format!(
+                    "{} exclude {}",
+                    self.inline_type(inner)?,
+                    excluded.join(", ")
+                )
+            }
+            TypeBody::Generic(path, args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.inline_type(a))
+                    .collect::<Option<Vec<_>>>()?;
+                format!("{}<{}>", self.path(path), args.join(", "))
+            }
+            TypeBody::Fn { args, ret } => {
+                let args = args
+                    .iter()
+                    .map(|a| self.inline_type(a))
+                    .collect::<Option<Vec<_>>>()?;
+                format!("fn({}) -> {}", args.join(", "), self.inline_type(ret)?)
+            }
+        })
+    }
+
+    // A struct with one field per line, each preceded by its comments.
+    fn struct_(&mut self, type_: &Type, entries: &[StructEntry], indent: usize) -> String {
+        let outer = std::mem::replace(&mut self.out, "{\n".to_string());
+        for entry in entries.iter() {
+            let (text, start, end) = match entry {
+                StructEntry::NameAndType(field) => {
+                    let text = format!(
+                        "{} {}{}",
+                        self.ident(&field.name),
+                        self.type_(&field.def, indent + 1, false),
+                        self.constraints(field)
+                    );
+                    for constraint in field.constraints.iter() {
+                        if let Some(range) = constraint.location().range() {
+                            let range = self.range(&range.start, &range.end);
+                            self.mark(range);
+                        }
+                    }
+                    (text, start(field.name.location()), field_end(field))
+                }
+                StructEntry::Include(path) => (
+                    format!("...{}", self.path(path)),
+                    path.first().and_then(|p| start(p.location())),
+                    path.last()
+                        .and_then(|p| p.location().range())
+                        .map(|r| r.end),
+                ),
+            };
+            if let Some(start) = &start {
+                self.comments(start, indent + 1);
+                self.blank_line_before(start);
+            }
+            self.out
+                .push_str(&format!("{}{},", INDENT.repeat(indent + 1), text));
+            if let Some(end) = &end {
+                self.trailing(end);
+            }
+            self.out.push('\n');
+        }
+        self.comments(&type_.end, indent + 1);
+        self.out.push_str(&format!("{}}}", INDENT.repeat(indent)));
+        std::mem::replace(&mut self.out, outer)
+    }
+
+    fn constraints(&self, field: &NameAndType) -> String {
+        let mut ret = String::new();
+        for constraint in field.constraints.iter() {
+            ret.push(' ');
+            match constraint.location().range() {
+                Some(range) => {
+                    let range = self.range(&range.start, &range.end);
+                    ret.push_str(&inline(&self.lexemes[range], 0));
+                }
+                None => ret.push_str(&match constraint.get() {
+                    Constraint::NotNull => "not null".to_string(),
+                    Constraint::Unique => "unique".to_string(),
+                    Constraint::AcceptedValues(values) => format!(
+                        "in ({})",
+                        values
+                            .iter()
+                            .map(|v| v.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    Constraint::Check(expr) => format!("check ({})", expr),
+                }),
+            }
+        }
+        ret
+    }
+
+    // Identifiers are written as they were in the source, which keeps their quotes.
+    fn ident(&self, ident: &Located<Ident>) -> String {
+        if let Some(start) = start(ident.location()) {
+            let i = self.index(&start);
+            match self.lexemes.get(i) {
+                Some(l) if l.start == start && matches!(l.kind, Kind::Word | Kind::Quoted) => {
+                    return l.text.clone();
+                }
+                _ => {}
+            }
+        }
+        let name = ident.get().to_string();
+        let simple = !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        match simple {
+            true => name,
+            false => format!("\"{}\"", name.replace('"', "\"\"")),
+        }
+    }
+
+    fn path(&self, path: &Path) -> String {
+        path.iter()
+            .map(|p| self.ident(p))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    // Writes out (on their own lines) the comments before `loc` that haven't been written yet.
+    fn comments(&mut self, loc: &Location, indent: usize) {
+        let end = self.index(loc);
+        for i in self.next..end {
+            if self.lexemes[i].kind == Kind::Comment && !self.emitted[i] {
+                self.emitted[i] = true;
+                if self.lexemes[i].newlines > 1 {
+                    self.blank_line();
+                }
+                let text = format!("{}{}\n", INDENT.repeat(indent), self.lexemes[i].text);
+                self.out.push_str(&text);
+            }
+        }
+        self.next = self.next.max(end);
+    }
+
+    // Writes the comments on the same line as (and after) `end`, skipping over the punctuation
+    // that ends a statement or field.
+    fn trailing(&mut self, end: &Location) {
+        let mut i = self.lexemes.partition_point(|l| &l.start <= end);
+        while let Some(lexeme) = self.lexemes.get(i) {
+            if lexeme.start.line != end.line {
+                break;
+            }
+            match lexeme.kind {
+                Kind::Punct if lexeme.text == "," || lexeme.text == ";" => {}
+                Kind::Comment if !self.emitted[i] => {
+                    self.out.push(' ');
+                    self.out.push_str(&lexeme.text);
+                    self.emitted[i] = true;
+                }
+                _ => break,
+            }
+            i += 1;
+        }
+    }
+
+    // Keeps (at most) one blank line wherever the source had one.
+    fn blank_line_before(&mut self, loc: &Location) {
+        match self.lexemes.get(self.index(loc)) {
+            Some(l) if l.newlines > 1 => self.blank_line(),
+            _ => {}
+        }
+    }
+
+    fn blank_line(&mut self) {
+        if !(self.out.is_empty() || self.out.ends_with("{\n") || self.out.ends_with("\n\n")) {
+            self.out.push('\n');
+        }
+    }
+
+    fn mark(&mut self, range: Range<usize>) {
+        for i in range {
+            if self.lexemes[i].kind == Kind::Comment {
+                self.emitted[i] = true;
+            }
+        }
+    }
+
+    fn index(&self, loc: &Location) -> usize {
+        self.lexemes.partition_point(|l| &l.start < loc)
+    }
+
+    // The lexemes from `start` through `end` (inclusive).
+    fn range(&self, start: &Location, end: &Location) -> Range<usize> {
+        self.index(start)..self.lexemes.partition_point(|l| &l.start <= end)
+    }
+
+    // An expression's tokens, along with any comments directly before it.
+    fn expr_range(&self, expr: &Expr) -> Range<usize> {
+        let Range { mut start, end } = self.range(&expr.start, &expr.end);
+        while start > 0 && self.lexemes[start - 1].kind == Kind::Comment && !self.emitted[start - 1]
+        {
+            start -= 1;
+        }
+        start..end
+    }
+
+    fn has_comments(&self, start: &Location, end: &Location) -> bool {
+        has_comments(&self.lexemes[self.range(start, end)])
+    }
+}
+
+fn start(loc: &SourceLocation) -> Option<Location> {
+    loc.range().map(|r| r.start)
+}
+
+fn field_end(field: &NameAndType) -> Option<Location> {
+    match field.constraints.last() {
+        Some(constraint) => constraint.location().range().map(|r| r.end),
+        None => Some(field.def.end.clone()),
+    }
+}
+
+fn is_query(expr: &Expr) -> bool {
+    matches!(expr.body, ExprBody::SQLQuery(_))
+}
+
+fn has_comments(tokens: &[Lexeme]) -> bool {
+    tokens.iter().any(|t| t.kind == Kind::Comment)
+}
+
+fn fits(indent: usize, text: &str, end: &str) -> bool {
+    !text.contains('\n') && INDENT.len() * indent + text.chars().count() + end.len() <= WIDTH
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn mat_args(args: &MaterializeArgs) -> String {
+    let columns = |columns: &Vec<Located<Ident>>| {
+        columns
+            .iter()
+            .map(|c| c.get().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut ret = Vec::new();
+    if let Some(db) = &args.db {
+        ret.push(match &db.body {
+            ExprBody::SQLExpr(expr) => expr.to_string(),
+            ExprBody::SQLQuery(query) => query.to_string(),
+        });
+    }
+    if let Some(file) = &args.file {
+        ret.push(format!("file => {}", quote(file.get())));
+    }
+    if let Some(format) = &args.format {
+        ret.push(format!("format => {}", quote(format.get())));
+    }
+    if let Some(kind) = &args.kind {
+        let kind = match kind.get() {
+            MaterializeKind::Table => "table",
+            MaterializeKind::View => "view",
+        };
+        ret.push(format!("kind => {}", quote(kind)));
+    }
+    if let Some(incremental) = &args.incremental {
+        ret.push(format!(
+            "incremental => {}",
+            quote(&incremental.get().to_string())
+        ));
+    }
+    for (name, value) in [
+        ("unique_key", &args.unique_key),
+        ("order_by", &args.order_by),
+        ("partition_by", &args.partition_by),
+    ] {
+        if !value.is_empty() {
+            ret.push(format!("{} => {}", name, quote(&columns(value))));
+        }
+    }
+    if !args.indexes.is_empty() {
+        let indexes = args.indexes.iter().map(columns).collect::<Vec<_>>();
+        ret.push(format!("indexes => {}", quote(&indexes.join("; "))));
+    }
+    if let Some(comment) = &args.comment {
+        ret.push(format!("comment => {}", quote(comment)));
+    }
+
+    match ret.is_empty() {
+        true => "mat".to_string(),
+        false => format!("mat({})", ret.join(", ")),
+    }
+}
+
+// Joins SQL tokens, with a space between them wherever one is needed. A line comment ends the
+// line, and the tokens after it continue on the next line at `indent`.
+fn inline(tokens: &[Lexeme], indent: usize) -> String {
+    let mut ret = String::new();
+    let mut prev: Option<&Lexeme> = None;
+    let mut last: Option<&Lexeme> = None;
+    let mut unary = false;
+    for token in tokens.iter() {
+        if let Some(prev) = prev {
+            if prev.is_line_comment() {
+                ret.push('\n');
+                ret.push_str(&INDENT.repeat(indent));
+            } else if space(prev, token, unary) {
+                ret.push(' ');
+            }
+        }
+        ret.push_str(&token.text);
+
+        if token.kind != Kind::Comment {
+            unary = (token.is_punct("-") || token.is_punct("+"))
+                && last.map_or(true, |l| match l.kind {
+                    Kind::Punct => !l.is_punct(")") && !l.is_punct("]"),
+                    Kind::Word => l.is_keyword(),
+                    _ => false,
+                });
+            last = Some(token);
+        }
+        prev = Some(token);
+    }
+    ret
+}
+
+fn space(prev: &Lexeme, next: &Lexeme, prev_unary: bool) -> bool {
+    if prev.kind == Kind::Comment || next.kind == Kind::Comment {
+        return true;
+    }
+    let (p, n) = (prev.text.as_str(), next.text.as_str());
+    // Don't turn `- -1` into a comment
+    if (p.ends_with('-') && n.starts_with('-')) || (p.ends_with('/') && n.starts_with('*')) {
+        return true;
+    }
+    if prev_unary || matches!(p, "(" | "[" | "." | "::") {
+        return false;
+    }
+    if next.kind == Kind::Punct && matches!(n, ")" | "]" | "," | "." | ";" | ":" | "::") {
+        return false;
+    }
+
+    // Function calls and subscripts, e.g. `count(*)` and `tags[1]`
+    let callee = match prev.kind {
+        Kind::Word => !prev.is_keyword(),
+        Kind::Quoted => p.starts_with('"') || p.starts_with('`'),
+        _ => false,
+    };
+    match n {
+        "(" => !callee,
+        "[" => !(callee || p == ")" || p == "]"),
+        _ => true,
+    }
+}
+
+// Lays out an expression on its own lines: a query with one clause per line, and anything else
+// on a single line.
+fn block(tokens: &[Lexeme], query: bool, indent: usize) -> String {
+    let start = tokens
+        .iter()
+        .position(|t| t.kind != Kind::Comment)
+        .unwrap_or(tokens.len());
+    let mut lines = tokens[..start]
+        .iter()
+        .map(|c| format!("{}{}", INDENT.repeat(indent), c.text))
+        .collect::<Vec<_>>();
+    if query {
+        lines.extend(clause_lines(&tokens[start..], indent));
+    } else {
+        lines.push(format!(
+            "{}{}",
+            INDENT.repeat(indent),
+            inline(&tokens[start..], indent + 1)
+        ));
+    }
+    lines.join("\n")
+}
+
+#[derive(Default)]
+struct Clause {
+    comments: Vec<Lexeme>,
+    head: Vec<Lexeme>,
+    body: Vec<Lexeme>,
+}
+
+fn clause_lines(tokens: &[Lexeme], indent: usize) -> Vec<String> {
+    let line = |indent: usize, text: &str| format!("{}{}", INDENT.repeat(indent), text);
+
+    let mut lines = Vec::new();
+    for clause in clauses(tokens) {
+        for comment in clause.comments.iter() {
+            lines.push(line(indent, &comment.text));
+        }
+        let head = inline(&clause.head, indent + 1);
+        let body = inline(&clause.body, indent + 1);
+        let text = match (head.is_empty(), body.is_empty()) {
+            (_, true) => head.clone(),
+            (true, false) => body,
+            (false, false) => format!("{} {}", head, body),
+        };
+
+        // Long lists (e.g. of the selected columns) get a line per item
+        let items = items(&clause.body);
+        if !head.is_empty()
+            && (has_comments(&clause.body) || (items.len() > 1 && !fits(indent, &text, ";")))
+        {
+            lines.push(line(indent, &head));
+            for item in items.iter() {
+                let start = item
+                    .iter()
+                    .position(|t| t.kind != Kind::Comment)
+                    .unwrap_or(item.len());
+                for comment in item[..start].iter() {
+                    lines.push(line(indent + 1, &comment.text));
+                }
+                if start < item.len() {
+                    lines.push(line(indent + 1, &inline(&item[start..], indent + 2)));
+                }
+            }
+        } else {
+            lines.push(line(indent, &text));
+        }
+    }
+    lines
+}
+
+// Splits a query into its clauses (`SELECT ...`, `FROM ...`, `LEFT JOIN ...`, `UNION ALL`, and so
+// on), ignoring those in subqueries. The comments before a clause belong to it.
+fn clauses(tokens: &[Lexeme]) -> Vec<Clause> {
+    let mut clauses = vec![Clause::default()];
+    let mut depth = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        if depth == 0 {
+            if let Some(n) = clause_head(tokens, i) {
+                let first = clauses.len() == 1;
+                let current = clauses.last_mut().unwrap();
+                let split = current
+                    .body
+                    .iter()
+                    .rposition(|t| t.kind != Kind::Comment)
+                    .map_or(0, |p| p + 1);
+                let comments = current.body.split_off(split);
+                if first && current.head.is_empty() {
+                    // Anything before the first clause (e.g. `unsafe`) is part of its head
+                    let mut head = std::mem::take(&mut current.body);
+                    head.extend(tokens[i..i + n].iter().cloned());
+                    current.head = head;
+                    current.comments = comments;
+                } else {
+                    clauses.push(Clause {
+                        comments,
+                        head: tokens[i..i + n].to_vec(),
+                        body: Vec::new(),
+                    });
+                }
+                i += n;
+                continue;
+            }
+        }
+
+        let token = &tokens[i];
+        if token.is_punct("(") || token.is_punct("[") {
+            depth += 1;
+        } else if token.is_punct(")") || token.is_punct("]") {
+            depth -= 1;
+        }
+        clauses.last_mut().unwrap().body.push(token.clone());
+        i += 1;
+    }
+    clauses
+}
+
+// The number of tokens in the keyword(s) that start a clause at `tokens[i]`, if any.
+fn clause_head(tokens: &[Lexeme], i: usize) -> Option<usize> {
+    let word = |j: usize| {
+        tokens
+            .get(j)
+            .filter(|t| t.kind == Kind::Word)
+            .map(|t| t.text.to_uppercase())
+    };
+    match word(i)?.as_str() {
+        "SELECT" | "FROM" | "WHERE" | "HAVING" | "QUALIFY" | "WINDOW" | "LIMIT" | "OFFSET"
+        | "FETCH" | "VALUES" | "WITH" => Some(1),
+        "GROUP" | "ORDER" => (word(i + 1).as_deref() == Some("BY")).then(|| 2),
+        "UNION" | "EXCEPT" | "INTERSECT" => {
+            // `SELECT * EXCEPT (a)` is not a set operation
+            if i > 0 && tokens[i - 1].is_punct("*") {
+                return None;
+            }
+            match word(i + 1).as_deref() {
+                Some("ALL") | Some("DISTINCT") => Some(2),
+                _ => Some(1),
+            }
+        }
+        _ => {
+            let mut j = i;
+            while word(j).map_or(false, |w| JOIN_MODIFIERS.contains(&w.as_str())) {
+                j += 1;
+            }
+            (word(j).as_deref() == Some("JOIN")).then(|| j - i + 1)
+        }
+    }
+}
+
+// Splits a clause's body at its top-level commas, each of which stays with the item before it.
+fn items(tokens: &[Lexeme]) -> Vec<Vec<Lexeme>> {
+    let mut items = vec![Vec::new()];
+    let mut depth = 0;
+    for token in tokens.iter() {
+        if token.is_punct("(") || token.is_punct("[") {
+            depth += 1;
+        } else if token.is_punct(")") || token.is_punct("]") {
+            depth -= 1;
+        }
+        items.last_mut().unwrap().push(token.clone());
+        if depth == 0 && token.is_punct(",") {
+            items.push(Vec::new());
+        }
+    }
+    if items.last().map_or(false, |i| i.is_empty()) {
+        items.pop();
+    }
+    items
+}
@@ -1,4 +1,5 @@
 pub mod error;
+pub mod format;
 pub mod parser;
 
 pub use error::ParserError;
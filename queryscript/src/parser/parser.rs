@@ -11,6 +11,7 @@ use sqlparser::{
     parser,
     tokenizer::{TokenWithLocation, Tokenizer},
 };
+use std::collections::BTreeMap;
 
 pub use sqlparser::tokenizer::Location;
 pub use sqlparser::tokenizer::Token;
@@ -32,6 +33,8 @@ type ParseResult<T> = MultiResult<T, ParserError>;
 pub struct Parser<'a> {
     file: String,
     sqlparser: parser::Parser<'a>,
+    // Doc comments, keyed by the (line, column) of the token that follows each one.
+    docs: BTreeMap<(u64, u64), String>,
 }
 
 impl<'a> Parser<'a> {
@@ -40,9 +43,21 @@ impl<'a> Parser<'a> {
         Parser {
             file: file.to_string(),
             sqlparser: parser::Parser::new_with_locations(tokens, eof, dialect),
+            docs: BTreeMap::new(),
         }
     }
 
+    // The tokenizer discards comments, so doc comments are found by scanning the text that the
+    // tokens were produced from.
+    pub fn with_doc_comments(mut self, text: &str) -> Parser<'a> {
+        self.docs = scan_doc_comments(text);
+        self
+    }
+
+    fn doc_comment(&self, loc: &Location) -> Option<String> {
+        self.docs.get(&(loc.line, loc.column)).cloned()
+    }
+
     pub fn next_token(&mut self) -> TokenWithLocation {
         self.sqlparser.next_token()
     }
@@ -212,6 +227,7 @@ impl<'a> Parser<'a> {
             body: StmtBody::Noop,
             start: start.clone(),
             end: start.clone(),
+            doc: None,
         });
         if self.consume_token(&Token::SemiColon) {
             return result;
@@ -228,6 +244,10 @@ impl<'a> Parser<'a> {
             self.parse_let(true)
         } else if self.consume_keyword("type") {
             self.parse_typedef()
+        } else if self.consume_keyword("assert") {
+            self.parse_assert()
+        } else if let Some(name) = self.maybe_parse(|parser| parser.parse_test_header()) {
+            self.parse_test(name)
         } else if self.consume_keyword("import") || export {
             self.parse_import()
         } else {
@@ -237,11 +257,18 @@ impl<'a> Parser<'a> {
         match body {
             Ok(body) => {
                 let end = self.prev_end_location();
+                let doc = match &body {
+                    StmtBody::Let { .. } | StmtBody::FnDef(..) | StmtBody::TypeDef { .. } => {
+                        self.doc_comment(&start)
+                    }
+                    _ => None,
+                };
                 result.set_result(Stmt {
                     export,
                     body,
                     start,
                     end,
+                    doc,
                 });
             }
             Err(err) => {
@@ -255,6 +282,8 @@ impl<'a> Parser<'a> {
                     && !self.peek_keyword("mat")
                     && !self.peek_keyword("type")
                     && !self.peek_keyword("import")
+                    && !self.peek_keyword("test")
+                    && !self.peek_keyword("assert")
                     && !self.peek_keyword("select")
                     && !self.peek_keyword("with")
                     && !self.peek_keyword("unsafe")
@@ -273,6 +302,7 @@ impl<'a> Parser<'a> {
                     body: StmtBody::Unparsed,
                     start,
                     end,
+                    doc: None,
                 });
 
                 result.add_error(Some(idx), err);
@@ -418,13 +448,10 @@ impl<'a> Parser<'a> {
         // Assume the leading "fn" has already been consumed
         //
         let name = self.parse_ident()?;
-        let generics = if self.consume_token(&Token::Lt) {
-            let list = self.parse_idents()?;
-            self.expect_token(&Token::Gt)?;
-
-            list
+        let (generics, bounds) = if self.consume_token(&Token::Lt) {
+            self.parse_generic_params()?
         } else {
-            Vec::new()
+            (Vec::new(), Vec::new())
         };
 
         self.expect_token(&Token::LParen)?;
@@ -485,12 +512,75 @@ impl<'a> Parser<'a> {
         Ok(StmtBody::FnDef(FnDef {
             name,
             generics,
+            bounds,
             args,
             ret,
             body,
         }))
     }
 
+    pub fn parse_generic_params(&mut self) -> Result<(Vec<Located<Ident>>, Vec<NameAndType>)> {
+        // Assume the leading "<" has already been consumed
+        //
+        let mut generics = Vec::new();
+        let mut bounds = Vec::new();
+        loop {
+            let name = self.parse_ident()?;
+            if self.consume_token(&Token::Colon) {
+                bounds.push(NameAndType {
+                    name: name.clone(),
+                    def: self.parse_type()?,
+                    constraints: Vec::new(),
+                    doc: None,
+                });
+            }
+            generics.push(name);
+
+            self.autocomplete_tokens(&[Token::Comma, Token::Gt]);
+            let next_token = self.next_token();
+            match &next_token.token {
+                Token::Comma => {}
+                Token::Gt => break,
+                _ => {
+                    return unexpected_token!(
+                        self.file.clone(),
+                        &next_token,
+                        "Expected: ',' | '>'"
+                    );
+                }
+            }
+        }
+
+        Ok((generics, bounds))
+    }
+
+    fn parse_mat_string(
+        &self,
+        name: &Located<Ident>,
+        arg: sqlast::FunctionArgExpr,
+    ) -> Result<String> {
+        match arg {
+            sqlast::FunctionArgExpr::Expr(sqlast::Expr::Value(
+                sqlast::Value::SingleQuotedString(value),
+            )) => Ok(value),
+            _ => Err(ParserError::unimplemented(
+                name.location().clone(),
+                &format!("non-string values for the mat argument {}", name.get()),
+            )),
+        }
+    }
+
+    // Parses a string argument to mat (e.g. `unique_key => 'org_id, id'`) as a list of column
+    // names, located at the argument's name.
+    fn parse_mat_columns(
+        &self,
+        name: &Located<Ident>,
+        arg: sqlast::FunctionArgExpr,
+    ) -> Result<Vec<Located<Ident>>> {
+        let columns = self.parse_mat_string(name, arg)?;
+        Ok(split_mat_columns(name, &columns))
+    }
+
     pub fn parse_let(&mut self, materialize: bool) -> Result<StmtBody> {
         let materialize = if materialize {
             let mut args = Vec::new();
@@ -502,44 +592,110 @@ impl<'a> Parser<'a> {
                     .context(self.token_context())?;
             }
 
-            if args.len() > 1 {
-                return Err(ParserError::unimplemented(
-                    self.token_location(),
-                    "mat supports up to 1 argument (db)",
-                ));
-            }
+            let mut mat_args = MaterializeArgs::default();
             let mut db = None;
             for (i, arg) in args.into_iter().enumerate() {
                 match arg {
                     sqlast::FunctionArg::Named { name, arg } => {
-                        if Into::<Ident>::into(name.get()) == Into::<Ident>::into("db") {
-                            db = Some(arg);
+                        let loc = SourceLocation::from_file_range(
+                            self.file.clone(),
+                            name.location().clone(),
+                        );
+                        let name = Ident::from_sqlident(loc.clone(), name.into_inner());
+                        match name.get().as_str() {
+                            "db" => db = Some(arg),
+                            "incremental" => {
+                                let mut columns = self.parse_mat_columns(&name, arg)?;
+                                if columns.len() != 1 {
+                                    return Err(ParserError::unimplemented(
+                                        loc,
+                                        "mat incremental argument must be a single column",
+                                    ));
+                                }
+                                mat_args.incremental = Some(columns.swap_remove(0));
+                            }
+                            "unique_key" => {
+                                mat_args.unique_key = self.parse_mat_columns(&name, arg)?;
+                            }
+                            "kind" => {
+                                let kind = match self.parse_mat_string(&name, arg)?.as_str() {
+                                    "table" => MaterializeKind::Table,
+                                    "view" => MaterializeKind::View,
+                                    kind => {
+                                        return Err(ParserError::unimplemented(
+                                            loc,
+                                            &format!(
+                                                "mat kind '{}' (expected 'table' or 'view')",
+                                                kind
+                                            ),
+                                        ));
+                                    }
+                                };
+                                mat_args.kind = Some(Located::new(kind, loc));
+                            }
+                            "order_by" => {
+                                mat_args.order_by = self.parse_mat_columns(&name, arg)?;
+                            }
+                            "partition_by" => {
+                                mat_args.partition_by = self.parse_mat_columns(&name, arg)?;
+                            }
+                            "indexes" => {
+                                // Indexes are separated by semicolons, and their columns by
+                                // commas (e.g. `indexes => 'org_id; org_id, id'`).
+                                mat_args.indexes = self
+                                    .parse_mat_string(&name, arg)?
+                                    .split(';')
+                                    .map(|index| split_mat_columns(&name, index))
+                                    .filter(|index| !index.is_empty())
+                                    .collect();
+                            }
+                            "file" => {
+                                let file = self.parse_mat_string(&name, arg)?;
+                                mat_args.file = Some(Located::new(file, loc));
+                            }
+                            "format" => {
+                                let format = self.parse_mat_string(&name, arg)?;
+                                mat_args.format = Some(Located::new(format, loc));
+                            }
+                            "comment" => {
+                                mat_args.comment = Some(self.parse_mat_string(&name, arg)?);
+                            }
+                            _ => {
+                                return Err(ParserError::unimplemented(
+                                    loc,
+                                    &format!("mat argument {}", name.get()),
+                                ));
+                            }
                         }
                     }
                     sqlast::FunctionArg::Unnamed(arg) => {
                         if i == 0 {
                             db = Some(arg);
+                        } else {
+                            return Err(ParserError::unimplemented(
+                                self.token_location(),
+                                "unnamed mat arguments other than the first (db)",
+                            ));
                         }
                     }
                 }
             }
-            Some(MaterializeArgs {
-                db: match db {
-                    Some(sqlast::FunctionArgExpr::Expr(e)) => Some(Expr {
-                        body: ExprBody::SQLExpr(e),
-                        start: self.peek_start_location().clone(),
-                        end: self.peek_start_location().clone(),
-                        is_unsafe: false,
-                    }),
-                    Some(_) => {
-                        return Err(ParserError::unimplemented(
-                            self.token_location(),
-                            "mat conn argument must be an expression",
-                        ));
-                    }
-                    None => None,
-                },
-            })
+            mat_args.db = match db {
+                Some(sqlast::FunctionArgExpr::Expr(e)) => Some(Expr {
+                    body: ExprBody::SQLExpr(e),
+                    start: self.peek_start_location().clone(),
+                    end: self.peek_start_location().clone(),
+                    is_unsafe: false,
+                }),
+                Some(_) => {
+                    return Err(ParserError::unimplemented(
+                        self.token_location(),
+                        "mat conn argument must be an expression",
+                    ));
+                }
+                None => None,
+            };
+            Some(mat_args)
         } else {
             None
         };
@@ -578,6 +734,74 @@ impl<'a> Parser<'a> {
         })
     }
 
+    // `test` is not reserved, so a statement is only a test block if it starts with
+    // `test <name> {`.
+    fn parse_test_header(&mut self) -> Result<Located<Ident>> {
+        self.expect_keyword("test")?;
+        let name = self.parse_ident()?;
+        self.expect_token(&Token::LBrace)?;
+        Ok(name)
+    }
+
+    pub fn parse_test(&mut self, name: Located<Ident>) -> Result<StmtBody> {
+        // Assume the leading `test <name> {` has already been consumed
+        //
+        let mut body = Vec::new();
+        while !self.consume_token(&Token::RBrace) {
+            let start = self.peek_start_location();
+            let stmt = if self.consume_keyword("let") {
+                self.parse_let(false)?
+            } else if self.consume_keyword("expect") {
+                self.parse_expect()?
+            } else if self.consume_keyword("assert") {
+                self.parse_assert()?
+            } else {
+                let token = self.peek_token();
+                self.sqlparser
+                    .expected::<StmtBody>("let, expect, assert, or }", token)
+                    .context(self.token_context())?
+            };
+            body.push(Stmt {
+                export: false,
+                body: stmt,
+                start,
+                end: self.prev_end_location(),
+                doc: None,
+            });
+        }
+
+        Ok(StmtBody::Test { name, body })
+    }
+
+    pub fn parse_expect(&mut self) -> Result<StmtBody> {
+        // Assume the leading "expect" has already been consumed
+        //
+        let actual = self.parse_expr()?;
+        self.expect_keyword("equals")?;
+        let expected = self.parse_expr()?;
+        self.expect_eos()?;
+
+        Ok(StmtBody::Expect { actual, expected })
+    }
+
+    pub fn parse_assert(&mut self) -> Result<StmtBody> {
+        // Assume the leading "assert" has already been consumed
+        //
+        let expr = self.parse_expr()?;
+        let message = if self.consume_token(&Token::Comma) {
+            Some(
+                self.sqlparser
+                    .parse_literal_string()
+                    .context(self.token_context())?,
+            )
+        } else {
+            None
+        };
+        self.expect_eos()?;
+
+        Ok(StmtBody::Assert { expr, message })
+    }
+
     pub fn parse_expr_stmt(&mut self) -> Result<StmtBody> {
         let expr = self.parse_expr()?;
         self.expect_eos()?;
@@ -589,12 +813,28 @@ impl<'a> Parser<'a> {
         // Assume the leading keywords have already been consumed
         //
         let name = self.parse_ident()?;
+        let generics = if self.consume_token(&Token::Lt) {
+            let list = self.parse_idents()?;
+            self.expect_token(&Token::Gt)?;
+
+            list
+        } else {
+            Vec::new()
+        };
+
+        // The "=" is optional, e.g. `type Paged<T> = {page int, rows [T]}`
+        self.consume_token(&Token::Eq);
+
         let def = self.parse_type()?;
         match def.body {
             TypeBody::Struct(_) => {}
             _ => self.expect_eos()?,
         }
-        Ok(StmtBody::TypeDef(NameAndType { name, def }))
+        Ok(StmtBody::TypeDef {
+            name,
+            generics,
+            def,
+        })
     }
 
     pub fn parse_type(&mut self) -> Result<Type> {
@@ -606,6 +846,8 @@ impl<'a> Parser<'a> {
             TypeBody::List(Box::new(inner))
         } else if self.peek_token().token == Token::LBrace {
             self.parse_struct()?
+        } else if self.consume_keyword("fn") {
+            self.parse_fn_type()?
         } else {
             let type_name = self.parse_path(AUTOCOMPLETE_TYPE)?;
             if self.consume_token(&Token::Lt) {
@@ -652,6 +894,40 @@ impl<'a> Parser<'a> {
         Ok(Type { body, start, end })
     }
 
+    pub fn parse_fn_type(&mut self) -> Result<TypeBody> {
+        // Assume the leading "fn" has already been consumed
+        //
+        self.expect_token(&Token::LParen)?;
+
+        let args = if self.consume_token(&Token::RParen) {
+            Vec::new()
+        } else {
+            let mut args = Vec::new();
+            loop {
+                args.push(self.parse_type()?);
+
+                self.autocomplete_tokens(&[Token::Comma, Token::RParen]);
+                let next_token = self.next_token();
+                match &next_token.token {
+                    Token::Comma => {}
+                    Token::RParen => break args,
+                    _ => {
+                        return unexpected_token!(
+                            self.file.clone(),
+                            &next_token,
+                            "Expected: ',' | ')'"
+                        );
+                    }
+                }
+            }
+        };
+
+        self.expect_token(&Token::Arrow)?;
+        let ret = Box::new(self.parse_type()?);
+
+        Ok(TypeBody::Fn { args, ret })
+    }
+
     pub fn parse_struct(&mut self) -> Result<TypeBody> {
         self.expect_token(&Token::LBrace)?;
         let mut struct_ = Vec::new();
@@ -698,9 +974,16 @@ impl<'a> Parser<'a> {
                             "Expected a comma before the next type declaration"
                         );
                     }
+                    let doc = self.doc_comment(&self.peek_start_location());
                     let name = self.parse_ident()?;
                     let def = self.parse_type()?;
-                    struct_.push(StructEntry::NameAndType(NameAndType { name, def }));
+                    let constraints = self.parse_constraints()?;
+                    struct_.push(StructEntry::NameAndType(NameAndType {
+                        name,
+                        def,
+                        constraints,
+                        doc,
+                    }));
                     needs_comma = true;
                 }
             }
@@ -708,10 +991,72 @@ impl<'a> Parser<'a> {
         Ok(TypeBody::Struct(struct_))
     }
 
+    // Parses the (possibly empty) list of constraints that follows a field's type, e.g.
+    // `not null unique in ('a', 'b') check (x > 0)`.
+    pub fn parse_constraints(&mut self) -> Result<Vec<Located<Constraint>>> {
+        let mut constraints = Vec::new();
+        loop {
+            self.autocomplete_tokens(&[
+                Token::make_keyword("NOT"),
+                Token::make_keyword("UNIQUE"),
+                Token::make_keyword("IN"),
+                Token::make_keyword("CHECK"),
+            ]);
+
+            let start = self.peek_start_location();
+            let constraint = if self.consume_keyword("not") {
+                self.expect_keyword("null")?;
+                Constraint::NotNull
+            } else if self.consume_keyword("unique") {
+                Constraint::Unique
+            } else if self.consume_keyword("in") {
+                self.expect_token(&Token::LParen)?;
+                let mut values = Vec::new();
+                loop {
+                    values.push(
+                        self.sqlparser
+                            .parse_expr()
+                            .context(self.range_context(&start))?,
+                    );
+                    if !self.consume_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.expect_token(&Token::RParen)?;
+                Constraint::AcceptedValues(values)
+            } else if self.consume_keyword("check") {
+                self.expect_token(&Token::LParen)?;
+                let expr = self
+                    .sqlparser
+                    .parse_expr()
+                    .context(self.range_context(&start))?;
+                self.expect_token(&Token::RParen)?;
+                Constraint::Check(expr)
+            } else {
+                break;
+            };
+
+            constraints.push(Located::new(
+                constraint,
+                SourceLocation::Range(
+                    self.file.clone(),
+                    Range {
+                        start,
+                        end: self.prev_end_location(),
+                    },
+                ),
+            ));
+        }
+        Ok(constraints)
+    }
+
     pub fn parse_expr(&mut self) -> Result<Expr> {
         let start = self.peek_start_location();
-        self.sqlparser
-            .autocomplete_tokens(&[Token::make_keyword("SELECT"), Token::make_keyword("WITH")]);
+        self.sqlparser.autocomplete_tokens(&[
+            Token::make_keyword("SELECT"),
+            Token::make_keyword("WITH"),
+            Token::make_keyword("VALUES"),
+        ]);
 
         let is_unsafe = self.consume_keyword("unsafe");
 
@@ -719,7 +1064,7 @@ impl<'a> Parser<'a> {
             Token::Word(Word {
                 value: _,
                 quote_style: _,
-                keyword: Keyword::SELECT | Keyword::WITH,
+                keyword: Keyword::SELECT | Keyword::WITH | Keyword::VALUES,
             }) => {
                 let query = self
                     .sqlparser
@@ -745,6 +1090,133 @@ impl<'a> Parser<'a> {
     }
 }
 
+// Splits a comma-separated list of column names, locating each one at the mat argument's name.
+fn split_mat_columns(name: &Located<Ident>, columns: &str) -> Vec<Located<Ident>> {
+    columns
+        .split(',')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(|c| Located::new(c.into(), name.location().clone()))
+        .collect()
+}
+
+// Finds the doc comments (`--!` lines or `/** */` blocks) in the text, keyed by the location of the
+// token that follows each one. Consecutive `--!` lines are joined into a single comment, and an
+// ordinary comment in between a doc comment and its token detaches it.
+fn scan_doc_comments(text: &str) -> BTreeMap<(u64, u64), String> {
+    let mut cursor = Cursor::new(text);
+    let mut ret = BTreeMap::new();
+    let mut pending = Vec::new();
+    while let Some(c) = cursor.peek(0) {
+        if c.is_whitespace() {
+            cursor.advance(1);
+        } else if cursor.starts_with("--") {
+            let comment = cursor.take(2, "\n", false);
+            match comment.strip_prefix("--!") {
+                Some(doc) => {
+                    pending.push(doc.strip_prefix(' ').unwrap_or(doc).trim_end().to_string())
+                }
+                None => pending.clear(),
+            }
+        } else if cursor.starts_with("/*") {
+            let comment = cursor.take(2, "*/", true);
+            pending.clear();
+            if let Some(doc) = comment
+                .strip_prefix("/**")
+                .and_then(|c| c.strip_suffix("*/"))
+            {
+                let lines = doc
+                    .lines()
+                    .map(|l| {
+                        let l = l.trim();
+                        l.strip_prefix('*').map_or(l, |l| l.trim_start())
+                    })
+                    .skip_while(|l| l.is_empty())
+                    .collect::<Vec<_>>();
+                let end = lines
+                    .iter()
+                    .rposition(|l| !l.is_empty())
+                    .map_or(0, |i| i + 1);
+                pending.extend(lines[..end].iter().map(|l| l.to_string()));
+            }
+        } else {
+            if !pending.is_empty() {
+                ret.insert((cursor.line, cursor.column), pending.join("\n"));
+                pending.clear();
+            }
+            // Skip over string literals and quoted identifiers, which may contain `--` or `/*`.
+            if c == '\'' || c == '"' {
+                cursor.take(1, &c.to_string(), true);
+            } else {
+                cursor.advance(1);
+            }
+        }
+    }
+    ret
+}
+
+// Tracks a position in the text, using the same (1-based) lines and columns as the tokenizer.
+pub(crate) struct Cursor {
+    chars: Vec<char>,
+    i: usize,
+    line: u64,
+    column: u64,
+}
+
+impl Cursor {
+    pub(crate) fn new(text: &str) -> Cursor {
+        Cursor {
+            chars: text.chars().collect(),
+            i: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    pub(crate) fn location(&self) -> Location {
+        Location {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    pub(crate) fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.i + offset).cloned()
+    }
+
+    pub(crate) fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(j, c)| self.peek(j) == Some(c))
+    }
+
+    pub(crate) fn advance(&mut self, n: usize) {
+        for _ in 0..n {
+            match self.peek(0) {
+                Some('\n') => {
+                    self.line += 1;
+                    self.column = 1;
+                }
+                Some(_) => self.column += 1,
+                None => return,
+            }
+            self.i += 1;
+        }
+    }
+
+    // Consumes an opening delimiter of `open` characters, and then the text up to (and, if
+    // `inclusive`, including) the next occurrence of `end`.
+    pub(crate) fn take(&mut self, open: usize, end: &str, inclusive: bool) -> String {
+        let start = self.i;
+        self.advance(open);
+        while self.peek(0).is_some() && !self.starts_with(end) {
+            self.advance(1);
+        }
+        if inclusive {
+            self.advance(end.chars().count());
+        }
+        self.chars[start..self.i].iter().collect()
+    }
+}
+
 pub fn tokenize(file: &str, text: &str) -> Result<(Vec<TokenWithLocation>, Location)> {
     let dialect = &GenericDialect {};
     let mut tokenizer = Tokenizer::new(dialect, text);
@@ -757,7 +1229,7 @@ pub fn tokenize(file: &str, text: &str) -> Result<(Vec<TokenWithLocation>, Locat
 pub fn parse_schema(file: &str, text: &str) -> ParseResult<Schema> {
     let mut result = ParseResult::new(Schema { stmts: Vec::new() });
     let (tokens, eof) = c_try!(result, tokenize(file.clone(), text));
-    let mut parser = Parser::new(file, tokens, eof);
+    let mut parser = Parser::new(file, tokens, eof).with_doc_comments(text);
 
     parser.parse_schema()
 }
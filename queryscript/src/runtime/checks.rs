@@ -0,0 +1,100 @@
+// Runs the data quality checks compiled from the constraints on a schema's `let` declarations, and
+// its `assert` statements.
+use std::fmt;
+use std::sync::Arc;
+
+use crate::ast::SourceLocation;
+use crate::compile::schema::{Assertion, Check};
+use crate::parser::error::PrettyError;
+use crate::types::{Relation, Value};
+
+use super::{context::Context, error::*, runtime::eval};
+
+#[derive(Debug, Clone)]
+pub struct CheckFailure {
+    pub check: Check,
+    // The rows that violate the check.
+    pub rows: Arc<dyn Relation>,
+}
+
+impl CheckFailure {
+    pub fn num_rows(&self) -> usize {
+        (0..self.rows.num_batches())
+            .map(|i| self.rows.batch(i).as_arrow_recordbatch().num_rows())
+            .sum()
+    }
+}
+
+impl fmt::Display for CheckFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let num_rows = self.num_rows();
+        write!(
+            f,
+            "Check failed: {} ({} failing {})",
+            self.check.description,
+            num_rows,
+            if num_rows == 1 { "row" } else { "rows" }
+        )
+    }
+}
+
+impl PrettyError for CheckFailure {
+    fn location(&self) -> SourceLocation {
+        self.check.loc.clone()
+    }
+}
+
+pub async fn run_check(ctx: &mut Context, check: &Check) -> Result<Option<CheckFailure>> {
+    let query = check.query.to_runtime_type()?;
+    match eval(ctx, &query).await? {
+        Value::Relation(rows) => {
+            let failure = CheckFailure {
+                check: check.clone(),
+                rows,
+            };
+            Ok(if failure.num_rows() > 0 {
+                Some(failure)
+            } else {
+                None
+            })
+        }
+        value => fail!(
+            "Expected check {} to return a relation, got {:?}",
+            check.description,
+            value.type_()
+        ),
+    }
+}
+
+/// Runs each check, and returns the ones that failed.
+pub async fn run_checks(ctx: &mut Context, checks: &[Check]) -> Result<Vec<CheckFailure>> {
+    let mut failures = Vec::new();
+    for check in checks {
+        if let Some(failure) = run_check(ctx, check).await? {
+            failures.push(failure);
+        }
+    }
+    Ok(failures)
+}
+
+/// Evaluates an `assert` statement, which fails (at the statement's location) unless its
+/// expression is true.
+pub async fn run_assertion(ctx: &mut Context, assertion: &Assertion) -> Result<()> {
+    let expr = assertion.expr.to_runtime_type()?;
+    match eval(ctx, &expr).await? {
+        Value::Boolean(true) => Ok(()),
+        _ => AssertionFailedSnafu {
+            loc: assertion.loc.clone(),
+            message: assertion.message.clone(),
+        }
+        .fail(),
+    }
+}
+
+/// Runs each assertion, stopping at the first one that fails.
+pub async fn run_assertions(ctx: &mut Context, assertions: &[Assertion]) -> Result<()> {
+    for assertion in assertions {
+        run_assertion(ctx, assertion).await?;
+    }
+    Ok(())
+}
@@ -21,6 +21,10 @@ pub struct Context {
 
     /// Materializations that we've saved up and can re-use.
     pub materializations: BTreeMap<String, Value>,
+
+    /// Temporary tables created for inlined materializations, along with a hash of the query and
+    /// parameters they were created from. They are dropped along with the context.
+    temp_tables: BTreeMap<(Option<Arc<ConnectionString>>, Ident), u64>,
 }
 
 impl Context {
@@ -32,14 +36,14 @@ impl Context {
             disable_typechecks: false,
             connections: BTreeMap::new(),
             materializations: BTreeMap::new(),
+            temp_tables: BTreeMap::new(),
         }
     }
 
     pub fn disable_typechecks(&self) -> Context {
-        Context {
-            disable_typechecks: true,
-            ..self.clone()
-        }
+        let mut ctx = self.clone();
+        ctx.disable_typechecks = true;
+        ctx
     }
 
     pub fn sql_engine<'a>(
@@ -61,6 +65,21 @@ impl Context {
             }
         })
     }
+
+    /// Returns the key that the temporary table `name` was last created with, if this context
+    /// created it.
+    pub fn temp_table_key(&self, url: &Option<Arc<ConnectionString>>, name: &Ident) -> Option<u64> {
+        self.temp_tables.get(&(url.clone(), name.clone())).cloned()
+    }
+
+    pub fn set_temp_table_key(
+        &mut self,
+        url: Option<Arc<ConnectionString>>,
+        name: Ident,
+        key: u64,
+    ) {
+        self.temp_tables.insert((url, name), key);
+    }
 }
 
 impl Clone for Context {
@@ -72,8 +91,33 @@ impl Clone for Context {
             disable_typechecks: self.disable_typechecks,
             materializations: self.materializations.clone(),
 
-            // Connections cannot be shared between contexts
+            // Connections cannot be shared between contexts, and temporary tables are scoped to
+            // a connection.
             connections: BTreeMap::new(),
+            temp_tables: BTreeMap::new(),
+        }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        let mut tables = BTreeMap::<Option<Arc<ConnectionString>>, Vec<Ident>>::new();
+        for (url, name) in std::mem::take(&mut self.temp_tables).into_keys() {
+            tables.entry(url).or_default().push(name);
+        }
+
+        for (url, names) in tables {
+            let engine = match url {
+                Some(url) => match self.connections.get_mut(&url) {
+                    Some(engine) => engine,
+                    None => continue,
+                },
+                None => &mut self.embedded_sql,
+            };
+
+            // There's nothing useful to do with an error while dropping the context, and the
+            // tables will go away with the connection anyway.
+            let _ = engine.drop_temporary_tables(&names);
         }
     }
 }
@@ -151,7 +151,13 @@ impl DuckDBEngine {
                     relation_params.insert(key.to_string());
                 }
                 Value::Fn(_) => {
-                    return rt_unimplemented!("Function parameters");
+                    // Function-valued arguments are inlined at their call sites by the compiler, so
+                    // a function should only reach SQL if it could not be inlined (e.g. inlining is
+                    // disabled, or the function is native).
+                    return rt_unimplemented!(
+                        "Function parameters ({} could not be inlined into SQL)",
+                        key
+                    );
                 }
                 _ => {
                     scalar_params.push(key.clone());
@@ -274,6 +280,39 @@ impl SQLEngine for DuckDBEngine {
         Ok(result.count() > 0)
     }
 
+    fn supports_transactional_ddl(&self) -> bool {
+        true
+    }
+
+    async fn begin_transaction(&mut self) -> Result<()> {
+        self.conn
+            .get_state()
+            .conn
+            .execute_batch("BEGIN TRANSACTION")?;
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<()> {
+        self.conn.get_state().conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    async fn rollback(&mut self) -> Result<()> {
+        self.conn.get_state().conn.execute_batch("ROLLBACK")?;
+        Ok(())
+    }
+
+    fn drop_temporary_tables(&mut self, names: &[Ident]) -> Result<()> {
+        let conn_state = self.conn.get_state();
+        for name in names {
+            let table: sqlast::ObjectName = name.into();
+            conn_state
+                .conn
+                .execute_batch(&format!("DROP TABLE IF EXISTS {}", table))?;
+        }
+        Ok(())
+    }
+
     fn engine_type(&self) -> SQLEngineType {
         SQLEngineType::DuckDB
     }
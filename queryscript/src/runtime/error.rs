@@ -68,6 +68,13 @@ pub enum RuntimeError {
         backtrace: Option<Backtrace>,
     },
 
+    #[snafu(display("Assertion failed: {}", message))]
+    AssertionFailed {
+        loc: crate::ast::SourceLocation,
+        message: String,
+        backtrace: Option<Backtrace>,
+    },
+
     #[snafu(display("Compile error: {}", source))]
     CompileError {
         #[snafu(backtrace)]
@@ -94,6 +101,32 @@ impl RuntimeError {
         }
         .build();
     }
+
+    /// Whether the error may go away if the operation is retried (e.g. because another process
+    /// holds a lock on the database, or a connection was dropped).
+    pub fn is_transient(&self) -> bool {
+        use std::io::ErrorKind::*;
+        match self {
+            RuntimeError::IOError { source, .. } => matches!(
+                source.kind(),
+                Interrupted
+                    | WouldBlock
+                    | TimedOut
+                    | ConnectionReset
+                    | ConnectionAborted
+                    | ConnectionRefused
+                    | BrokenPipe
+                    | NotConnected
+            ),
+            RuntimeError::DuckDBError { source, .. } => {
+                let message = source.to_string();
+                ["Could not set lock", "Conflict on", "database is locked"]
+                    .iter()
+                    .any(|pattern| message.contains(pattern))
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Into<arrow::error::ArrowError> for RuntimeError {
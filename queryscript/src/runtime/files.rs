@@ -0,0 +1,280 @@
+// Reads and writes relations as files. A directory is read as a single relation made up of all of
+// the files within it, which is how partitioned outputs are laid out.
+use arrow::{
+    array::{ArrayRef, UInt32Array},
+    compute::{concat_batches, lexsort_to_indices, take, SortColumn},
+    datatypes::{DataType as ArrowDataType, Schema as ArrowSchema, SchemaRef as ArrowSchemaRef},
+    error::ArrowError,
+    record_batch::RecordBatch,
+    util::display::array_value_to_string,
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::types::{Relation, Type};
+
+use super::error::{fail, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    Csv,
+    Parquet,
+}
+
+impl FileFormat {
+    pub fn from_name(name: &str) -> Option<FileFormat> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(FileFormat::Json),
+            "csv" => Some(FileFormat::Csv),
+            "parquet" => Some(FileFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    // Uses the explicit format if there is one, and otherwise the file's extension. Anything
+    // unrecognized is treated as JSON.
+    pub fn from_path(path: &Path, format: &Option<String>) -> FileFormat {
+        let format_name = match format {
+            Some(format) => Some(format.as_str()),
+            None => path.extension().and_then(|s| s.to_str()),
+        };
+        format_name
+            .and_then(FileFormat::from_name)
+            .unwrap_or(FileFormat::Json)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Json => "json",
+            FileFormat::Csv => "csv",
+            FileFormat::Parquet => "parquet",
+        }
+    }
+}
+
+// The value that hive-style layouts use for null partition values.
+const NULL_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Converts a relation type (a list of records) into the Arrow schema of its files.
+pub fn relation_schema(type_: &Type) -> Result<ArrowSchemaRef> {
+    if let ArrowDataType::List(dt) = type_.try_into()? {
+        if let ArrowDataType::Struct(s) = dt.data_type() {
+            return Ok(Arc::new(ArrowSchema::new(s.clone())));
+        }
+    }
+    fail!("Type {:?} is not a list of records", type_)
+}
+
+// Hidden files (e.g. the temporary files written by `write_relation`) are skipped, so that
+// readers never see partial output.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .map_or(false, |s| s.starts_with('.'))
+}
+
+/// Returns the files that make up `path`: either the file itself, or every file (recursively)
+/// within the directory, in a stable order.
+pub fn data_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let mut entries = std::fs::read_dir(path)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if is_hidden(&entry) {
+            continue;
+        }
+        files.extend(data_files(&entry)?);
+    }
+    Ok(files)
+}
+
+/// Writes a relation to `path`, with the given schema. If `directory` is set (or there are
+/// partitioning columns), `path` is a directory that holds one file per partition, in a
+/// hive-style layout (e.g. `org_id=1/part-00000.parquet`). The partitioning columns are kept in
+/// the files, so that the directory can be read back with the same type.
+///
+/// The output is first written to a hidden path next to `path`, and then moved into place, so
+/// that readers never see a partially written relation.
+pub fn write_relation(
+    path: &Path,
+    format: FileFormat,
+    directory: bool,
+    schema: ArrowSchemaRef,
+    relation: &dyn Relation,
+    partition_by: &[String],
+    order_by: &[String],
+) -> Result<()> {
+    let batches = (0..relation.num_batches())
+        .map(|i| {
+            RecordBatch::try_new(
+                schema.clone(),
+                relation.batch(i).as_arrow_recordbatch().columns().to_vec(),
+            )
+        })
+        .collect::<Result<Vec<_>, ArrowError>>()?;
+    let batches = sort_batches(&schema, batches, order_by)?;
+
+    let (parent, name) = match (path.parent(), path.file_name().and_then(|s| s.to_str())) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return fail!("Invalid output path {:?}", path),
+    };
+    std::fs::create_dir_all(parent)?;
+    let staging = parent.join(format!(".{}.tmp", name));
+    remove_path(&staging)?;
+
+    if !directory && partition_by.is_empty() {
+        write_batches(&staging, format, schema, &batches)?;
+        std::fs::rename(&staging, path)?;
+        return Ok(());
+    }
+
+    let partitions = partition_batches(&schema, batches, partition_by)?;
+    for (key, batches) in partitions {
+        let mut dir = staging.clone();
+        for (column, value) in partition_by.iter().zip(key.iter()) {
+            dir.push(format!("{}={}", column, value));
+        }
+        std::fs::create_dir_all(&dir)?;
+        write_batches(
+            &dir.join(format!("part-00000.{}", format.extension())),
+            format,
+            schema.clone(),
+            &batches,
+        )?;
+    }
+    if !staging.exists() {
+        std::fs::create_dir_all(&staging)?;
+    }
+
+    // A directory cannot be replaced in a single rename, so the old one is moved aside first.
+    let old = parent.join(format!(".{}.old", name));
+    remove_path(&old)?;
+    if path.exists() {
+        std::fs::rename(path, &old)?;
+    }
+    std::fs::rename(&staging, path)?;
+    remove_path(&old)?;
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    } else if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn column_indices(schema: &ArrowSchema, columns: &[String]) -> Result<Vec<usize>> {
+    columns
+        .iter()
+        .map(|column| match schema.index_of(column) {
+            Ok(index) => Ok(index),
+            Err(_) => fail!("No such column {:?} in the output", column),
+        })
+        .collect()
+}
+
+fn take_rows(batch: &RecordBatch, indices: &UInt32Array) -> Result<RecordBatch> {
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|c| take(c.as_ref(), indices, None))
+        .collect::<Result<Vec<_>, ArrowError>>()?;
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+fn sort_batches(
+    schema: &ArrowSchemaRef,
+    batches: Vec<RecordBatch>,
+    order_by: &[String],
+) -> Result<Vec<RecordBatch>> {
+    if order_by.is_empty() {
+        return Ok(batches);
+    }
+
+    let batch = concat_batches(schema, &batches)?;
+    let sort_columns = column_indices(schema, order_by)?
+        .into_iter()
+        .map(|i| SortColumn {
+            values: batch.column(i).clone(),
+            options: None,
+        })
+        .collect::<Vec<_>>();
+    let indices = lexsort_to_indices(&sort_columns, None)?;
+    Ok(vec![take_rows(&batch, &indices)?])
+}
+
+fn partition_value(column: &ArrayRef, row: usize) -> Result<String> {
+    if column.is_null(row) {
+        return Ok(NULL_PARTITION.to_string());
+    }
+    let value = array_value_to_string(column, row)?;
+    Ok(url::form_urlencoded::byte_serialize(value.as_bytes()).collect())
+}
+
+// Splits the batches by the values of the partitioning columns. Rows keep their relative order
+// within each partition.
+fn partition_batches(
+    schema: &ArrowSchemaRef,
+    batches: Vec<RecordBatch>,
+    partition_by: &[String],
+) -> Result<BTreeMap<Vec<String>, Vec<RecordBatch>>> {
+    let columns = column_indices(schema, partition_by)?;
+
+    let mut ret = BTreeMap::<Vec<String>, Vec<RecordBatch>>::new();
+    for batch in batches {
+        let mut rows = BTreeMap::<Vec<String>, Vec<u32>>::new();
+        for row in 0..batch.num_rows() {
+            let key = columns
+                .iter()
+                .map(|i| partition_value(batch.column(*i), row))
+                .collect::<Result<Vec<_>>>()?;
+            rows.entry(key).or_default().push(row as u32);
+        }
+        for (key, indices) in rows {
+            let partition = take_rows(&batch, &UInt32Array::from(indices))?;
+            ret.entry(key).or_default().push(partition);
+        }
+    }
+    Ok(ret)
+}
+
+fn write_batches(
+    path: &Path,
+    format: FileFormat,
+    schema: ArrowSchemaRef,
+    batches: &[RecordBatch],
+) -> Result<()> {
+    let fd = std::fs::File::create(path)?;
+    match format {
+        FileFormat::Csv => {
+            let mut writer = arrow::csv::Writer::new(fd);
+            for batch in batches {
+                writer.write(batch)?;
+            }
+        }
+        FileFormat::Json => {
+            let mut writer = arrow::json::LineDelimitedWriter::new(fd);
+            writer.write_batches(batches)?;
+            writer.finish()?;
+        }
+        FileFormat::Parquet => {
+            let mut writer = parquet::arrow::ArrowWriter::try_new(fd, schema, None)?;
+            for batch in batches {
+                writer.write(batch)?;
+            }
+            writer.close()?;
+        }
+    }
+    Ok(())
+}
@@ -1,13 +1,15 @@
 use arrow::{
-    datatypes::{DataType as ArrowDataType, Schema as ArrowSchema},
+    datatypes::Schema as ArrowSchema,
     error::ArrowError,
     record_batch::{RecordBatch, RecordBatchReader},
 };
 use async_trait::async_trait;
 use futures::future::{BoxFuture, FutureExt};
+use std::fmt;
 use std::path::{Path as FilePath, PathBuf as FilePathBuf};
 use std::sync::Arc;
 
+use crate::ast::Ident;
 use crate::compile::schema;
 use crate::{
     types,
@@ -15,10 +17,45 @@ use crate::{
 };
 
 use super::{
-    error::{fail, Result, RuntimeError},
+    error::{fail, rt_unimplemented, Result, RuntimeError},
+    files::{data_files, relation_schema, FileFormat},
     runtime, Context,
 };
 
+/// Builds the implementation of a native function, given the function's (fully resolved) type
+/// wherever it is evaluated. Generic functions can use the type to specialize themselves.
+pub type NativeFnBuilder = Arc<dyn Fn(&types::Type) -> Result<Arc<dyn FnValue>> + Send + Sync>;
+
+/// A native (Rust) function provided by an embedder (see `CompilerConfig::with_native_fn`).
+#[derive(Clone)]
+pub struct NativeFn {
+    // The QueryScript signature of the function, e.g. `(zip varchar) -> varchar`.
+    pub signature: String,
+    pub builder: NativeFnBuilder,
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFn")
+            .field("signature", &self.signature)
+            .finish_non_exhaustive()
+    }
+}
+
+// Instantiates a native function. Embedder-provided functions carry their own builder, and the
+// remaining ones are the builtin native functions, which are declared along with the other builtins.
+pub fn native_fn(name: &Ident, native: Option<&NativeFn>, type_: &types::Type) -> Result<Value> {
+    let f: Arc<dyn FnValue> = match native {
+        Some(native) => (native.builder)(type_)?,
+        None => match name.as_str() {
+            "load" => Arc::new(LoadFileFn::new(type_)?),
+            "__native_identity" => Arc::new(IdentityFn::new(type_)?),
+            _ => return rt_unimplemented!("native function: {}", name),
+        },
+    };
+    Ok(Value::Fn(f))
+}
+
 type TypeRef = schema::Ref<types::Type>;
 
 #[derive(Clone, Debug)]
@@ -77,13 +114,6 @@ impl FnValue for QSFn {
     }
 }
 
-#[derive(Clone, Debug)]
-enum Format {
-    Json,
-    Csv,
-    Parquet,
-}
-
 #[derive(Clone, Debug)]
 pub struct LoadFileFn {
     schema: Arc<ArrowSchema>,
@@ -96,16 +126,9 @@ impl LoadFileFn {
             _ => return fail!("Type of load is not a function"),
         };
 
-        let mut schema = None;
-        if let ArrowDataType::List(dt) = ret_type.as_ref().try_into()? {
-            if let ArrowDataType::Struct(s) = dt.data_type() {
-                schema = Some(Arc::new(ArrowSchema::new(s.clone())));
-            }
-        }
-
-        let schema = match schema {
-            Some(schema) => schema,
-            None => {
+        let schema = match relation_schema(ret_type.as_ref()) {
+            Ok(schema) => schema,
+            Err(_) => {
                 return fail!(
                     "Return type of load ({:?}) is not a list of records",
                     ret_type.as_ref(),
@@ -116,70 +139,18 @@ impl LoadFileFn {
         Ok(LoadFileFn { schema })
     }
 
-    fn derive_format(file: &FilePath, format: &Option<String>) -> Format {
-        let format_lower = format.as_ref().map(|s| s.to_lowercase());
-        let format_name = match format_lower.as_deref() {
-            None => file.extension().and_then(|s| s.to_str()),
-            Some(fmt) => Some(fmt),
-        };
-
-        match format_name {
-            Some("csv") => Format::Csv,
-            Some("parquet") => Format::Parquet,
-            Some("json") | _ => Format::Json,
-        }
-    }
-
     pub async fn load(
         &self,
         _ctx: &Context,
         file_path: &FilePath,
         format: Option<String>,
     ) -> Result<Value> {
-        let format_type = Self::derive_format(file_path, &format);
-
         let records = runtime::expensive(move || {
-            let fd = std::fs::File::open(file_path)?;
-
-            // NOTES:
-            // - This reads the entire file into memory, and then operates over it. We could
-            //   instead implement the Relation attribute for each Reader (or for Iterator<Item=RecordBatch>).
-            // - DataFusion implements an async reader for non-files (i.e. streams that are already async) by reading
-            //   newline delimited chunks of the file. We could do something like that to leverage async file reading.
-            // - The parquet library actually supports async reading, which we could do in a separate branch
-            let records = Arc::new(match format_type {
-                Format::Csv => {
-                    let reader = arrow::csv::ReaderBuilder::new()
-                        .has_header(true)
-                        .with_schema(self.schema.clone())
-                        .build(fd)?;
-
-                    reader.collect::<Result<Vec<RecordBatch>, ArrowError>>()
-                }
-                Format::Json => {
-                    let reader = arrow::json::ReaderBuilder::new()
-                        .with_schema(self.schema.clone())
-                        .build(fd)?;
-
-                    reader.collect::<Result<Vec<RecordBatch>, ArrowError>>()
-                }
-                Format::Parquet => {
-                    let reader =
-                        parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(fd)?
-                            .build()?;
-
-                    if reader.schema() != self.schema {
-                        return fail!(
-                            "Parquet file {:?} has a different schema than the target variable's type",
-                            file_path
-                        );
-                    }
-
-                    reader.collect::<Result<Vec<RecordBatch>, ArrowError>>()
-                }
-            }? as Vec<RecordBatch>);
-
-            Ok::<Arc<Vec<_>>, RuntimeError>(records)
+            let mut records = Vec::new();
+            for file in data_files(file_path)? {
+                records.extend(self.load_file(&file, &format)?);
+            }
+            Ok::<Arc<Vec<_>>, RuntimeError>(Arc::new(records))
         })?;
 
         Ok(Value::Relation(ArrowRecordBatchRelation::new(
@@ -188,6 +159,49 @@ impl LoadFileFn {
         )))
     }
 
+    fn load_file(&self, file_path: &FilePath, format: &Option<String>) -> Result<Vec<RecordBatch>> {
+        let format_type = FileFormat::from_path(file_path, format);
+        let fd = std::fs::File::open(file_path)?;
+
+        // NOTES:
+        // - This reads the entire file into memory, and then operates over it. We could
+        //   instead implement the Relation attribute for each Reader (or for Iterator<Item=RecordBatch>).
+        // - DataFusion implements an async reader for non-files (i.e. streams that are already async) by reading
+        //   newline delimited chunks of the file. We could do something like that to leverage async file reading.
+        // - The parquet library actually supports async reading, which we could do in a separate branch
+        Ok(match format_type {
+            FileFormat::Csv => {
+                let reader = arrow::csv::ReaderBuilder::new()
+                    .has_header(true)
+                    .with_schema(self.schema.clone())
+                    .build(fd)?;
+
+                reader.collect::<Result<Vec<RecordBatch>, ArrowError>>()
+            }
+            FileFormat::Json => {
+                let reader = arrow::json::ReaderBuilder::new()
+                    .with_schema(self.schema.clone())
+                    .build(fd)?;
+
+                reader.collect::<Result<Vec<RecordBatch>, ArrowError>>()
+            }
+            FileFormat::Parquet => {
+                let reader =
+                    parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(fd)?
+                        .build()?;
+
+                if reader.schema() != self.schema {
+                    return fail!(
+                        "Parquet file {:?} has a different schema than the target variable's type",
+                        file_path
+                    );
+                }
+
+                reader.collect::<Result<Vec<RecordBatch>, ArrowError>>()
+            }
+        }?)
+    }
+
     fn parse_args(ctx: &Context, args: Vec<Value>) -> Result<(FilePathBuf, Option<String>)> {
         if args.len() != 2 {
             return fail!("load expects exactly 2 arguments");
@@ -218,13 +232,18 @@ impl LoadFileFn {
     ) -> crate::runtime::Result<crate::types::Type> {
         let (file_path, format) = Self::parse_args(ctx, args)?;
         let file_path = &*file_path;
-        let format_type = Self::derive_format(file_path, &format);
 
         runtime::expensive(move || {
-            let fd = std::fs::File::open(file_path)?;
+            // A directory is inferred from its first file, since they all share the same schema.
+            let file_path = match data_files(file_path)?.into_iter().next() {
+                Some(file_path) => file_path,
+                None => return fail!("Cannot infer the type of {:?}: it is empty", file_path),
+            };
+            let format_type = FileFormat::from_path(&file_path, &format);
+            let fd = std::fs::File::open(&file_path)?;
 
             Ok(crate::types::Type::List(Box::new(match format_type {
-                Format::Csv => {
+                FileFormat::Csv => {
                     let reader = arrow::csv::ReaderBuilder::new()
                         .infer_schema(Some(100))
                         .has_header(true)
@@ -233,7 +252,7 @@ impl LoadFileFn {
                     let schema = reader.schema();
                     schema.as_ref().try_into()?
                 }
-                Format::Json => {
+                FileFormat::Json => {
                     let reader = arrow::json::ReaderBuilder::new()
                         .infer_schema(Some(100))
                         .build(fd)?;
@@ -241,7 +260,7 @@ impl LoadFileFn {
                     let schema = reader.schema();
                     schema.as_ref().try_into()?
                 }
-                Format::Parquet => {
+                FileFormat::Parquet => {
                     let reader =
                         parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(fd)?;
                     let schema = reader.schema();
@@ -1,5 +1,7 @@
+pub mod checks;
 pub mod context;
 pub mod error;
+pub mod files;
 pub mod functions;
 mod normalize;
 pub mod runtime;
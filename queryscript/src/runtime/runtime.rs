@@ -1,12 +1,15 @@
+use arrow::array::{Array, ArrayData};
 use futures::future::{BoxFuture, FutureExt};
-use std::collections::HashMap;
+use sqlparser::ast as sqlast;
+use std::collections::{hash_map::DefaultHasher, HashMap};
+use std::hash::{Hash, Hasher};
 
 use crate::compile::schema;
 use crate::compile::sql::create_table_as;
 use crate::{
     ast::Ident,
     types,
-    types::{arrow::EMPTY_RELATION, Arc, Value},
+    types::{arrow::EMPTY_RELATION, Value},
 };
 
 use super::{context::Context, error::*, sql::SQLParam};
@@ -19,12 +22,16 @@ pub type Runtime = tokio::runtime::Runtime;
 
 #[cfg(feature = "multi-thread")]
 pub fn build() -> Result<Runtime> {
-    Ok(tokio::runtime::Builder::new_multi_thread().build()?)
+    Ok(tokio::runtime::Builder::new_multi_thread()
+        .enable_time()
+        .build()?)
 }
 
 #[cfg(not(feature = "multi-thread"))]
 pub fn build() -> Result<Runtime> {
-    Ok(tokio::runtime::Builder::new_current_thread().build()?)
+    Ok(tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()?)
 }
 
 pub fn expensive<F, R>(f: F) -> R
@@ -56,6 +63,88 @@ pub async fn eval_params<'a>(
     Ok(param_values)
 }
 
+// Identifies the contents of a temporary table by the statement that creates it and the values of
+// its parameters. The key is only used within the process, so it does not need a stable hash.
+fn temp_table_key(
+    ctx: &Context,
+    query: &sqlast::Statement,
+    params: &schema::Params<TypeRef>,
+    values: &HashMap<Ident, SQLParam>,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    query.to_string().hash(&mut hasher);
+
+    let mut names = values.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        name.as_str().hash(&mut hasher);
+        // An inlined materialization evaluates to an empty relation, since its rows are in the
+        // temporary table it created, so the key of that table identifies it instead.
+        let inlined_key = match params.get(name).map(|param| param.expr.as_ref()) {
+            Some(schema::Expr::Materialize(schema::MaterializeExpr {
+                inlined: true,
+                expr,
+                decl_name,
+                ..
+            })) => match expr.expr.as_ref() {
+                schema::Expr::SQL(_, sql_url) => ctx.temp_table_key(sql_url, decl_name),
+                _ => None,
+            },
+            _ => None,
+        };
+        match inlined_key {
+            Some(key) => key.hash(&mut hasher),
+            None => hash_value(&values[name].value, &mut hasher),
+        }
+    }
+    hasher.finish()
+}
+
+fn hash_value(value: &Value, hasher: &mut DefaultHasher) {
+    match value {
+        Value::Relation(r) => {
+            r.num_batches().hash(hasher);
+            for i in 0..r.num_batches() {
+                for column in r.batch(i).as_arrow_recordbatch().columns() {
+                    hash_array_data(column.data(), hasher);
+                }
+            }
+        }
+        Value::Record(r) => {
+            let schema = r.schema();
+            schema.len().hash(hasher);
+            for i in 0..schema.len() {
+                hash_value(r.column(i), hasher);
+            }
+        }
+        Value::List(l) => {
+            let values = l.as_vec();
+            values.len().hash(hasher);
+            for value in values.iter() {
+                hash_value(value, hasher);
+            }
+        }
+        _ => format!("{:?}", value).hash(hasher),
+    }
+}
+
+// Hashes the underlying buffers of an array. Equal arrays laid out differently in memory (e.g.
+// slices) may hash differently, which only causes an unnecessary refresh.
+fn hash_array_data(data: &ArrayData, hasher: &mut DefaultHasher) {
+    data.data_type().hash(hasher);
+    data.len().hash(hasher);
+    data.offset().hash(hasher);
+    if let Some(nulls) = data.null_buffer() {
+        nulls.as_slice().hash(hasher);
+    }
+    for buffer in data.buffers() {
+        buffer.as_slice().hash(hasher);
+    }
+    for child in data.child_data() {
+        hash_array_data(child, hasher);
+    }
+}
+
 pub fn eval<'a>(
     ctx: &'a mut Context,
     typed_expr: &'a schema::TypedExpr<TypeRef>,
@@ -94,17 +183,8 @@ pub fn eval<'a>(
                 };
                 QSFn::new(typed_expr.type_.clone(), body)
             }
-            schema::Expr::NativeFn(name) => {
-                use super::functions::*;
-                match name.as_str() {
-                    "load" => Ok(Value::Fn(Arc::new(LoadFileFn::new(
-                        &*typed_expr.type_.read()?,
-                    )?))),
-                    "__native_identity" => Ok(Value::Fn(Arc::new(IdentityFn::new(
-                        &*typed_expr.type_.read()?,
-                    )?))),
-                    _ => return rt_unimplemented!("native function: {}", name),
-                }
+            schema::Expr::NativeFn(name, native) => {
+                super::functions::native_fn(name, native.as_ref(), &*typed_expr.type_.read()?)
             }
             schema::Expr::Materialize(schema::MaterializeExpr {
                 key,
@@ -119,21 +199,26 @@ pub fn eval<'a>(
 
                 let result = match (inlined, expr.expr.as_ref()) {
                     (true, schema::Expr::SQL(sql, sql_url)) => {
-                        let table_name = decl_name.into();
-                        let engine = ctx.sql_engine(sql_url.clone())?;
+                        let table_name: sqlast::ObjectName = decl_name.into();
 
-                        if !engine.table_exists(&table_name).await? {
-                            let query = create_table_as(table_name, sql.body.as_query(), true);
-                            let sql_params = eval_params(ctx, &sql.names.params).await?;
+                        // The temporary table is (re)created unless it was created from the same
+                        // query and inputs. A table saved to the database under the same name may
+                        // be out of date, so it is shadowed rather than used as is.
+                        let created_key = ctx.temp_table_key(sql_url, decl_name);
+                        let query = create_table_as(table_name, sql.body.as_query(), true);
+                        let sql_params = eval_params(ctx, &sql.names.params).await?;
+                        let content_key =
+                            temp_table_key(ctx, &query, &sql.names.params, &sql_params);
+                        if created_key != Some(content_key) {
                             let _ = ctx
                                 .sql_engine(sql_url.clone())?
                                 .eval(&query, sql_params)
                                 .await?;
+                            ctx.set_temp_table_key(sql_url.clone(), decl_name.clone(), content_key);
                         }
 
-                        // Don't stash the fact that we created the temporary table in the materialization index
-                        // NOTE: For performance sake, we could cache something here (that we created the temp
-                        // table), but for now we assume checking if the table exists is fast enough.
+                        // Don't stash the fact that we created the temporary table in the materialization
+                        // index, since the table must be recreated if its inputs change.
                         return Ok(EMPTY_RELATION.clone());
                     }
                     _ => eval(ctx, expr).await?,
@@ -1,7 +1,7 @@
 use sqlparser::ast as sqlast;
 use std::{collections::HashMap, sync::Arc};
 
-use super::error::Result;
+use super::error::{rt_unimplemented, Result};
 use crate::ast::Ident;
 use crate::compile::ConnectionString;
 use crate::types::{Relation, Type, Value};
@@ -34,6 +34,31 @@ pub trait SQLEngine: std::fmt::Debug + Send + Sync {
     /// eventually.
     async fn table_exists(&mut self, name: &sqlast::ObjectName) -> Result<bool>;
 
+    /// Whether DDL statements (e.g. CREATE TABLE) can be rolled back as part of a transaction. If
+    /// this returns true, the engine must implement the transaction functions below.
+    fn supports_transactional_ddl(&self) -> bool {
+        false
+    }
+
+    async fn begin_transaction(&mut self) -> Result<()> {
+        rt_unimplemented!("Transactions in {:?}", self)
+    }
+
+    async fn commit(&mut self) -> Result<()> {
+        rt_unimplemented!("Transactions in {:?}", self)
+    }
+
+    async fn rollback(&mut self) -> Result<()> {
+        rt_unimplemented!("Transactions in {:?}", self)
+    }
+
+    /// Drops temporary tables that were created on this connection. Unlike the functions above, this
+    /// is synchronous, since it is called when a context is dropped. Engines whose temporary tables
+    /// are dropped along with the connection can leave this as a no-op.
+    fn drop_temporary_tables(&mut self, _names: &[Ident]) -> Result<()> {
+        Ok(())
+    }
+
     fn engine_type(&self) -> SQLEngineType;
 }
 
@@ -0,0 +1,531 @@
+// Runs a project's QueryScript files and compares their output to the `.expected` file next to
+// each one, which is the same workflow as the repository's own `tests/qs` harness. Each file's
+// `test` blocks are also run, with their `let`s standing in for the module's lets and externs.
+use difference::Changeset;
+use snafu::prelude::*;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ast::{self, Ident, SourceLocation};
+use crate::compile::{
+    error::RuntimeSnafu,
+    schema::{Expectation, SCHEMA_EXTENSIONS},
+    CompileError, Compiler, Result, Schema,
+};
+use crate::error::QSError;
+use crate::parser::error::PrettyError;
+use crate::runtime::{
+    self,
+    checks::{run_assertions, run_checks},
+    Context, ContextPool, Runtime, SQLEngineType,
+};
+use crate::types::{Relation, Type, Value};
+
+pub const EXPECTED_EXTENSION: &str = "expected";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    // There was no expected output, so it was created from the current output (i.e. with
+    // --bless). Without --bless, a missing expected output is a failure.
+    Created,
+    // The expected output was rewritten with the current output (i.e. with --bless).
+    Blessed,
+}
+
+impl TestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TestStatus::Passed => "passed",
+            TestStatus::Failed => "failed",
+            TestStatus::Created => "created",
+            TestStatus::Blessed => "blessed",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TestResult {
+    pub path: PathBuf,
+    // The name of the test block, or None for the file's own output.
+    pub name: Option<String>,
+    pub status: TestStatus,
+    // Why the test failed: a diff against the expected output, compilation errors, or failed
+    // checks.
+    pub message: Option<String>,
+}
+
+impl TestResult {
+    fn new(path: &Path, status: TestStatus, message: Option<String>) -> TestResult {
+        TestResult {
+            path: path.to_path_buf(),
+            name: None,
+            status,
+            message,
+        }
+    }
+
+    fn new_block(
+        path: &Path,
+        name: &Ident,
+        status: TestStatus,
+        message: Option<String>,
+    ) -> TestResult {
+        TestResult {
+            name: Some(name.to_string()),
+            ..TestResult::new(path, status, message)
+        }
+    }
+}
+
+pub struct TestSummary {
+    pub results: Vec<TestResult>,
+}
+
+impl TestSummary {
+    pub fn count(&self, status: TestStatus) -> usize {
+        self.results.iter().filter(|r| r.status == status).count()
+    }
+
+    pub fn success(&self) -> bool {
+        self.count(TestStatus::Failed) == 0
+    }
+}
+
+impl fmt::Display for TestSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} passed, {} failed",
+            self.count(TestStatus::Passed),
+            self.count(TestStatus::Failed),
+        )?;
+        for status in [TestStatus::Created, TestStatus::Blessed] {
+            let count = self.count(status);
+            if count > 0 {
+                write!(f, ", {} {}", count, status.as_str())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds the QueryScript files under a directory, in sorted order. Hidden directories (e.g.
+/// `.git`) are skipped.
+pub fn find_test_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.path());
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if !entry.file_name().to_string_lossy().starts_with('.') {
+                files.extend(find_test_files(&path)?);
+            }
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map_or(false, |ext| SCHEMA_EXTENSIONS.contains(&ext))
+        {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Runs every QueryScript file under `dir`. With `bless`, the expected output of each file is
+/// rewritten instead of compared.
+pub fn run_tests(
+    rt: &Runtime,
+    engine_type: SQLEngineType,
+    dir: &Path,
+    bless: bool,
+) -> Result<TestSummary> {
+    let mut results = Vec::new();
+    for path in find_test_files(dir)? {
+        results.push(run_test_file(rt, engine_type, &path, bless)?);
+        results.extend(run_test_blocks(rt, engine_type, &path)?);
+    }
+    Ok(TestSummary { results })
+}
+
+pub fn run_test_file(
+    rt: &Runtime,
+    engine_type: SQLEngineType,
+    path: &Path,
+    bless: bool,
+) -> Result<TestResult> {
+    let compiler = Compiler::new()?;
+    let schema_result = compiler.compile_schema_from_file(path);
+    let schema = match schema_result.result {
+        Some(schema) if schema_result.errors.is_empty() => schema,
+        _ => {
+            let contents = compiler.file_contents()?;
+            let errors = schema_result
+                .errors
+                .iter()
+                .map(|(_idx, e)| e.pretty_with_code(&contents.files))
+                .collect::<Vec<_>>();
+            return Ok(TestResult::new(
+                path,
+                TestStatus::Failed,
+                Some(errors.join("\n")),
+            ));
+        }
+    };
+
+    let ctx_pool = ContextPool::new(schema.read()?.folder.clone(), engine_type);
+    let mut ctx = ctx_pool.get();
+
+    // Runtime errors are part of the output, so that a test can expect a query to fail.
+    let mut output = String::new();
+    for expr in schema.read()?.exprs.iter() {
+        let value = match expr.to_runtime_type() {
+            Ok(expr) => rt.block_on(async { runtime::eval(&mut ctx, &expr).await }),
+            Err(e) => Err(e),
+        };
+        match value {
+            Ok(value) => output.push_str(&format!("{}\n\n", value)),
+            Err(e) => output.push_str(&format!("Error: {}\n\n", e)),
+        }
+    }
+
+    // Failing checks are failures regardless of the expected output.
+    let checks = schema.read()?.checks.clone();
+    let failures = rt
+        .block_on(async { run_checks(&mut ctx, &checks).await })
+        .context(RuntimeSnafu {
+            loc: SourceLocation::File(path.to_string_lossy().to_string()),
+        })?;
+    if !failures.is_empty() {
+        let contents = compiler.file_contents()?;
+        let message = failures
+            .iter()
+            .map(|failure| {
+                format!(
+                    "{}\n\n{}",
+                    failure.pretty_with_code(&contents.files),
+                    Value::Relation(failure.rows.clone())
+                )
+            })
+            .collect::<Vec<_>>();
+        return Ok(TestResult::new(
+            path,
+            TestStatus::Failed,
+            Some(message.join("\n")),
+        ));
+    }
+
+    // As are failing assertions.
+    let asserts = schema.read()?.asserts.clone();
+    if let Err(e) = rt.block_on(async { run_assertions(&mut ctx, &asserts).await }) {
+        let contents = compiler.file_contents()?;
+        return Ok(TestResult::new(
+            path,
+            TestStatus::Failed,
+            Some(pretty_runtime_error(e, &contents.files)),
+        ));
+    }
+
+    // Files without any expressions (e.g. ones that are only imported by others) have nothing to
+    // compare.
+    let expected_file = path.with_extension(EXPECTED_EXTENSION);
+    if output.is_empty() && !expected_file.exists() {
+        return Ok(TestResult::new(path, TestStatus::Passed, None));
+    }
+
+    if bless {
+        let status = if expected_file.exists() {
+            TestStatus::Blessed
+        } else {
+            TestStatus::Created
+        };
+        fs::write(&expected_file, output.as_bytes())?;
+        return Ok(TestResult::new(path, status, None));
+    }
+
+    if !expected_file.exists() {
+        return Ok(TestResult::new(
+            path,
+            TestStatus::Failed,
+            Some(format!(
+                "Missing {} (run with --bless to create it)",
+                expected_file.display()
+            )),
+        ));
+    }
+
+    let expected = fs::read_to_string(&expected_file)?;
+    let changeset = Changeset::new(expected.as_str(), output.as_str(), "\n");
+    Ok(if changeset.distance == 0 {
+        TestResult::new(path, TestStatus::Passed, None)
+    } else {
+        TestResult::new(path, TestStatus::Failed, Some(changeset.to_string()))
+    })
+}
+
+/// Runs each `test` block in a file. Within a block, `let` statements replace the module's
+/// non-exported lets and externs of the same name (e.g. with a few rows written as `VALUES`), and
+/// each `expect <actual> equals <expected>` statement must hold.
+pub fn run_test_blocks(
+    rt: &Runtime,
+    engine_type: SQLEngineType,
+    path: &Path,
+) -> Result<Vec<TestResult>> {
+    // Parse errors are reported by run_test_file.
+    let ast = match Compiler::new()?.open_file(path) {
+        Ok((_, _, ast)) => ast,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut results = Vec::new();
+    for stmt in ast.stmts.iter() {
+        if let ast::StmtBody::Test { name, body } = &stmt.body {
+            results.push(run_test_block(
+                rt,
+                engine_type,
+                path,
+                &ast,
+                name.get(),
+                body,
+            )?);
+        }
+    }
+    Ok(results)
+}
+
+fn run_test_block(
+    rt: &Runtime,
+    engine_type: SQLEngineType,
+    path: &Path,
+    module: &ast::Schema,
+    name: &Ident,
+    body: &Vec<ast::Stmt>,
+) -> Result<TestResult> {
+    let compiler = Compiler::new()?;
+    let (file, folder, _) = compiler.open_file(path)?;
+    let schema = Schema::new(file, folder);
+    let result = match test_schema(module, body) {
+        Ok(ast) => compiler
+            .compile_schema_ast(schema.clone(), &ast)
+            .as_result(),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        let contents = compiler.file_contents()?;
+        return Ok(TestResult::new_block(
+            path,
+            name,
+            TestStatus::Failed,
+            Some(e.pretty_with_code(&contents.files)),
+        ));
+    }
+
+    let ctx_pool = ContextPool::new(schema.read()?.folder.clone(), engine_type);
+    let mut ctx = ctx_pool.get();
+    let asserts = schema.read()?.asserts.clone();
+    if let Err(e) = rt.block_on(async { run_assertions(&mut ctx, &asserts).await }) {
+        let contents = compiler.file_contents()?;
+        return Ok(TestResult::new_block(
+            path,
+            name,
+            TestStatus::Failed,
+            Some(pretty_runtime_error(e, &contents.files)),
+        ));
+    }
+
+    let mut failures = Vec::new();
+    for expectation in schema.read()?.expectations.iter() {
+        if let Some(failure) =
+            rt.block_on(async { check_expectation(&mut ctx, expectation).await })?
+        {
+            failures.push(failure);
+        }
+    }
+
+    if failures.is_empty() {
+        return Ok(TestResult::new_block(path, name, TestStatus::Passed, None));
+    }
+    let contents = compiler.file_contents()?;
+    let message = failures
+        .iter()
+        .map(|failure| failure.pretty_with_code(&contents.files))
+        .collect::<Vec<_>>();
+    Ok(TestResult::new_block(
+        path,
+        name,
+        TestStatus::Failed,
+        Some(message.join("\n")),
+    ))
+}
+
+// Builds the module as a test block sees it: the block's lets replace the module's lets and
+// externs of the same name (keeping their declared types, so that `VALUES` columns are named
+// after the type's fields), and its remaining statements are added at the end. The module's own
+// expressions, assertions, and other test blocks are left out.
+fn test_schema(module: &ast::Schema, body: &Vec<ast::Stmt>) -> Result<ast::Schema> {
+    let mut overrides = Vec::new();
+    let mut rest = Vec::new();
+    for stmt in body {
+        match &stmt.body {
+            ast::StmtBody::Let { name, .. } => overrides.push((name.clone(), stmt.clone())),
+            _ => rest.push(stmt.clone()),
+        }
+    }
+
+    let mut stmts = Vec::new();
+    for stmt in module.stmts.iter() {
+        let (name, declared_type) = match &stmt.body {
+            ast::StmtBody::Expr(_) | ast::StmtBody::Assert { .. } | ast::StmtBody::Test { .. } => {
+                continue
+            }
+            ast::StmtBody::Let { name, type_, .. } => (name, type_.clone()),
+            ast::StmtBody::Extern { name, type_ } => (name, Some(type_.clone())),
+            _ => {
+                stmts.push(stmt.clone());
+                continue;
+            }
+        };
+
+        let index = overrides.iter().position(|(o, _)| o.get() == name.get());
+        let mut replacement = match index {
+            Some(index) => overrides.remove(index).1,
+            None => {
+                stmts.push(stmt.clone());
+                continue;
+            }
+        };
+        if stmt.export {
+            return Err(CompileError::wrong_kind(
+                vec![name.clone()],
+                "a non-exported let or extern",
+                "exported",
+            ));
+        }
+        if let ast::StmtBody::Let { type_, .. } = &mut replacement.body {
+            if type_.is_none() {
+                *type_ = declared_type;
+            }
+        }
+        stmts.push(replacement);
+    }
+
+    // Lets that do not override anything are local to the test.
+    stmts.extend(overrides.into_iter().map(|(_, stmt)| stmt));
+    stmts.extend(rest);
+    Ok(ast::Schema { stmts })
+}
+
+// Failed assertions point at the `assert` statement.
+fn pretty_runtime_error(
+    e: runtime::RuntimeError,
+    files: &std::collections::BTreeMap<String, String>,
+) -> String {
+    QSError::RuntimeError {
+        source: e,
+        file: None,
+    }
+    .pretty_with_code(files)
+}
+
+#[derive(Debug)]
+struct ExpectationFailure {
+    loc: SourceLocation,
+    message: String,
+}
+
+impl fmt::Display for ExpectationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Expectation failed: {}", self.message)
+    }
+}
+
+impl PrettyError for ExpectationFailure {
+    fn location(&self) -> SourceLocation {
+        self.loc.clone()
+    }
+}
+
+// Compares both sides by type and then by value. Relations are compared as unordered sets of rows,
+// and their columns by position (so `VALUES` can stand in for a query's named columns).
+async fn check_expectation(
+    ctx: &mut Context,
+    expectation: &Expectation,
+) -> Result<Option<ExpectationFailure>> {
+    let fail = |message: String| {
+        Ok(Some(ExpectationFailure {
+            loc: expectation.loc.clone(),
+            message,
+        }))
+    };
+
+    let (actual, expected) = match (
+        expectation.actual.to_runtime_type(),
+        expectation.expected.to_runtime_type(),
+    ) {
+        (Ok(actual), Ok(expected)) => (actual, expected),
+        (Err(e), _) | (_, Err(e)) => return fail(e.to_string()),
+    };
+
+    let actual_type = actual.type_.read()?.clone();
+    let expected_type = expected.type_.read()?.clone();
+    if !same_type(&actual_type, &expected_type) {
+        return fail(format!(
+            "expected a value of type {:?}, got {:?}",
+            expected_type, actual_type
+        ));
+    }
+
+    let actual = match runtime::eval(ctx, &actual).await {
+        Ok(value) => value,
+        Err(e) => return fail(e.to_string()),
+    };
+    let expected = match runtime::eval(ctx, &expected).await {
+        Ok(value) => value,
+        Err(e) => return fail(e.to_string()),
+    };
+
+    let equal = match (&actual, &expected) {
+        (Value::Relation(a), Value::Relation(e)) => {
+            sorted_rows(a.as_ref()) == sorted_rows(e.as_ref())
+        }
+        _ => actual.to_string() == expected.to_string(),
+    };
+    if equal {
+        Ok(None)
+    } else {
+        fail(format!("expected\n{}\ngot\n{}", expected, actual))
+    }
+}
+
+fn same_type(actual: &Type, expected: &Type) -> bool {
+    match (actual, expected) {
+        (Type::List(actual), Type::List(expected)) => same_type(actual, expected),
+        (Type::Record(actual), Type::Record(expected)) => {
+            actual.len() == expected.len()
+                && actual
+                    .iter()
+                    .zip(expected.iter())
+                    .all(|(a, e)| same_type(&a.type_, &e.type_))
+        }
+        _ => actual == expected,
+    }
+}
+
+fn sorted_rows(relation: &dyn Relation) -> Vec<Vec<String>> {
+    let num_columns = relation.schema().len();
+    let mut rows = relation
+        .records()
+        .iter()
+        .map(|record| {
+            (0..num_columns)
+                .map(|i| record.column(i).to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    rows.sort();
+    rows
+}
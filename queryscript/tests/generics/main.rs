@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use queryscript::{
+        ast::{Ident, SourceLocation},
+        compile::{
+            self,
+            generics::{as_generic, validate_args},
+            inference::{mkcref, CRef},
+            schema::MType,
+            Compiler, CompilerConfig, Generic, GenericConstructor,
+        },
+        runtime::{self, Context, SQLEngineType},
+        types,
+    };
+
+    lazy_static::lazy_static! {
+        static ref CURRENCY_NAME: Ident = "Currency".into();
+    }
+
+    // A toy generic that tags a numeric type as a currency amount. At runtime it is just the
+    // inner type.
+    #[derive(Clone)]
+    struct Currency(CRef<MType>);
+
+    impl GenericConstructor for Currency {
+        fn new(
+            loc: &SourceLocation,
+            mut args: Vec<CRef<MType>>,
+        ) -> compile::Result<Arc<dyn Generic>> {
+            validate_args(loc, &args, 1, Self::static_name())?;
+            Ok(Arc::new(Currency(args.swap_remove(0))))
+        }
+
+        fn static_name() -> &'static Ident {
+            &CURRENCY_NAME
+        }
+    }
+
+    impl std::fmt::Debug for Currency {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}<{:?}>", Self::static_name(), self.0)
+        }
+    }
+
+    impl Generic for Currency {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn name(&self) -> &Ident {
+            Self::static_name()
+        }
+
+        fn to_runtime_type(&self) -> runtime::Result<types::Type> {
+            self.0.must()?.read()?.to_runtime_type()
+        }
+
+        fn substitute(
+            &self,
+            variables: &BTreeMap<Ident, CRef<MType>>,
+        ) -> compile::Result<Arc<dyn Generic>> {
+            Ok(Arc::new(Currency(self.0.substitute(variables)?)))
+        }
+
+        fn unify(&self, other: &MType) -> compile::Result<()> {
+            match other {
+                MType::Generic(other) => match as_generic::<Self>(other.get().as_ref()) {
+                    Some(other) => self.0.unify(&other.0),
+                    None => self.0.unify(&mkcref(MType::Generic(other.clone()))),
+                },
+                other => self.0.unify(&mkcref(other.clone())),
+            }
+        }
+
+        fn resolve(&self, _loc: &SourceLocation) -> compile::Result<CRef<MType>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    const SCRIPT: &str = "let price Currency<bigint> = 42;\nprice;";
+
+    #[test]
+    fn test_register_generic() {
+        let compiler = Compiler::new_with_config(
+            CompilerConfig::default()
+                .with_generic(compile::BuiltinGeneric::<Currency>::constructor()),
+        )
+        .unwrap();
+        let schema = compile::Schema::new("<test>".to_string(), None);
+        compiler
+            .compile_string(schema.clone(), SCRIPT)
+            .as_result()
+            .unwrap();
+
+        let expr = schema.read().unwrap().exprs[0].to_runtime_type().unwrap();
+        let rt = runtime::build().unwrap();
+        let mut ctx = Context::new(None, SQLEngineType::DuckDB);
+        let value = rt
+            .block_on(async move { runtime::eval(&mut ctx, &expr).await })
+            .unwrap();
+        assert_eq!(format!("{}", value), "42");
+    }
+
+    #[test]
+    fn test_unregistered_generic() {
+        let compiler = Compiler::new().unwrap();
+        let schema = compile::Schema::new("<test>".to_string(), None);
+        assert!(compiler
+            .compile_string(schema.clone(), SCRIPT)
+            .as_result()
+            .is_err());
+    }
+}
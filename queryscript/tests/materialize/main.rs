@@ -74,7 +74,7 @@ mod tests {
                     match mode {
                         TestMode::Unmaterialized => {} // The file should already be free of materializations
                         TestMode::MaterializedNoUrl => {
-                            *materialize = Some(ast::MaterializeArgs { db: None });
+                            *materialize = Some(ast::MaterializeArgs::default());
                         }
                         TestMode::MaterializedUrl => {
                             *materialize = Some(ast::MaterializeArgs {
@@ -84,6 +84,7 @@ mod tests {
                                         None,
                                     )),
                                 ))),
+                                ..Default::default()
                             });
                         }
                     };
@@ -171,6 +172,24 @@ mod tests {
         // Next, parse, the schema and then modify it depending on the mode
         let schema_file = target_dir.join("schema.qs");
         let view_schema = build_schema(&compiler, mode, &schema_file).unwrap();
+
+        // The plan should list every dependency before the views that depend on it
+        let plan = rt
+            .block_on(async { materialize::plan_save_views(&ctx_pool, view_schema.clone()).await })
+            .unwrap();
+        let mut planned = HashSet::new();
+        for step in plan.steps.iter() {
+            for dep in step.dependencies.iter() {
+                assert!(
+                    planned.contains(dep),
+                    "{} planned before {}",
+                    step.name,
+                    dep
+                );
+            }
+            planned.insert(step.name.clone());
+        }
+
         rt.block_on(async { materialize::save_views(&ctx_pool, view_schema.clone()).await })
             .unwrap();
 
@@ -257,4 +276,593 @@ mod tests {
     fn test_materialize_duckdb() {
         test_materialize(SQLEngineType::DuckDB)
     }
+
+    fn setup_save_dir(name: &str) -> PathBuf {
+        let target_dir = GEN_ROOT.join(name);
+        let _ = std::fs::remove_dir_all(&target_dir); // Don't care if this errors
+        std::fs::create_dir_all(&target_dir).unwrap();
+        for file in ["data.qs", "t.csv"] {
+            std::fs::copy(TEST_ROOT.join("simple").join(file), target_dir.join(file)).unwrap();
+        }
+        target_dir
+    }
+
+    // The state of a test that saves schemas into a database, which starts out with the table
+    // saved by "simple/data.qs".
+    struct SaveContext {
+        rt: tokio::runtime::Runtime,
+        ctx_pool: ContextPool,
+        compiler: Compiler,
+        engine_type: SQLEngineType,
+        target_dir: PathBuf,
+    }
+
+    fn setup_save_context(name: &str, engine_type: SQLEngineType) -> SaveContext {
+        let target_dir = setup_save_dir(name);
+        let save = SaveContext {
+            rt: queryscript::runtime::build().unwrap(),
+            ctx_pool: ContextPool::new(Some(target_dir.must_string()), engine_type),
+            compiler: Compiler::new().unwrap(),
+            engine_type,
+            target_dir,
+        };
+
+        let data = std::fs::read_to_string(save.target_dir.join("data.qs")).unwrap();
+        save.save_file("data.qs", &data, &materialize::SaveOptions::default())
+            .unwrap();
+        save
+    }
+
+    impl SaveContext {
+        fn compile_file(&self, file: &str, contents: &str) -> compile::SchemaRef {
+            let path = self.target_dir.join(file);
+            std::fs::write(&path, contents).unwrap();
+            self.compiler
+                .compile_schema_from_file(&path)
+                .as_result()
+                .unwrap()
+                .unwrap()
+        }
+
+        fn save_file(
+            &self,
+            file: &str,
+            contents: &str,
+            options: &materialize::SaveOptions,
+        ) -> compile::Result<()> {
+            let schema = self.compile_file(file, contents);
+            self.save_schema(schema, options)
+        }
+
+        fn save_schema(
+            &self,
+            schema: compile::SchemaRef,
+            options: &materialize::SaveOptions,
+        ) -> compile::Result<()> {
+            self.rt.block_on(async {
+                materialize::save_views_with_options(&self.ctx_pool, schema, options).await
+            })
+        }
+
+        fn save_report(
+            &self,
+            file: &str,
+            contents: &str,
+            options: &materialize::SaveOptions,
+        ) -> materialize::SaveReport {
+            let schema = self.compile_file(file, contents);
+            self.rt
+                .block_on(async {
+                    materialize::save_views_with_report(&self.ctx_pool, schema, options).await
+                })
+                .unwrap()
+        }
+
+        fn plan_file(&self, file: &str, contents: &str) -> materialize::Plan {
+            let schema = self.compile_file(file, contents);
+            self.rt
+                .block_on(async { materialize::plan_save_views(&self.ctx_pool, schema).await })
+                .unwrap()
+        }
+
+        fn url(&self) -> String {
+            get_engine_url(self.engine_type)
+        }
+
+        fn export_views(&self, names: &[&str]) -> String {
+            let mut ret = format!("import '{}';\n", self.url());
+            for name in names {
+                ret.push_str(&format!("export let {name} = SELECT MAX(a) FROM db.t;\n"));
+            }
+            ret
+        }
+
+        fn connection(&self, url: &str) -> std::sync::Arc<ConnectionString> {
+            ConnectionString::maybe_parse(
+                Some(self.target_dir.must_string()),
+                url,
+                &SourceLocation::Unknown,
+            )
+            .unwrap()
+            .unwrap()
+        }
+
+        // Runs a query against the database at `url`, and returns each row's columns as strings.
+        fn query_at(&self, url: &str, query: &str) -> Vec<Vec<String>> {
+            let conn_str = self.connection(url);
+            let query =
+                sqlparser::parser::Parser::parse_sql(&sqlparser::dialect::GenericDialect {}, query)
+                    .unwrap()
+                    .swap_remove(0);
+            let mut ctx = self.ctx_pool.get();
+            self.rt
+                .block_on(async {
+                    ctx.sql_engine(Some(conn_str.clone()))?
+                        .eval(&query, HashMap::new())
+                        .await
+                })
+                .unwrap()
+                .records()
+                .into_iter()
+                .map(|r| {
+                    (0..r.schema().len())
+                        .map(|i| r.column(i).to_string())
+                        .collect()
+                })
+                .collect()
+        }
+
+        fn query(&self, query: &str) -> Vec<Vec<String>> {
+            self.query_at(&self.url(), query)
+        }
+
+        fn list_views(&self) -> HashSet<String> {
+            let conn_str = self.connection(&self.url());
+            let mut ctx = self.ctx_pool.get();
+            self.rt
+                .block_on(async {
+                    ctx.sql_engine(Some(conn_str.clone()))?
+                        .eval(&show_views_query(self.engine_type), HashMap::new())
+                        .await
+                })
+                .unwrap()
+                .records()
+                .into_iter()
+                .map(|r| r.column(0).to_string())
+                .collect()
+        }
+
+        fn count_rows(&self, table: &str) -> String {
+            self.query(&format!("SELECT COUNT(*) FROM {table}"))[0][0].clone()
+        }
+
+        fn table_exists(&self, url: &str, table: &str) -> bool {
+            let conn_str = self.connection(url);
+            let mut ctx = self.ctx_pool.get();
+            self.rt
+                .block_on(async {
+                    ctx.sql_engine(Some(conn_str.clone()))?
+                        .table_exists(&(&Ident::from(table)).into())
+                        .await
+                })
+                .unwrap()
+        }
+    }
+
+    fn test_prune(engine_type: SQLEngineType) {
+        let save = setup_save_context("prune", engine_type);
+        let options = materialize::SaveOptions {
+            prune: true,
+            ..Default::default()
+        };
+
+        for views in [
+            save.export_views(&["report_1", "report_2"]),
+            save.export_views(&["report_1"]),
+            // Saving again should not change anything
+            save.export_views(&["report_1"]),
+        ] {
+            save.save_file("views.qs", &views, &options).unwrap();
+        }
+
+        assert_eq!(save.list_views(), HashSet::from(["report_1".to_string()]));
+
+        // report_1 has not changed since it was last saved, so the plan should skip it
+        let plan = save.plan_file("views.qs", &save.export_views(&["report_1"]));
+        assert_eq!(
+            plan.steps
+                .iter()
+                .map(|step| (step.name.as_str(), step.unchanged))
+                .collect::<Vec<_>>(),
+            vec![("report_1", true)]
+        );
+
+        // The table from data.qs was saved from a different file, so it should not be pruned
+        assert!(save.table_exists(&save.url(), "t"));
+
+        // A different path to the same file is still the same source, and an imported database
+        // that no longer has any exports is still pruned.
+        let other_url = "duckdb://other.duckdb";
+        let with_copy = format!(
+            "{}import '{other_url}';\nexport mat(other) report_copy = SELECT MAX(a) AS a FROM db.t;\n",
+            save.export_views(&["report_1"])
+        );
+        save.save_file("views.qs", &with_copy, &options).unwrap();
+        assert!(save.table_exists(other_url, "report_copy"));
+
+        let without_copy = format!(
+            "{}import '{other_url}';\n",
+            save.export_views(&["report_2"])
+        );
+        save.save_file("./views.qs", &without_copy, &options)
+            .unwrap();
+        assert!(!save.table_exists(other_url, "report_copy"));
+        assert_eq!(save.list_views(), HashSet::from(["report_2".to_string()]));
+    }
+
+    #[test]
+    fn test_prune_duckdb() {
+        test_prune(SQLEngineType::DuckDB)
+    }
+
+    fn test_atomic(engine_type: SQLEngineType) {
+        let save = setup_save_context("atomic", engine_type);
+        let options = materialize::SaveOptions {
+            atomic: true,
+            ..Default::default()
+        };
+
+        // report_1 is saved before z_missing fails, so it should be rolled back. z_other does not
+        // depend on z_missing, but it is saved after it, in the aborted transaction.
+        let views = save.export_views(&["report_1", "z_other"])
+            + "export mat(db) z_missing [{a int}] = load('missing.csv');\n"
+            + "export let z_dep = SELECT a FROM z_missing;\n";
+        let report = save.save_report("views.qs", &views, &options);
+
+        let statuses = report
+            .steps
+            .iter()
+            .map(|step| (step.name.as_str(), (step.status, step.blocked_by.clone())))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            statuses,
+            BTreeMap::from([
+                ("report_1", (materialize::SaveStatus::RolledBack, vec![])),
+                (
+                    "z_dep",
+                    (
+                        materialize::SaveStatus::Blocked,
+                        vec!["z_missing".to_string()]
+                    )
+                ),
+                ("z_missing", (materialize::SaveStatus::Failed, vec![])),
+                ("z_other", (materialize::SaveStatus::Aborted, vec![])),
+            ])
+        );
+        assert!(report.into_result().is_err());
+
+        assert_eq!(save.list_views(), HashSet::new());
+    }
+
+    #[test]
+    fn test_atomic_duckdb() {
+        test_atomic(SQLEngineType::DuckDB)
+    }
+
+    fn test_save_report(engine_type: SQLEngineType) {
+        let save = setup_save_context("save_report", engine_type);
+        let options = materialize::SaveOptions::default();
+
+        // z_missing fails, which should block z_dep, but by default, report_1 (which does not
+        // depend on it) should still be saved
+        let views = save.export_views(&["report_1"])
+            + "export mat(db) z_missing [{a int}] = load('missing.csv');\n"
+            + "export let z_dep = SELECT a FROM z_missing;\n";
+        let report = save.save_report("views.qs", &views, &options);
+
+        let statuses = report
+            .steps
+            .iter()
+            .map(|step| (step.name.as_str(), step.status))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            statuses,
+            BTreeMap::from([
+                ("report_1", materialize::SaveStatus::Created),
+                ("z_dep", materialize::SaveStatus::Blocked),
+                ("z_missing", materialize::SaveStatus::Failed),
+            ])
+        );
+
+        // A missing file is not a transient error, so it should not be retried
+        let failed = report.steps.iter().find(|s| s.name == "z_missing").unwrap();
+        assert_eq!(failed.attempts, 1);
+        assert!(failed.error.is_some());
+        assert!(!report.success);
+        assert!(report.into_result().is_err());
+
+        assert_eq!(save.list_views(), HashSet::from(["report_1".to_string()]));
+    }
+
+    #[test]
+    fn test_save_report_duckdb() {
+        test_save_report(SQLEngineType::DuckDB)
+    }
+
+    fn test_temporary_dependency(engine_type: SQLEngineType) {
+        let save = setup_save_context("temporary_dependency", engine_type);
+        let options = materialize::SaveOptions::default();
+
+        // report_4 only exists as a temporary table, so a view that reads from it cannot be saved
+        let views = format!(
+            "import '{}';\n{}\n{}\n",
+            save.url(),
+            "mat report_4 = SELECT * FROM db.t;",
+            "export let report_5 = SELECT COUNT(*) FROM report_4;",
+        );
+        let err = save
+            .save_file("views.qs", &views, &options)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("report_4"), "{}", err);
+        assert_eq!(save.list_views(), HashSet::new());
+
+        // Materializing report_5 saves a copy of the data instead, which is fine
+        let views = views.replace("export let report_5", "export mat(db) report_5");
+        save.save_file("views.qs", &views, &options).unwrap();
+    }
+
+    #[test]
+    fn test_temporary_dependency_duckdb() {
+        test_temporary_dependency(SQLEngineType::DuckDB)
+    }
+
+    fn test_checks(engine_type: SQLEngineType) {
+        let save = setup_save_context("checks", engine_type);
+
+        // id 2 is duplicated and "deleted" is not an accepted status, but every id is present
+        std::fs::write(
+            save.target_dir.join("users.csv"),
+            "id,status\n1,active\n2,inactive\n2,deleted\n3,active\n",
+        )
+        .unwrap();
+        let views = save.export_views(&["report_1"])
+            + "let users [{id bigint unique not null, status text in ('active', 'inactive')}] = load('users.csv');\n";
+        let schema = save.compile_file("views.qs", &views);
+
+        let checks = schema.read().unwrap().checks.clone();
+        assert_eq!(checks.len(), 3);
+        let mut ctx = save.ctx_pool.get();
+        let failures = save
+            .rt
+            .block_on(async { runtime::checks::run_checks(&mut ctx, &checks).await })
+            .unwrap();
+        let failures = failures
+            .iter()
+            .map(|f| (f.check.description.as_str(), f.num_rows()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            failures,
+            BTreeMap::from([
+                ("users.id is unique", 1),
+                ("users.status is in ('active', 'inactive')", 1),
+            ])
+        );
+
+        // With checks enabled, a failing check prevents anything from being saved
+        let options = materialize::SaveOptions {
+            check: true,
+            ..Default::default()
+        };
+        let err = save
+            .save_schema(schema.clone(), &options)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Check failed"), "{}", err);
+        assert_eq!(save.list_views(), HashSet::new());
+
+        save.save_schema(schema, &materialize::SaveOptions::default())
+            .unwrap();
+        assert_eq!(save.list_views(), HashSet::from(["report_1".to_string()]));
+    }
+
+    #[test]
+    fn test_checks_duckdb() {
+        test_checks(SQLEngineType::DuckDB)
+    }
+
+    fn test_incremental(engine_type: SQLEngineType) {
+        let save = setup_save_context("incremental", engine_type);
+        let options = materialize::SaveOptions::default();
+
+        save.query("CREATE TABLE events (a INT, b VARCHAR)");
+        save.query("INSERT INTO events VALUES (1, 'x'), (2, 'y')");
+        let views = format!(
+            "import '{}';\n{}\n{}\n",
+            save.url(),
+            "export mat(db, incremental => 'a') appended = SELECT a, b FROM db.events;",
+            "export mat(db, unique_key => 'a') merged = SELECT a, b FROM db.events;",
+        );
+        save.save_file("views.qs", &views, &options).unwrap();
+
+        // Add a new row, and change the row with an existing key
+        save.query("INSERT INTO events VALUES (3, 'z')");
+        save.query("UPDATE events SET b = 'y2' WHERE a = 2");
+        save.save_file("views.qs", &views, &options).unwrap();
+
+        save.query("INSERT INTO events VALUES (4, 'w')");
+        save.save_file("views.qs", &views, &options).unwrap();
+
+        // Only the rows past the watermark are appended, so the change to the existing row is
+        // not picked up.
+        assert_eq!(
+            save.query("SELECT a, b FROM appended ORDER BY a"),
+            save.query("SELECT * FROM (VALUES (1, 'x'), (2, 'y'), (3, 'z'), (4, 'w')) AS v(a, b)")
+        );
+        // Merged rows replace the existing rows with the same key, rather than duplicating them.
+        assert_eq!(
+            save.query("SELECT a, b FROM merged ORDER BY a"),
+            save.query("SELECT a, b FROM events ORDER BY a")
+        );
+        assert_eq!(save.count_rows("merged"), "4");
+    }
+
+    #[test]
+    fn test_incremental_duckdb() {
+        test_incremental(SQLEngineType::DuckDB)
+    }
+
+    fn test_mat_options(engine_type: SQLEngineType) {
+        let save = setup_save_context("mat_options", engine_type);
+        let options = materialize::SaveOptions::default();
+
+        save.query("CREATE TABLE unsorted (a INT, b VARCHAR)");
+        save.query("INSERT INTO unsorted VALUES (3, 'x'), (1, 'y'), (2, 'x')");
+        let views = format!(
+            "import '{}';\n{}\n{}\n{}\n",
+            save.url(),
+            "export mat(db, kind => 'view', order_by => 'a') as_view = SELECT a FROM db.unsorted;",
+            "export mat(db, order_by => 'a', indexes => 'a', comment => 'Sorted') sorted = SELECT a FROM db.unsorted;",
+            "export mat(db, partition_by => 'b', order_by => 'a') clustered = SELECT a, b FROM db.unsorted;",
+        );
+        save.save_file("views.qs", &views, &options).unwrap();
+
+        assert_eq!(save.list_views(), HashSet::from(["as_view".to_string()]));
+
+        // The rows are stored in order, with the partitioning columns sorted on first
+        assert_eq!(
+            save.query("SELECT a FROM sorted"),
+            save.query("SELECT a FROM unsorted ORDER BY a")
+        );
+        assert_eq!(
+            save.query("SELECT a, b FROM clustered"),
+            save.query("SELECT a, b FROM unsorted ORDER BY b, a")
+        );
+
+        assert_eq!(
+            save.query("SELECT index_name FROM duckdb_indexes() WHERE table_name = 'sorted'"),
+            save.query("SELECT '__qs_idx_sorted_a'")
+        );
+        assert_eq!(
+            save.query(&format!(
+                "SELECT name, comment FROM {} WHERE comment IS NOT NULL",
+                materialize::METADATA_TABLE
+            )),
+            save.query("SELECT 'sorted', 'Sorted'")
+        );
+    }
+
+    #[test]
+    fn test_mat_options_duckdb() {
+        test_mat_options(SQLEngineType::DuckDB)
+    }
+
+    fn test_file_targets(engine_type: SQLEngineType) {
+        let save = setup_save_context("file_targets", engine_type);
+        let options = materialize::SaveOptions::default();
+        let conn_url = save.url();
+
+        let views = format!(
+            "import '{conn_url}';\n{}\n{}\n",
+            "export mat(file => 'out/t.parquet') t_file = SELECT a FROM db.t;",
+            "export mat(file => 'out/t_parts', partition_by => 'a', format => 'csv') t_parts = SELECT a FROM db.t;",
+        );
+        save.save_file("views.qs", &views, &options).unwrap();
+        assert!(save.target_dir.join("out/t.parquet").is_file());
+        assert!(save.target_dir.join("out/t_parts").is_dir());
+
+        // Reading the files back should produce the same rows as the source table
+        let read_schema = save.compile_file(
+            "read.qs",
+            "type T { a int };
+let t_file [T] = load('out/t.parquet');
+let t_parts [T] = load('out/t_parts');
+export let file_rows = SELECT a FROM t_file ORDER BY a;
+export let part_rows = SELECT a FROM t_parts ORDER BY a;
+",
+        );
+        let expected_schema = save.compile_file(
+            "expected.qs",
+            &format!(
+                "import '{conn_url}';
+export let file_rows = SELECT a FROM db.t ORDER BY a;
+export let part_rows = SELECT a FROM db.t ORDER BY a;
+"
+            ),
+        );
+
+        let mut ctx = save.ctx_pool.get();
+        let mut snapshot_schema = |schema: &compile::SchemaRef| {
+            save.rt
+                .block_on(async { snapshot(&mut ctx, schema).await })
+                .unwrap()
+        };
+        assert_eq!(
+            snapshot_schema(&read_schema),
+            snapshot_schema(&expected_schema)
+        );
+    }
+
+    #[test]
+    fn test_file_targets_duckdb() {
+        test_file_targets(SQLEngineType::DuckDB)
+    }
+
+    fn test_temp_table_refresh(engine_type: SQLEngineType, name: &str, schema_text: &str) {
+        let save = setup_save_context(name, engine_type);
+        let schema = save.compile_file("temp.qs", schema_text);
+
+        let data_file = save.target_dir.join("data.json");
+        std::fs::write(&data_file, "{\"a\": 1}\n{\"a\": 2}\n").unwrap();
+        let mut ctx = save.ctx_pool.get();
+        let before = save
+            .rt
+            .block_on(async { snapshot(&mut ctx, &schema).await })
+            .unwrap();
+
+        // Changing the data should recreate the temporary table in the same context
+        std::fs::write(&data_file, "{\"a\": 1}\n{\"a\": 5}\n").unwrap();
+        let after = save
+            .rt
+            .block_on(async { snapshot(&mut ctx, &schema).await })
+            .unwrap();
+        let fresh = save
+            .rt
+            .block_on(async { snapshot(&mut save.ctx_pool.get(), &schema).await })
+            .unwrap();
+
+        assert_ne!(before, after);
+        assert_eq!(after, fresh);
+    }
+
+    #[test]
+    fn test_temp_table_refresh_duckdb() {
+        // `doubled` is inlined into `result` as a temporary table, which is created from the
+        // contents of data.json.
+        test_temp_table_refresh(
+            SQLEngineType::DuckDB,
+            "temp_table_refresh",
+            "type T { a int };
+let data [T] = load('data.json');
+mat doubled = SELECT a * 2 AS b FROM data;
+export let result = SELECT MAX(b) AS b FROM doubled;
+",
+        )
+    }
+
+    #[test]
+    fn test_chained_temp_table_refresh_duckdb() {
+        // `quadrupled` only reads the temporary table of `doubled`, so it must be recreated
+        // whenever `doubled` is.
+        test_temp_table_refresh(
+            SQLEngineType::DuckDB,
+            "chained_temp_table_refresh",
+            "type T { a int };
+let data [T] = load('data.json');
+mat doubled = SELECT a * 2 AS b FROM data;
+mat quadrupled = SELECT b * 2 AS c FROM doubled;
+export let result = SELECT MAX(c) AS c FROM quadrupled;
+",
+        )
+    }
 }
@@ -0,0 +1,82 @@
+#[cfg(test)]
+mod tests {
+    use futures::future::{BoxFuture, FutureExt};
+    use std::sync::Arc;
+
+    use queryscript::{
+        compile::{self, Compiler, CompilerConfig},
+        runtime::{self, Context, SQLEngineType},
+        types::{self, FnValue, Value},
+    };
+
+    #[derive(Debug, Clone)]
+    struct AddOneFn {
+        type_: types::FnType,
+    }
+
+    impl FnValue for AddOneFn {
+        fn execute<'a>(
+            &'a self,
+            _ctx: &'a mut Context,
+            args: Vec<Value>,
+        ) -> BoxFuture<'a, runtime::Result<Value>> {
+            async move {
+                match args.as_slice() {
+                    [Value::Int64(v)] => Ok(Value::Int64(v + 1)),
+                    _ => Err(runtime::RuntimeError::new("add_one expects a bigint")),
+                }
+            }
+            .boxed()
+        }
+
+        fn fn_type(&self) -> types::FnType {
+            self.type_.clone()
+        }
+
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    fn add_one(type_: &types::Type) -> runtime::Result<Arc<dyn FnValue>> {
+        match type_ {
+            types::Type::Fn(type_) => Ok(Arc::new(AddOneFn {
+                type_: type_.clone(),
+            }) as Arc<dyn FnValue>),
+            _ => Err(runtime::RuntimeError::new(
+                "add_one must have a function type",
+            )),
+        }
+    }
+
+    #[test]
+    fn test_register_native_fn() {
+        let compiler = Compiler::new_with_config(CompilerConfig::default().with_native_fn(
+            "add_one",
+            "(value bigint) -> bigint",
+            add_one,
+        ))
+        .unwrap();
+        let schema = compile::Schema::new("<test>".to_string(), None);
+        compiler
+            .compile_string(schema.clone(), "add_one(41);")
+            .as_result()
+            .unwrap();
+
+        let expr = schema.read().unwrap().exprs[0].to_runtime_type().unwrap();
+        let rt = runtime::build().unwrap();
+        let mut ctx = Context::new(None, SQLEngineType::DuckDB);
+        let value = rt
+            .block_on(async move { runtime::eval(&mut ctx, &expr).await })
+            .unwrap();
+        assert_eq!(format!("{}", value), "42");
+
+        // Native functions are only visible to the compiler they are registered on
+        let schema = compile::Schema::new("<test>".to_string(), None);
+        assert!(Compiler::new()
+            .unwrap()
+            .compile_string(schema, "add_one(41);")
+            .as_result()
+            .is_err());
+    }
+}
@@ -10,7 +10,9 @@ mod tests {
 
     use queryscript::ast;
     use queryscript::parser;
+    use queryscript::parser::error::PrettyError;
     use queryscript::runtime;
+    use queryscript::testing;
     use queryscript::types;
     use queryscript::{
         compile,
@@ -183,24 +185,156 @@ mod tests {
 
         result.insert("decls".to_string(), Box::new(decls));
         result.insert("queries".to_string(), Box::new(exprs));
+        feature_outputs(rt, path, &schema, &mut result);
         (schema, result)
     }
 
-    fn test_schema(rt: &runtime::Runtime, path: &std::path::Path) {
-        let (_schema, result) = execute_test_schema(rt, path, IdentityTransformer());
-        let result_str = format!("{:#?}", result);
+    // The files in some directories test a feature that is built on top of the compiled schema, so
+    // they also record that feature's output. Which one depends on the directory's name.
+    fn feature_outputs(
+        rt: &runtime::Runtime,
+        path: &Path,
+        schema: &SchemaRef,
+        result: &mut BTreeMap<String, Box<dyn fmt::Debug>>,
+    ) {
+        let engine_type = runtime::SQLEngineType::DuckDB;
+        match path
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(OsStr::to_str)
+        {
+            Some("asserts") => {
+                let mut ctx =
+                    runtime::Context::new(schema.read().unwrap().folder.clone(), engine_type);
+                let asserts = schema.read().unwrap().asserts.clone();
+                let asserts = asserts
+                    .iter()
+                    .map(|a| rt.block_on(runtime::checks::run_assertion(&mut ctx, a)))
+                    .collect::<Vec<_>>();
+                result.insert("asserts".to_string(), Box::new(asserts));
+            }
+            Some("docs") => {
+                let compiler = compile::Compiler::new().expect("Failed to create compiler");
+                let pages = queryscript::docs::schema_docs(&compiler, path)
+                    .expect("Failed to generate docs")
+                    .into_iter()
+                    .map(|page| (page.file_name(), lines(&page.markdown)))
+                    .collect::<BTreeMap<_, _>>();
+                result.insert("docs".to_string(), Box::new(pages));
+            }
+            Some("graph") => {
+                let compiler = compile::Compiler::new().expect("Failed to create compiler");
+                let graph = queryscript::graph::declaration_graph(&compiler, path)
+                    .expect("Failed to build the declaration graph");
+                let ids = |graph: queryscript::graph::Graph| {
+                    graph.nodes.into_iter().map(|n| n.id).collect::<Vec<_>>()
+                };
+                let upstream = graph
+                    .nodes
+                    .iter()
+                    .map(|n| (n.id.clone(), ids(graph.upstream(&n.id))))
+                    .collect::<BTreeMap<_, _>>();
+                let downstream = graph
+                    .nodes
+                    .iter()
+                    .map(|n| (n.id.clone(), ids(graph.downstream(&n.id))))
+                    .collect::<BTreeMap<_, _>>();
+                result.insert("graph".to_string(), Box::new(lines(&graph.to_dot())));
+                result.insert(
+                    "exports".to_string(),
+                    Box::new(lines(&graph.exports().to_dot())),
+                );
+                result.insert("mermaid".to_string(), Box::new(lines(&graph.to_mermaid())));
+                result.insert("upstream".to_string(), Box::new(upstream));
+                result.insert("downstream".to_string(), Box::new(downstream));
+            }
+            Some("lints") | Some("lint_levels") | Some("lint_config_error") => {
+                let compiler = compile::Compiler::new().expect("Failed to create compiler");
+                let compiled = compiler.compile_schema_from_file(path);
+                let warnings = compiled
+                    .warnings
+                    .iter()
+                    .map(|w| w.to_string())
+                    .collect::<Vec<_>>();
+                let errors = compiled
+                    .errors
+                    .iter()
+                    .map(|(_, e)| e.to_string())
+                    .collect::<Vec<_>>();
+                result.insert("lints".to_string(), Box::new(warnings));
+                result.insert("lint_errors".to_string(), Box::new(errors));
+            }
+            Some("lineage") => {
+                let lineage = queryscript::lineage::schema_lineage(&schema.read().unwrap());
+                result.insert("lineage".to_string(), Box::new(lines(&lineage.to_dot())));
+            }
+            Some("related") => {
+                let compiler = compile::Compiler::new().expect("Failed to create compiler");
+                let errors = compiler
+                    .compile_schema_from_file(path)
+                    .errors
+                    .iter()
+                    .map(|(_, e)| {
+                        let related = e.related().iter().map(|r| r.pretty()).collect::<Vec<_>>();
+                        (e.to_string(), related)
+                    })
+                    .collect::<Vec<_>>();
+                result.insert("related".to_string(), Box::new(errors));
+            }
+            Some("test_blocks") => {
+                let tests = testing::run_test_blocks(rt, engine_type, path)
+                    .expect("Failed to run test blocks")
+                    .into_iter()
+                    .map(|r| (r.name, r.status))
+                    .collect::<Vec<_>>();
+                result.insert("tests".to_string(), Box::new(tests));
+            }
+            _ => {}
+        }
+    }
 
-        let mut expected_file = PathBuf::from(path);
-        expected_file.set_extension("expected");
+    // Compares against the expected output in a file, or writes it if the file doesn't exist yet.
+    fn assert_golden(expected_file: &Path, actual: &str) {
         if expected_file.exists() {
-            let expected_str = fs::read_to_string(&expected_file)
+            let expected_str = fs::read_to_string(expected_file)
                 .expect(format!("Could not read {}", expected_file.display()).as_str());
 
-            assert_diff!(expected_str.as_str(), result_str.as_str(), "\n", 0);
+            assert_diff!(expected_str.as_str(), actual, "\n", 0);
         } else {
-            fs::write(&expected_file, result_str.as_bytes())
+            fs::write(expected_file, actual.as_bytes())
                 .expect(format!("Could not write {}", expected_file.display()).as_str());
         }
+    }
+
+    // Writes a fresh directory of files under tests/generated/qs for tests that need their own
+    // project on disk.
+    #[cfg(feature = "cli")]
+    fn fixture_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/generated/qs")
+            .join(name);
+        let _ = fs::remove_dir_all(&dir); // Don't care if this errors
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    // Text outputs (e.g. DOT graphs) are split into lines, so that they are readable in the
+    // expected files.
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    fn test_schema(rt: &runtime::Runtime, path: &std::path::Path) {
+        let (_schema, result) = execute_test_schema(rt, path, IdentityTransformer());
+        let result_str = format!("{:#?}", result);
+
+        let mut expected_file = PathBuf::from(path);
+        expected_file.set_extension("expected");
+        assert_golden(&expected_file, &result_str);
 
         // Unfortunately transforming blindly to unsafe mode doesn't quite work, because the types
         // are not preserved and the aliases can be different. However this is a WIP and still useful
@@ -223,6 +357,9 @@ mod tests {
         .collect::<PathBuf>();
         assert!(tests.is_dir());
 
+        // Some messages (e.g. lints) include types, which are otherwise colored in a terminal.
+        colored::control::set_override(false);
+
         let rt = runtime::build().expect("Failed to build runtime");
         println!("Running tests in {}", tests.display());
         for entry in
@@ -268,4 +405,121 @@ mod tests {
             }
         }
     }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_cli_exit_status() {
+        let dir = fixture_dir(
+            "cli_exit_status",
+            &[
+                ("pass/lib.qs", "export let x = 1;\n"),
+                ("fail/nested/a.qs", "SELECT 1 AS a;\n"),
+            ],
+        );
+        let qs_test = |dir: PathBuf, args: &[&str]| {
+            std::process::Command::new(env!("CARGO_BIN_EXE_qs"))
+                .arg("test")
+                .arg(dir)
+                .args(args)
+                .output()
+                .unwrap()
+        };
+        let stderr =
+            |output: &std::process::Output| String::from_utf8_lossy(&output.stderr).to_string();
+
+        // Files without expressions are skipped, so they pass without an expected output.
+        assert!(qs_test(dir.join("pass"), &[]).status.success());
+        assert!(!dir.join("pass/lib.expected").exists());
+
+        // The missing expected output fails the run, which should be reflected in the exit code.
+        let output = qs_test(dir.join("fail"), &["--engine", "duckdb"]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("1 of 1 tests failed"));
+        assert!(!dir.join("fail/nested/a.expected").exists());
+
+        // Unless it is created with --bless, after which the run passes.
+        assert!(qs_test(dir.join("fail"), &["--bless"]).status.success());
+        let expected = fs::read_to_string(dir.join("fail/nested/a.expected")).unwrap();
+        assert!(qs_test(dir.join("fail"), &[]).status.success());
+
+        // A changed result fails until it is blessed again.
+        fs::write(dir.join("fail/nested/a.qs"), "SELECT 2 AS a;\n").unwrap();
+        assert_eq!(qs_test(dir.join("fail"), &[]).status.code(), Some(1));
+        assert!(qs_test(dir.join("fail"), &["--bless"]).status.success());
+        assert_ne!(
+            fs::read_to_string(dir.join("fail/nested/a.expected")).unwrap(),
+            expected
+        );
+        assert!(qs_test(dir.join("fail"), &[]).status.success());
+
+        // An unknown engine is an error, rather than a panic
+        let output = qs_test(dir.join("pass"), &["--engine", "nope"]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("Invalid --engine"));
+    }
+
+    #[test]
+    fn test_format() {
+        let text = "-- Users loaded from the warehouse.\n\
+                    export type user {id bigint, -- The primary key.\n\
+                    name text}\n\
+                    /** Doubles a number. */\n\
+                    fn double(x bigint) {x*2}\n\
+                    let users = load('users.csv', user);\n\
+                    export let counts = SELECT name, COUNT(*) AS n, SUM(double(id)) AS total, MAX(id) AS last FROM users WHERE id > 10 GROUP BY name ORDER BY n DESC;\n\
+                    export let recent = SELECT id -- Only the ids.\n\
+                    FROM users /* The most recent users. */ WHERE id > 100;\n";
+        let formatted = parser::format::format_schema("<test>", text).unwrap();
+        assert!(formatted.starts_with("-- Users loaded from the warehouse.\nexport type user {\n"));
+        assert!(formatted.contains("    id bigint, -- The primary key.\n"));
+        assert!(formatted.contains("/** Doubles a number. */\nfn double(x bigint) {"));
+        assert!(formatted.contains("\nexport let counts =\n    SELECT name, COUNT(*) AS n,"));
+        assert!(formatted.contains("\n    FROM users\n    WHERE id > 10\n"));
+        // Comments inside of SQL are kept
+        assert!(formatted.contains("SELECT id -- Only the ids.\n"));
+        assert!(formatted.contains("/* The most recent users. */"));
+        assert_diff!(
+            &formatted,
+            &parser::format::format_schema("<test>", &formatted).unwrap(),
+            "\n",
+            0
+        );
+        assert_same_ast("<test>", text, &formatted);
+
+        // Every test schema that parses should format to a fixed point, without changing its AST.
+        for file in testing::find_test_files(Path::new("tests/qs")).unwrap() {
+            let contents = fs::read_to_string(&file).unwrap();
+            let file = file.to_str().unwrap();
+            if parser::parse_schema(file, &contents).as_result().is_err() {
+                continue;
+            }
+            let once = parser::format::format_schema(file, &contents).unwrap();
+            let twice = parser::format::format_schema(file, &once).unwrap();
+            assert_diff!(&once, &twice, "\n", 0);
+            assert_same_ast(file, &contents, &once);
+        }
+    }
+
+    // Formatting only changes the layout of a file, so it should parse to the same AST, apart from
+    // the locations.
+    fn assert_same_ast(file: &str, original: &str, formatted: &str) {
+        let ast = |text: &str| {
+            let ast = format!(
+                "{:#?}",
+                parser::parse_schema(file, text).as_result().unwrap()
+            );
+            let mut ret = String::new();
+            let mut chars = ast.chars().peekable();
+            while let Some(c) = chars.next() {
+                ret.push(c);
+                if ret.ends_with("line: ") || ret.ends_with("column: ") {
+                    while chars.peek().map_or(false, |c| c.is_ascii_digit()) {
+                        chars.next();
+                    }
+                }
+            }
+            ret
+        };
+        assert_diff!(&ast(original), &ast(formatted), "\n", 0);
+    }
 }